@@ -28,5 +28,46 @@ pub fn pub_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, pub_benchmark);
+pub fn concurrent_pub_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish_concurrent");
+    group.warm_up_time(Duration::from_secs(1));
+    group.throughput(Throughput::Elements(1));
+
+    let msg: Vec<u8> = (0..128).map(|_| 22).collect();
+    for publishers in [1_u64, 2, 4, 8, 16].iter() {
+        let msg = &msg;
+        group.bench_with_input(
+            BenchmarkId::from_parameter(publishers),
+            publishers,
+            |b, &publishers| {
+                b.to_async(tokio::runtime::Runtime::new().unwrap())
+                    .iter_custom(|n| async move {
+                        let nc = nats_aflowt::connect("127.0.0.1").await.unwrap();
+                        let start = Instant::now();
+                        let tasks: Vec<_> = (0..publishers)
+                            .map(|task| {
+                                let nc = nc.clone();
+                                let msg = msg.clone();
+                                // Split the iterations evenly between the tasks.
+                                let count = n / publishers + u64::from(task < n % publishers);
+                                tokio::spawn(async move {
+                                    for _i in 0..count {
+                                        nc.publish("bench", &msg).await.unwrap();
+                                    }
+                                })
+                            })
+                            .collect();
+                        for task in tasks {
+                            task.await.unwrap();
+                        }
+                        nc.flush().await.unwrap();
+                        start.elapsed()
+                    });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pub_benchmark, concurrent_pub_benchmark);
 criterion_main!(benches);
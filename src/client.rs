@@ -13,14 +13,21 @@
 
 use crate::{
     connector::{Connector, NatsStream, ServerAddress},
+    events::{ConnectionEvent, ConnectionState, Events},
     header::HeaderMap,
     inject_delay, inject_io_failure,
     message::Message,
-    proto::{self, ClientOp, ServerOp},
+    proto::{self, ClientOp, ServerOp, ServerOpReader},
+    runtime,
+    statistics::{Counter, Statistics, SubscriptionCounter},
+    subscription::{
+        PendingBytes, PendingLimits, SlowConsumerPolicy, SubscriptionOptions, SubscriptionReceiver,
+    },
     BoxFuture, Options, ServerInfo,
 };
+use bytes::Bytes;
 #[cfg(not(feature = "otel"))]
-use log::{debug, error};
+use log::error;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     fmt,
@@ -28,39 +35,41 @@ use std::{
     mem,
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, PoisonError, Weak,
     },
     task::{Context, Poll},
     time::{Duration, Instant},
 };
 use tokio::{
-    io::{AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
-    sync::Mutex,
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::{mpsc::error::TrySendError, oneshot, Mutex},
 };
 #[cfg(feature = "otel")]
-use tracing::{debug, error};
+use tracing::error;
 
 const BUF_CAPACITY: usize = 32 * 1024;
-/// maximum messages to queue before applying backpressure
-const MAX_SUBSCRIPTION_QUEUE: usize = 650;
-
-const PING_FLUSH_TIMEOUT_SEC: u64 = 40;
 
-// TODO(dlc) - Make configurable.
-const PING_INTERVAL: Duration = Duration::from_secs(20); // was 120 sec
-const MAX_PINGS_OUT: u8 = 2;
+/// Number of operations that can be queued for the writer task. This is also
+/// the most operations the writer coalesces into a single flush.
+const WRITE_QUEUE_CAPACITY: usize = 1024;
 
 /// Client state.
 ///
-/// NB: locking protocol - writes must ALWAYS be locked
-///     first and released after when both are used.
-///     Failure to follow this strict rule WILL create
-///     a deadlock!
+/// The write half of the connection is owned by the writer task, which drains
+/// the `commands` queue. Operations that must stay in order with changes to
+/// the read state, like SUB and PING, are queued while holding its lock.
 struct State {
-    write: Mutex<WriteState>,
+    commands: tokio::sync::mpsc::Sender<Command>,
+    status: Arc<WriterStatus>,
     read: Mutex<ReadState>,
     meta: Mutex<MetaState>,
+
+    /// Next subscription ID.
+    next_sid: AtomicU64,
+
+    /// Wakes up the dispatcher when PINGs went unanswered.
+    stale: tokio::sync::Notify,
 }
 
 struct MetaState {
@@ -68,24 +77,50 @@ struct MetaState {
     mutes: HashSet<u64>,
 }
 
-struct WriteState {
-    /// Buffered writer with an active connection.
-    ///
-    /// When `None`, the client is either reconnecting or closed.
-    writer: Option<BufWriter<NatsStream>>,
+/// Connection status published by the writer task.
+#[derive(Default)]
+struct WriterStatus {
+    /// Set while the writer task has an active connection.
+    connected: AtomicBool,
 
-    /// Signals to the client thread that the writer needs a flush.
-    flush_kicker: tokio::sync::mpsc::Sender<()>,
+    /// Set once the writer task had its first connection.
+    has_connected: AtomicBool,
 
-    /// The reconnect buffer.
-    ///
-    /// When the client is reconnecting, PUB messages get buffered here. When
-    /// the connection is re-established, contents of the buffer are
-    /// flushed to the server.
-    buffer: Buffer,
+    /// Number of bytes in the reconnect buffer.
+    buffered: AtomicUsize,
 
-    /// Next subscription ID.
-    next_sid: u64,
+    /// Notified when writing into the connection failed, so that the client
+    /// reconnects.
+    failed: tokio::sync::Notify,
+}
+
+/// An operation queued for the writer task.
+enum Command {
+    /// Encoded PUB or HPUB operations, which are stored in the reconnect
+    /// buffer while disconnected. The outcome is sent to `ack`, if set.
+    Publish {
+        chunks: Vec<Bytes>,
+        ack: Option<oneshot::Sender<io::Result<()>>>,
+    },
+
+    /// Any other encoded operation, which is dropped while disconnected.
+    Control(Vec<u8>),
+
+    /// Acknowledged once all operations queued before it have been flushed.
+    Flush(oneshot::Sender<()>),
+
+    /// Starts writing into a new connection, beginning with `preamble`.
+    /// Buffered HPUB operations are dropped unless the server supports
+    /// `headers`.
+    Connect {
+        writer: BufWriter<NatsStream>,
+        preamble: Vec<u8>,
+        headers: bool,
+        ack: oneshot::Sender<io::Result<()>>,
+    },
+
+    /// Shuts down the current connection.
+    Disconnect,
 }
 
 struct ReadState {
@@ -99,7 +134,7 @@ struct ReadState {
     last_active: Instant,
 
     /// Used for client side monitoring of connection health.
-    pings_out: u8,
+    pings_out: usize,
 }
 
 /// A handler for preprocess messages for a subscription as they arrive over the wire.
@@ -121,6 +156,234 @@ struct Subscription {
     queue_group: Option<String>,
     messages: tokio::sync::mpsc::Sender<Message>,
     preprocess: Pin<Box<dyn Preprocessor>>,
+    counter: SubscriptionCounter,
+
+    /// Limits on the messages queued for the subscriber.
+    limits: PendingLimits,
+    pending: Arc<PendingBytes>,
+
+    /// The receiving end of `messages`, used to drop the oldest message.
+    receiver: Weak<std::sync::Mutex<tokio::sync::mpsc::Receiver<Message>>>,
+
+    /// Set while messages are being dropped, so slow consumers are reported once.
+    slow: AtomicBool,
+
+    /// Number of messages received from the server, including dropped ones.
+    received: AtomicU64,
+
+    /// Total number of messages after which the subscription ends, if set.
+    max_msgs: Option<u64>,
+}
+
+/// Outcome of handing a message to a subscription.
+enum Delivery {
+    /// The message was queued, after dropping this many older messages.
+    Queued(u64),
+    /// The message was dropped because the subscriber is too slow.
+    Dropped,
+    /// The message was dropped because the subscriber is gone.
+    Closed,
+}
+
+impl Subscription {
+    /// Counts a message received from the server. Returns true if it is the last
+    /// message before the subscription auto-unsubscribes.
+    fn count_received(&self) -> bool {
+        let received = self.received.fetch_add(1, Ordering::Relaxed) + 1;
+        matches!(self.max_msgs, Some(max) if received >= max)
+    }
+
+    /// Returns the number of messages left before the subscription auto-unsubscribes.
+    fn remaining(&self) -> Option<u64> {
+        self.max_msgs
+            .map(|max| max.saturating_sub(self.received.load(Ordering::Relaxed)))
+    }
+
+    /// Queues a message for the subscriber, applying the subscription's pending limits.
+    async fn deliver(&self, mut msg: Message) -> Delivery {
+        let size = msg.data.len();
+        let pending = &self.pending;
+        let over_bytes = || matches!(self.limits.bytes, Some(max) if pending.get() > 0 && pending.get() + size > max);
+
+        match self.limits.policy {
+            SlowConsumerPolicy::Block => {
+                while over_bytes() {
+                    let released = pending.released();
+                    if !over_bytes() {
+                        break;
+                    }
+                    tokio::select! {
+                        _ = released => {}
+                        _ = self.messages.closed() => return Delivery::Closed,
+                    }
+                }
+                pending.acquire(size);
+                if self.messages.send(msg).await.is_ok() {
+                    Delivery::Queued(0)
+                } else {
+                    pending.release(size);
+                    Delivery::Closed
+                }
+            }
+            SlowConsumerPolicy::DropNew => {
+                if over_bytes() {
+                    return Delivery::Dropped;
+                }
+                pending.acquire(size);
+                match self.messages.try_send(msg) {
+                    Ok(()) => Delivery::Queued(0),
+                    Err(err) => {
+                        pending.release(size);
+                        match err {
+                            TrySendError::Full(_) => Delivery::Dropped,
+                            TrySendError::Closed(_) => Delivery::Closed,
+                        }
+                    }
+                }
+            }
+            SlowConsumerPolicy::DropOldest => {
+                let mut dropped = 0;
+                loop {
+                    if !over_bytes() {
+                        pending.acquire(size);
+                        match self.messages.try_send(msg) {
+                            Ok(()) => return Delivery::Queued(dropped),
+                            Err(TrySendError::Full(returned)) => {
+                                pending.release(size);
+                                msg = returned;
+                            }
+                            Err(TrySendError::Closed(_)) => {
+                                pending.release(size);
+                                return Delivery::Closed;
+                            }
+                        }
+                    }
+
+                    // Make room by dropping the oldest pending message.
+                    let receiver = match self.receiver.upgrade() {
+                        Some(receiver) => receiver,
+                        None => return Delivery::Closed,
+                    };
+                    let oldest = receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .try_recv()
+                        .ok();
+                    match oldest {
+                        Some(oldest) => {
+                            pending.release(oldest.data.len());
+                            dropped += 1;
+                        }
+                        // The subscriber took the last messages, and is about
+                        // to release their bytes.
+                        None => runtime::yield_now().await,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Updates the counters after a delivery, and returns a slow consumer error
+    /// when the subscription starts dropping messages.
+    fn record(&self, sid: u64, delivery: &Delivery) -> Option<crate::Error> {
+        let dropped = match delivery {
+            Delivery::Queued(dropped) => {
+                self.counter.delivered();
+                *dropped
+            }
+            Delivery::Dropped => 1,
+            Delivery::Closed => {
+                self.counter.dropped(1);
+                return None;
+            }
+        };
+
+        if dropped == 0 {
+            self.slow.store(false, Ordering::Relaxed);
+            return None;
+        }
+        self.counter.dropped(dropped);
+        if self.slow.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        Some(crate::Error::SlowConsumer {
+            sid,
+            subject: self.subject.clone(),
+            dropped: self.counter.dropped_total(),
+        })
+    }
+}
+
+/// Encodes a PUB operation, or HPUB if there are headers, for the writer task.
+///
+/// With the `otel` feature and `trace` set, the context of the current span is
+/// added to the headers.
+fn encode_publish(
+    chunks: &mut Vec<Bytes>,
+    subject: &str,
+    reply_to: Option<&str>,
+    headers: Option<&HeaderMap>,
+    payload: Bytes,
+    trace: bool,
+) {
+    #[cfg(feature = "otel")]
+    let headers = if trace {
+        crate::otel::inject_context(headers)
+    } else {
+        headers.map(std::borrow::Cow::Borrowed)
+    };
+    #[cfg(feature = "otel")]
+    let headers = headers.as_deref();
+    #[cfg(not(feature = "otel"))]
+    let _ = trace;
+
+    proto::encode_publish(chunks, subject, reply_to, headers, payload);
+}
+
+/// Encodes the PUB or HPUB operation for a `Message`, sharing its payload.
+fn encode_message(chunks: &mut Vec<Bytes>, msg: &Message, trace: bool) {
+    encode_publish(
+        chunks,
+        &msg.subject,
+        msg.reply.as_deref(),
+        msg.headers.as_ref(),
+        msg.data.clone(),
+        trace,
+    );
+}
+
+/// Removes HPUB operations from encoded PUB and HPUB operations, and returns
+/// the remaining operations along with the number of removed ones.
+fn without_hpub(mut bytes: &[u8]) -> (Vec<u8>, usize) {
+    let mut kept = Vec::with_capacity(bytes.len());
+    let mut dropped = 0;
+    while !bytes.is_empty() {
+        // The operation line ends with the total size of what follows it.
+        let line = match bytes.windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => pos + 2,
+            None => bytes.len(),
+        };
+        let size = std::str::from_utf8(&bytes[..line])
+            .ok()
+            .and_then(|op| op.split_whitespace().last())
+            .and_then(|size| size.parse::<usize>().ok())
+            .unwrap_or(0);
+        let (op, rest) = bytes.split_at((line + size + 2).min(bytes.len()));
+        if op.starts_with(b"HPUB ") {
+            dropped += 1;
+        } else {
+            kept.extend_from_slice(op);
+        }
+        bytes = rest;
+    }
+    (kept, dropped)
+}
+
+/// Encodes an operation to be queued for the writer task.
+async fn encode(op: ClientOp<'_>) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    proto::encode(&mut bytes, op).await?;
+    Ok(bytes)
 }
 
 /// A NATS client.
@@ -137,14 +400,30 @@ pub struct Client {
 
     /// The options that this `Client` was created using.
     pub(crate) options: Arc<Options>,
+
+    /// Number of times the client has reconnected.
+    reconnects: Arc<tokio::sync::watch::Sender<u64>>,
+
+    /// The pool of servers the client connects to.
+    servers: Arc<tokio::sync::watch::Sender<Vec<ServerAddress>>>,
+
+    /// Connection state and lifecycle events.
+    pub(crate) events: Arc<Events>,
+
+    /// Counters of messages received from the server.
+    inbound: Arc<Counter>,
+
+    /// Counters of published messages.
+    outbound: Arc<Counter>,
 }
 
 impl Client {
     /// Creates a new client that will begin connecting in the background.
     pub(crate) async fn connect(urls: Vec<ServerAddress>, options: Options) -> io::Result<Client> {
         crate::init_tracing();
-        // A channel for coordinating flushes.
-        let (flush_kicker, mut flush_wanted) = tokio::sync::mpsc::channel(10);
+        // The queue of operations for the writer task.
+        let (commands, queued) = tokio::sync::mpsc::channel(WRITE_QUEUE_CAPACITY);
+        let status = Arc::new(WriterStatus::default());
 
         // Channels for coordinating initial connect.
         let (run_sender, run_receiver) = tokio::sync::oneshot::channel();
@@ -153,32 +432,50 @@ impl Client {
         // The client state.
         let _client = Client {
             state: Arc::new(State {
+                commands,
+                status: status.clone(),
                 meta: Mutex::new(MetaState {
                     mutes: HashSet::new(),
                 }),
-                write: Mutex::new(WriteState {
-                    writer: None,
-                    flush_kicker,
-                    buffer: Buffer::new(options.reconnect_buffer_size),
-                    next_sid: 1,
-                }),
                 read: Mutex::new(ReadState {
                     subscriptions: HashMap::new(),
                     pongs: VecDeque::from(vec![pong_sender]),
                     last_active: Instant::now(),
                     pings_out: 0,
                 }),
+                next_sid: AtomicU64::new(1),
+                stale: tokio::sync::Notify::new(),
             }),
             server_info: Arc::new(Mutex::new(ServerInfo::default())),
             shutdown: Arc::new(AtomicBool::new(false)),
             options: Arc::new(options),
+            reconnects: Arc::new(tokio::sync::watch::channel(0).0),
+            servers: Arc::new(tokio::sync::watch::channel(Vec::new()).0),
+            events: Arc::new(Events::new()),
+            inbound: Arc::new(Counter::default()),
+            outbound: Arc::new(Counter::default()),
         };
 
         let options = _client.options.clone();
 
+        // Spawn the writer task, which runs until the client is dropped.
+        let writer = Writer {
+            writer: None,
+            buffer: Buffer::new(options.reconnect_buffer_size),
+            status,
+            flushes: Vec::new(),
+        };
+        runtime::spawn(writer.run(queued));
+
         // Connector for creating the initial connection and reconnecting when
         // it is broken.
-        let connector = Connector::new(urls, options.clone()).await?;
+        let connector = Connector::new(
+            urls,
+            options.clone(),
+            _client.events.clone(),
+            _client.servers.clone(),
+        )
+        .await?;
 
         // Spawn the async task responsible for:
         // - Maintaining a connection to the server and reconnecting when it is
@@ -187,206 +484,268 @@ impl Client {
         // - Forwarding MSG operations to subscribers.
         let client = _client.clone();
         let opt = options.clone();
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             {
                 let res = client.run(connector).await;
-                run_sender.send(res).ok();
+
+                // Nobody waits for the outcome once connected, or when retrying
+                // a failed connect, so report errors to the error callback.
+                client.shutdown();
+                if let Err(Err(err)) = run_sender.send(res) {
+                    let si = client.server_info().await;
+                    opt.error_callback.call(si, err).await;
+                }
 
                 // One final flush before shutting down.
                 // This way we make sure buffered published messages reach the
                 // server.
-                {
-                    let mut write = client.state.write.lock().await;
-                    if let Some(writer) = write.writer.as_mut() {
-                        writer.shutdown().await.ok();
-                    }
+                let (ack, flushed) = oneshot::channel();
+                if client.send(Command::Flush(ack)).await.is_ok() {
+                    flushed.await.ok();
                 }
+                client.send(Command::Disconnect).await.ok();
+                client.events.emit(ConnectionEvent::Closed);
                 opt.close_callback.call().await;
             }
         });
 
-        tokio::select! {
-            res = run_receiver => {
-                res.expect("client thread has panicked")?;
-                unreachable!()
-            }
-            _ = pong_receiver.recv()  => { }
-        };
+        if options.retry_on_failed_connect {
+            // Keep connecting in the background, while subscriptions and
+            // publishes are buffered.
+            _client.events.set_state(ConnectionState::Reconnecting);
+        } else {
+            tokio::select! {
+                res = run_receiver => {
+                    res.expect("client thread has panicked")?;
+                    unreachable!()
+                }
+                _ = pong_receiver.recv()  => { }
+            };
+        }
 
-        // Spawn a thread that periodically flushes buffered messages.
-        let client = _client.clone();
-        tokio::spawn(async move {
-            // Track last flush/write time.
-            const MIN_FLUSH_BETWEEN: Duration = Duration::from_millis(5);
+        // Spawn a task that periodically checks the health of the connection.
+        if !options.ping_interval.is_zero() {
+            runtime::spawn(_client.clone().ping());
+        }
 
-            // Handle recv timeouts and check if we should send a PING.
+        Ok(_client)
+    }
 
-            let mut last = Instant::now() - MIN_FLUSH_BETWEEN;
+    /// Retrieves server info as received by the most recent connection.
+    pub async fn server_info(&self) -> ServerInfo {
+        self.server_info.lock().await.clone()
+    }
 
-            // Wait until at least one message is buffered.
-            loop {
-                if tokio::time::timeout(PING_INTERVAL, flush_wanted.recv())
-                    .await
-                    .is_ok()
-                {
-                    let since = last.elapsed();
-                    if since < MIN_FLUSH_BETWEEN {
-                        tokio::time::sleep(MIN_FLUSH_BETWEEN - since).await;
-                    }
+    /// Creates a new unique inbox below the configured inbox prefix.
+    pub(crate) fn new_inbox(&self) -> String {
+        format!("{}.{}", self.options.inbox_prefix, nuid::next())
+    }
 
-                    // Flush the writer.
-                    let mut write = client.state.write.lock().await;
-                    let mut read = client.state.read.lock().await;
-                    if let Some(writer) = write.writer.as_mut() {
-                        // If flushing fails, disconnect.
-                        if writer.flush().await.is_err() {
-                            last = Instant::now();
-                            let _ = writer.shutdown().await;
-                            write.writer = None;
-                            read.pongs.clear();
-                        }
-                    }
+    /// Returns the addresses of the server pool.
+    pub(crate) fn servers(&self) -> Vec<ServerAddress> {
+        self.servers.borrow().clone()
+    }
 
-                    // NB see locking protocol for state.write and state.read
-                    drop(read);
-                    drop(write);
-                } else {
-                    // timeout
-                    let mut write = client.state.write.lock().await;
-                    let mut read = client.state.read.lock().await;
+    /// Returns a receiver that is notified every time the client reconnects.
+    pub(crate) fn reconnects(&self) -> tokio::sync::watch::Receiver<u64> {
+        self.reconnects.subscribe()
+    }
 
-                    if read.pings_out >= MAX_PINGS_OUT {
-                        if let Some(writer) = write.writer.as_mut() {
-                            writer.get_mut().shutdown().await;
-                        }
-                        write.writer = None;
-                        read.pongs.clear();
-                    } else if read.last_active.elapsed() > PING_INTERVAL {
-                        read.pings_out += 1;
-                        read.pongs.push_back(write.flush_kicker.clone());
-                        // Send out a PING here.
-                        if let Some(mut writer) = write.writer.as_mut() {
-                            // Ok to ignore errors here.
-                            let _ = proto::encode(&mut writer, ClientOp::Ping).await;
-                            if writer.flush().await.is_err() {
-                                // NB see locking protocol for state.write and state.read
-                                writer.shutdown().await.ok();
-                                write.writer = None;
-                                read.pongs.clear();
-                            }
-                        }
-                    }
+    /// Sends PINGs to a quiet server, and reports the connection as stale when
+    /// too many of them go unanswered.
+    async fn ping(self) {
+        let ping_interval = self.options.ping_interval;
 
-                    // NB see locking protocol for state.write and state.read
-                    drop(read);
-                    drop(write);
-                }
+        // Nobody waits for the PONGs of these PINGs.
+        let (ping_pong, _) = tokio::sync::mpsc::channel(1);
+
+        let mut next_ping = Instant::now() + ping_interval;
+        loop {
+            runtime::sleep_until(next_ping).await;
+            next_ping += ping_interval;
+            if self.check_shutdown().is_err() {
+                break;
+            }
+            if !self.state.status.connected.load(Ordering::Acquire) {
+                continue;
             }
-        });
 
-        Ok(_client)
+            let mut read = self.state.read.lock().await;
+            if read.pings_out >= self.options.max_pings_outstanding {
+                // The server stopped responding, go through the reconnect path.
+                read.pings_out = 0;
+                read.pongs.clear();
+                drop(read);
+                self.state.stale.notify_one();
+
+                let si = self.server_info().await;
+                self.options
+                    .error_callback
+                    .call(si, crate::Error::StaleConnection.into())
+                    .await;
+            } else if read.last_active.elapsed() >= ping_interval {
+                read.pings_out += 1;
+                read.pongs.push_back(ping_pong.clone());
+                // Ok to ignore errors here.
+                self.send_op(ClientOp::Ping).await.ok();
+            }
+        }
     }
 
-    /// Retrieves server info as received by the most recent connection.
-    pub async fn server_info(&self) -> ServerInfo {
-        self.server_info.lock().await.clone()
+    /// Queues an operation for the writer task.
+    async fn send(&self, command: Command) -> io::Result<()> {
+        self.state
+            .commands
+            .send(command)
+            .await
+            .map_err(|_| crate::Error::ConnectionClosed.into())
+    }
+
+    /// Encodes and queues an operation other than PUB for the writer task.
+    async fn send_op(&self, op: ClientOp<'_>) -> io::Result<()> {
+        let bytes = encode(op).await?;
+        self.send(Command::Control(bytes)).await
+    }
+
+    /// Queues encoded PUB operations for the writer task.
+    ///
+    /// While disconnected, waits until they are stored in the reconnect
+    /// buffer, so that a full buffer is reported to the publisher.
+    async fn send_publish(&self, chunks: Vec<Bytes>) -> io::Result<()> {
+        if self.state.status.connected.load(Ordering::Acquire) {
+            return self.send(Command::Publish { chunks, ack: None }).await;
+        }
+        let (ack, stored) = oneshot::channel();
+        self.send(Command::Publish {
+            chunks,
+            ack: Some(ack),
+        })
+        .await?;
+        stored
+            .await
+            .unwrap_or_else(|_| Err(crate::Error::ConnectionClosed.into()))
+    }
+
+    /// Queues encoded PUB operations for the writer task, unless its queue is
+    /// full.
+    async fn try_send_publish(&self, chunks: Vec<Bytes>) -> Option<io::Result<()>> {
+        let (ack, stored) = if self.state.status.connected.load(Ordering::Acquire) {
+            (None, None)
+        } else {
+            let (ack, stored) = oneshot::channel();
+            (Some(ack), Some(stored))
+        };
+        match self
+            .state
+            .commands
+            .try_send(Command::Publish { chunks, ack })
+        {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => return None,
+            Err(TrySendError::Closed(_)) => {
+                return Some(Err(crate::Error::ConnectionClosed.into()))
+            }
+        }
+        match stored {
+            None => Some(Ok(())),
+            Some(stored) => Some(
+                stored
+                    .await
+                    .unwrap_or_else(|_| Err(crate::Error::ConnectionClosed.into())),
+            ),
+        }
     }
 
     /// Makes a round trip to the server to ensure buffered messages reach it.
+    /// Fails with `Error::TimedOut` if that takes longer than `timeout`.
     pub(crate) async fn flush(&self, timeout: Duration) -> io::Result<()> {
+        runtime::timeout(timeout, self.ping_pong()).await?
+    }
+
+    /// Sends a PING and waits for its PONG.
+    async fn ping_pong(&self) -> io::Result<()> {
         let mut pong = {
             // Inject random delays when testing.
             inject_delay().await;
 
-            let mut write = self.state.write.lock().await;
-
             // Check if the client is closed.
             self.check_shutdown()?;
 
             let (sender, receiver) = tokio::sync::mpsc::channel(1);
 
-            // If connected, send a PING.
-            match write.writer.as_mut() {
-                None => {}
-                Some(mut writer) => {
-                    // uses timeout for duration, not per-write
-                    tokio::time::timeout(timeout, async {
-                        if let Ok(()) = proto::encode(&mut writer, ClientOp::Ping).await {
-                            let _ = writer.flush().await;
-                        }
-                    })
-                    .await?;
-                }
-            }
-
-            if let Err(e) = write.flush_kicker.send(()).await {
-                debug!("error triggering flush {}", e);
-            }
-            // Enqueue an expected PONG.
+            // Send a PING, dropped by the writer task while disconnected, and
+            // enqueue the expected PONG. The read lock keeps both in order.
             let mut read = self.state.read.lock().await;
+            self.send_op(ClientOp::Ping).await?;
             read.pongs.push_back(sender);
-
-            // NB see locking protocol for state.write and state.read
             drop(read);
-            drop(write);
 
             receiver
         };
 
         // Wait until the PONG operation is received.
-        match tokio::time::timeout(Duration::from_secs(PING_FLUSH_TIMEOUT_SEC), pong.recv()).await {
-            Err(_) => {
-                // if socket is closed, don't fail
-                //error!("ping flush timed out after {} sec", PING_FLUSH_TIMEOUT_SEC);
-                //Err(Error::new(ErrorKind::ConnectionReset, "flush failed"))
-                Ok(())
-            }
-            Ok(Some(())) => Ok(()),
-            Ok(None) => {
+        match pong.recv().await {
+            Some(()) => Ok(()),
+            None => {
                 error!("ping sender quit unexpectedly");
-                Err(Error::new(
-                    ErrorKind::ConnectionReset,
-                    "flush failed - ping sender quit",
-                ))
+                Err(crate::Error::ConnectionClosed.into())
             }
         }
     }
 
+    /// Returns a snapshot of the client's counters.
+    pub(crate) async fn statistics(&self) -> Statistics {
+        let read = self.state.read.lock().await;
+
+        let mut subscriptions: Vec<_> = read
+            .subscriptions
+            .iter()
+            .map(|(sid, subscription)| subscription.counter.snapshot(*sid, &subscription.subject))
+            .collect();
+        subscriptions.sort_by_key(|subscription| subscription.sid);
+
+        let statistics = Statistics {
+            in_msgs: self.inbound.msgs(),
+            in_bytes: self.inbound.bytes(),
+            out_msgs: self.outbound.msgs(),
+            out_bytes: self.outbound.bytes(),
+            reconnects: *self.reconnects.borrow(),
+            pending_bytes: self.state.status.buffered.load(Ordering::Relaxed),
+            subscriptions,
+        };
+        drop(read);
+
+        statistics
+    }
+
     /// Closes the client.
     pub(crate) async fn close(&self) {
         // Inject random delays when testing.
         inject_delay().await;
 
-        let mut write = self.state.write.lock().await;
         let mut read = self.state.read.lock().await;
 
         // Initiate shutdown process.
         if self.shutdown() {
+            self.events.emit(ConnectionEvent::Closed);
+
             // Clear all subscriptions.
             let old_subscriptions = mem::take(&mut read.subscriptions);
             for (sid, _) in old_subscriptions {
                 // Send an UNSUB message and ignore errors.
-                if let Some(writer) = write.writer.as_mut() {
-                    let max_msgs = None;
-                    proto::encode(writer, ClientOp::Unsub { sid, max_msgs })
-                        .await
-                        .ok();
-                    write.flush_kicker.try_send(()).ok();
-                }
-            }
-            read.subscriptions.clear();
-
-            // Flush the writer in case there are buffered messages.
-            if let Some(writer) = write.writer.as_mut() {
-                writer.flush().await.ok();
+                let max_msgs = None;
+                self.send_op(ClientOp::Unsub { sid, max_msgs }).await.ok();
             }
 
             // Wake up all pending flushes.
             read.pongs.clear();
-
-            // NB see locking protocol for state.write and state.read
             drop(read);
-            drop(write);
+
+            // Wait until buffered messages are flushed.
+            let (ack, flushed) = oneshot::channel();
+            if self.send(Command::Flush(ack)).await.is_ok() {
+                flushed.await.ok();
+            }
         }
     }
 
@@ -400,7 +759,7 @@ impl Client {
 
     fn check_shutdown(&self) -> io::Result<()> {
         if self.shutdown.load(Ordering::Acquire) {
-            Err(Error::new(ErrorKind::NotConnected, "the client is closed"))
+            Err(crate::Error::ConnectionClosed.into())
         } else {
             Ok(())
         }
@@ -427,33 +786,63 @@ impl Client {
         subject: String,
         queue_group: Option<String>,
         message_processor: Pin<Box<dyn Preprocessor>>,
+    ) -> io::Result<(u64, crate::subscription::SubscriptionReceiver<Message>)> {
+        self.subscribe_with_limits(
+            subject,
+            queue_group,
+            message_processor,
+            PendingLimits::default(),
+        )
+        .await
+    }
+
+    /// Subscribes to a subject with the given options.
+    pub(crate) async fn subscribe_with_options(
+        &self,
+        subject: &str,
+        options: &SubscriptionOptions,
+    ) -> io::Result<(u64, crate::subscription::SubscriptionReceiver<Message>)> {
+        self.subscribe_with_limits(
+            subject.to_string(),
+            options.queue_group.clone(),
+            Box::pin(NoProcessing::default()),
+            options.limits,
+        )
+        .await
+    }
+
+    async fn subscribe_with_limits(
+        &self,
+        subject: String,
+        queue_group: Option<String>,
+        message_processor: Pin<Box<dyn Preprocessor>>,
+        limits: PendingLimits,
     ) -> io::Result<(u64, crate::subscription::SubscriptionReceiver<Message>)> {
         inject_delay().await;
 
-        let mut write = self.state.write.lock().await;
         let mut read = self.state.read.lock().await;
 
         // Check if the client is closed.
         self.check_shutdown()?;
 
         // Generate a subject ID.
-        let sid = write.next_sid;
-        write.next_sid += 1;
-
-        // If connected, send a SUB operation.
-        if let Some(writer) = write.writer.as_mut() {
-            let queue_group = queue_group.as_ref();
-            let op = ClientOp::Sub {
-                subject: &subject,
-                queue_group: queue_group.map(|s| s.as_str()),
-                sid,
-            };
-            proto::encode(writer, op).await?;
-            write.flush_kicker.try_send(()).ok();
-        }
+        let sid = self.state.next_sid.fetch_add(1, Ordering::Relaxed);
+
+        // Send a SUB operation, dropped by the writer task while disconnected.
+        let op = ClientOp::Sub {
+            subject: &subject,
+            queue_group: queue_group.as_deref(),
+            sid,
+        };
+        self.send_op(op).await?;
 
         // Register the subscription in the hash map.
-        let (sender, receiver) = tokio::sync::mpsc::channel(MAX_SUBSCRIPTION_QUEUE);
+        let (sender, receiver) = tokio::sync::mpsc::channel(limits.messages);
+        let pending = Arc::new(PendingBytes::default());
+        let receiver =
+            SubscriptionReceiver::with_pending(receiver, pending.clone(), |msg: &Message| {
+                msg.data.len()
+            });
         read.subscriptions.insert(
             sid,
             Subscription {
@@ -461,14 +850,18 @@ impl Client {
                 queue_group,
                 messages: sender,
                 preprocess: message_processor,
+                counter: SubscriptionCounter::default(),
+                limits,
+                pending,
+                receiver: receiver.downgrade(),
+                slow: AtomicBool::new(false),
+                received: AtomicU64::new(0),
+                max_msgs: None,
             },
         );
-
-        // NB see locking protocol for state.write and state.read
         drop(read);
-        drop(write);
 
-        Ok((sid, receiver.into()))
+        Ok((sid, receiver))
     }
 
     /// Marks a subscription as muted.
@@ -483,7 +876,6 @@ impl Client {
         // Inject random delays when testing.
         inject_delay().await;
 
-        let mut write = self.state.write.lock().await;
         let mut read = self.state.read.lock().await;
 
         // Check if the client is closed.
@@ -492,76 +884,80 @@ impl Client {
         let subscription = read
             .subscriptions
             .remove(&old_sid)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "subscription not found"))?;
+            .ok_or_else(|| io::Error::from(crate::Error::validation("subscription not found")))?;
 
         // Generate a subject ID.
-        let new_sid = write.next_sid;
-        write.next_sid += 1;
+        let new_sid = self.state.next_sid.fetch_add(1, Ordering::Relaxed);
 
         // Send an UNSUB and SUB messages.
-        if let Some(writer) = write.writer.as_mut() {
-            proto::encode(
-                writer,
-                ClientOp::Unsub {
-                    sid: old_sid,
-                    max_msgs: None,
-                },
-            )
-            .await?;
-        }
+        self.send_op(ClientOp::Unsub {
+            sid: old_sid,
+            max_msgs: None,
+        })
+        .await?;
 
         let queue_group = subscription.queue_group.clone();
         read.subscriptions.insert(new_sid, subscription);
 
-        if let Some(writer) = write.writer.as_mut() {
-            proto::encode(
-                writer,
-                ClientOp::Sub {
-                    sid: new_sid,
-                    subject: new_subject,
-                    queue_group: queue_group.as_deref(),
-                },
-            )
-            .await?;
-            write.flush_kicker.try_send(()).ok();
-        }
-
-        // NB see locking protocol for state.write and state.read
+        self.send_op(ClientOp::Sub {
+            sid: new_sid,
+            subject: new_subject,
+            queue_group: queue_group.as_deref(),
+        })
+        .await?;
         drop(read);
-        drop(write);
 
         Ok(new_sid)
     }
 
+    /// Unsubscribes automatically after the subscription received `max_msgs` messages in total.
+    pub(crate) async fn auto_unsubscribe(&self, sid: u64, max_msgs: u64) -> io::Result<()> {
+        // Inject random delays when testing.
+        inject_delay().await;
+
+        let mut read = self.state.read.lock().await;
+
+        // Check if the client is closed.
+        self.check_shutdown()?;
+
+        let subscription = match read.subscriptions.get_mut(&sid) {
+            Some(subscription) => subscription,
+            None => return Err(crate::Error::validation("subscription not found").into()),
+        };
+        subscription.max_msgs = Some(max_msgs);
+
+        // Unsubscribe right away if enough messages were received already.
+        let max_msgs = if subscription.remaining() == Some(0) {
+            read.subscriptions.remove(&sid);
+            None
+        } else {
+            Some(max_msgs)
+        };
+
+        // Send an UNSUB message. The server counts the messages it delivered as well.
+        self.send_op(ClientOp::Unsub { sid, max_msgs }).await?;
+        drop(read);
+
+        Ok(())
+    }
+
     /// Unsubscribes from a subject.
     pub(crate) async fn unsubscribe(&self, sid: u64) -> io::Result<()> {
         // Inject random delays when testing.
         inject_delay().await;
 
-        let mut write = self.state.write.lock().await;
         let mut read = self.state.read.lock().await;
 
         // Remove the subscription from the map.
         if read.subscriptions.remove(&sid).is_none() {
             // already unsubscribed
-
-            // NB see locking protocol for state.write and state.read
-            drop(read);
-            drop(write);
-
             return Ok(());
         }
 
         // Send an UNSUB message.
-        if let Some(writer) = write.writer.as_mut() {
-            let max_msgs = None;
-            proto::encode(writer, ClientOp::Unsub { sid, max_msgs }).await?;
-            write.flush_kicker.try_send(()).ok();
-        }
-
-        // NB see locking protocol for state.write and state.read
+        let max_msgs = None;
+        self.send_op(ClientOp::Unsub { sid, max_msgs }).await?;
         drop(read);
-        drop(write);
 
         Ok(())
     }
@@ -572,152 +968,139 @@ impl Client {
         subject: &str,
         reply_to: Option<&str>,
         headers: Option<&HeaderMap>,
-        msg: &[u8],
+        msg: Bytes,
     ) -> io::Result<()> {
         // Inject random delays when testing.
         inject_delay().await;
 
-        let server_info = self.server_info.lock().await;
-        if headers.is_some() && !server_info.headers {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "the server does not support headers",
-            ));
-        }
-        drop(server_info);
+        self.check_headers(headers.is_some()).await?;
 
         // Check if the client is closed.
         self.check_shutdown()?;
 
-        let op = if let Some(headers) = headers {
-            ClientOp::Hpub {
-                subject,
-                reply_to,
-                payload: msg,
-                headers,
-            }
-        } else {
-            ClientOp::Pub {
-                subject,
-                reply_to,
-                payload: msg,
-            }
-        };
+        let len = msg.len();
+        let trace = self.traces().await;
+        let mut chunks = Vec::with_capacity(3);
+        encode_publish(&mut chunks, subject, reply_to, headers, msg, trace);
+        self.send_publish(chunks).await?;
+        self.outbound.add(len);
+        Ok(())
+    }
 
-        let mut write = self.state.write.lock().await;
-        let written = write.buffer.written;
-        match write.writer.as_mut() {
-            None => {
-                // If reconnecting, write into the buffer.
-                proto::encode(&mut write.buffer, op).await?;
-                write.buffer.flush().await?;
-                Ok(())
-            }
-            Some(mut writer) => {
-                assert_eq!(written, 0);
+    /// Publishes a batch of messages as a single operation for the writer task,
+    /// which writes them out with one flush.
+    pub(crate) async fn publish_batch(&self, messages: &[Message]) -> io::Result<()> {
+        // Inject random delays when testing.
+        inject_delay().await;
 
-                // If connected, write into the writer.
-                let res = proto::encode(&mut writer, op).await;
+        self.check_batch_headers(messages).await?;
 
-                // If writing fails, disconnect.
-                if res.is_err() {
-                    write.writer = None;
+        // Check if the client is closed.
+        self.check_shutdown()?;
 
-                    // NB see locking protocol for state.write and state.read
-                    let mut read = self.state.read.lock().await;
-                    read.pongs.clear();
-                }
+        let trace = self.traces().await;
+        let mut chunks = Vec::with_capacity(3 * messages.len());
+        for msg in messages {
+            encode_message(&mut chunks, msg, trace);
+        }
+        self.send_publish(chunks).await?;
+        for msg in messages {
+            self.outbound.add(msg.data.len());
+        }
+        Ok(())
+    }
 
-                write.flush_kicker.try_send(()).ok();
+    /// Attempts to publish a batch of messages without blocking.
+    ///
+    /// Queues messages from the front of the batch as long as the writer task
+    /// has room for them, and returns how many were queued. Returns `None` if
+    /// nothing could be queued without blocking.
+    pub(crate) async fn try_publish_batch(
+        &self,
+        messages: &[Message],
+    ) -> Option<io::Result<usize>> {
+        if let Err(err) = self.check_batch_headers(messages).await {
+            return Some(Err(err));
+        }
+
+        // Check if the client is closed.
+        if let Err(e) = self.check_shutdown() {
+            return Some(Err(e));
+        }
 
-                res
+        let trace = self.traces().await;
+        let mut count = 0;
+        for msg in messages {
+            let mut chunks = Vec::with_capacity(3);
+            encode_message(&mut chunks, msg, trace);
+            match self.try_send_publish(chunks).await {
+                Some(Ok(())) => self.outbound.add(msg.data.len()),
+                Some(Err(err)) => return Some(Err(err)),
+                None => break,
             }
+            count += 1;
+        }
+
+        if count == 0 && !messages.is_empty() {
+            None
+        } else {
+            Some(Ok(count))
         }
     }
 
+    /// Fails if any message has headers and the server does not support them.
+    async fn check_batch_headers(&self, messages: &[Message]) -> io::Result<()> {
+        self.check_headers(messages.iter().any(|msg| msg.headers.is_some()))
+            .await
+    }
+
+    /// Returns if publishes carry the trace context of the current span, which
+    /// needs the `otel` feature and a server that is known to support headers.
+    async fn traces(&self) -> bool {
+        cfg!(feature = "otel")
+            && self.state.status.has_connected.load(Ordering::Acquire)
+            && self.server_info.lock().await.headers
+    }
+
+    /// Fails if `headers` are used and the server does not support them.
+    ///
+    /// Until the first connect the server is unknown, so messages with headers
+    /// are buffered and checked by the writer task once it flushes the buffer.
+    async fn check_headers(&self, headers: bool) -> io::Result<()> {
+        if headers
+            && self.state.status.has_connected.load(Ordering::Acquire)
+            && !self.server_info.lock().await.headers
+        {
+            return Err(crate::Error::validation("the server does not support headers").into());
+        }
+        Ok(())
+    }
+
     /// Attempts to publish a message without blocking.
     ///
-    /// This only works when the write buffer has enough space to encode the
-    /// whole message.
+    /// This only works when the queue of the writer task has room for the
+    /// message.
     pub async fn try_publish(
         &self,
         subject: &str,
         reply_to: Option<&str>,
         headers: Option<&HeaderMap>,
-        msg: &[u8],
+        msg: Bytes,
     ) -> Option<io::Result<()>> {
         // Check if the client is closed.
         if let Err(e) = self.check_shutdown() {
             return Some(Err(e));
         }
 
-        // Estimate how many bytes the message will consume when written into
-        // the stream. We must make a conservative guess: it's okay to
-        // overestimate but not to underestimate.
-        let mut estimate = 1024 + subject.len() + reply_to.map_or(0, str::len) + msg.len();
-        if let Some(headers) = headers {
-            estimate += headers
-                .iter()
-                .map(|(k, v)| k.len() + v.len() + 3)
-                .sum::<usize>();
-        }
-
-        let op = if let Some(headers) = headers {
-            ClientOp::Hpub {
-                subject,
-                reply_to,
-                payload: msg,
-                headers,
-            }
-        } else {
-            ClientOp::Pub {
-                subject,
-                reply_to,
-                payload: msg,
-            }
-        };
-
-        let mut write = match self.state.write.try_lock() {
-            Ok(w) => w,
-            Err(e) => {
-                return Some(Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("mutex error: {}", e),
-                )))
-            }
-        };
-
-        match write.writer.as_mut() {
-            None => {
-                // If reconnecting, write into the buffer.
-                Some(match proto::encode(&mut write.buffer, op).await {
-                    Ok(()) => write.buffer.flush().await,
-                    Err(e) => Err(e),
-                })
-            }
-            Some(mut writer) => {
-                // Check if there's enough space in the buffer to encode the
-                // whole message.
-                if BUF_CAPACITY - writer.buffer().len() < estimate {
-                    return None;
-                }
-
-                // If connected, write into the writer. This is not going to
-                // block because there's enough space in the buffer.
-                let res = proto::encode(&mut writer, op).await;
-                write.flush_kicker.try_send(()).ok();
-
-                // If writing fails, disconnect.
-                if res.is_err() {
-                    write.writer = None;
-
-                    // NB see locking protocol for state.write and state.read
-                    let mut read = self.state.read.lock().await;
-                    read.pongs.clear();
-                }
-                Some(res)
-            }
+        let len = msg.len();
+        let trace = self.traces().await;
+        let mut chunks = Vec::with_capacity(3);
+        encode_publish(&mut chunks, subject, reply_to, headers, msg, trace);
+        let res = self.try_send_publish(chunks).await;
+        if let Some(Ok(())) = res {
+            self.outbound.add(len);
         }
+        res
     }
 
     /// Runs the loop that connects and reconnects the client.
@@ -725,34 +1108,52 @@ impl Client {
         let mut first_connect = true;
 
         loop {
-            //  Don't use backoff on first connect.
-            let use_backoff = !first_connect;
+            //  Don't use backoff on first connect, unless retrying it.
+            let use_backoff = !first_connect || self.options.retry_on_failed_connect;
 
             // Make a connection to the server.
             let (server_info, stream) = connector.connect(use_backoff).await?;
             self.process_info(&server_info, &connector).await;
 
-            let reader = BufReader::with_capacity(BUF_CAPACITY, stream.clone());
+            let reader = ServerOpReader::new(stream.clone());
             let writer = BufWriter::with_capacity(BUF_CAPACITY, stream);
 
             // Set up the new connection for this client.
             if self.reconnect(server_info, writer).await.is_ok() {
                 // Connected! Now dispatch MSG operations.
-                if !first_connect {
+                let server = connector.server().cloned();
+                if first_connect {
+                    if let Some(server) = server {
+                        self.events.emit(ConnectionEvent::Connected { server });
+                    }
+                } else {
+                    self.reconnects.send_modify(|reconnects| *reconnects += 1);
+                    if let Some(server) = server {
+                        let reconnects = *self.reconnects.borrow();
+                        self.events
+                            .emit(ConnectionEvent::Reconnected { server, reconnects });
+                    }
                     connector.get_options().reconnect_callback.call().await;
                 }
-                if self.dispatch(reader, &mut connector).await.is_ok() {
+                match self.dispatch(reader, &mut connector).await {
                     // If the client stopped gracefully, return.
-                    return Ok(());
-                } else {
-                    connector.get_options().disconnect_callback.call().await;
-                    self.state.write.lock().await.writer = None;
+                    Ok(()) => return Ok(()),
+                    Err(err) => {
+                        self.events.emit(ConnectionEvent::Disconnected {
+                            server: connector.server().cloned(),
+                            error: Some(Arc::new(err)),
+                        });
+                        connector.get_options().disconnect_callback.call().await;
+                        self.send(Command::Disconnect).await.ok();
+                    }
                 }
             }
 
-            // Clear our pings_out.
+            // Clear our pings_out, and wake up pending flushes as their PONGs
+            // will never arrive.
             let mut read = self.state.read.lock().await;
             read.pings_out = 0;
+            read.pongs.clear();
             drop(read);
 
             // Inject random delays when testing.
@@ -770,7 +1171,7 @@ impl Client {
     async fn reconnect(
         &self,
         server_info: ServerInfo,
-        mut writer: BufWriter<NatsStream>,
+        writer: BufWriter<NatsStream>,
     ) -> io::Result<()> {
         // Inject random delays when testing.
         inject_delay().await;
@@ -778,20 +1179,17 @@ impl Client {
         // Check if the client is closed.
         self.check_shutdown()?;
 
-        let mut write = self.state.write.lock().await;
         let mut read = self.state.read.lock().await;
 
-        // Drop the current writer, if there is one.
-        write.writer = None;
-
         // Inject random I/O failures when testing.
         inject_io_failure()?;
 
         // Restart subscriptions that existed before the last reconnect.
+        let mut preamble = Vec::new();
         for (sid, subscription) in &read.subscriptions {
             // Send a SUB operation to the server.
             proto::encode(
-                &mut writer,
+                &mut preamble,
                 ClientOp::Sub {
                     subject: subscription.subject.as_str(),
                     queue_group: subscription.queue_group.as_deref(),
@@ -799,37 +1197,56 @@ impl Client {
                 },
             )
             .await?;
+
+            // The new server has not delivered anything yet, so only ask it for
+            // the remaining messages.
+            if let Some(remaining) = subscription.remaining() {
+                proto::encode(
+                    &mut preamble,
+                    ClientOp::Unsub {
+                        sid: *sid,
+                        max_msgs: Some(remaining),
+                    },
+                )
+                .await?;
+            }
         }
 
         // Take out expected PONGs.
         let pongs = mem::take(&mut read.pongs);
 
-        // Take out buffered operations.
-        let buffered = write.buffer.clear();
-
-        // Write buffered PUB operations into the new writer.
-        writer.write_all(buffered).await?;
-        writer.flush().await?;
-
-        // All good, continue with this connection.
+        let headers = server_info.headers;
         *self.server_info.lock().await = server_info;
-        write.writer = Some(writer);
+
+        // Hand the new connection to the writer task, which writes the
+        // subscriptions and then buffered PUB operations into it.
+        let (ack, connected) = oneshot::channel();
+        self.send(Command::Connect {
+            writer,
+            preamble,
+            headers,
+            ack,
+        })
+        .await?;
+        drop(read);
+        connected
+            .await
+            .unwrap_or_else(|_| Err(crate::Error::ConnectionClosed.into()))?;
 
         // Complete PONGs because the connection is healthy.
         for p in pongs {
             p.try_send(()).ok();
         }
 
-        // NB see locking protocol for state.write and state.read
-        drop(read);
-        drop(write);
-
         Ok(())
     }
 
     // processes action need to be performed based on retrieved server info.
     async fn process_info(&self, server_info: &ServerInfo, connector: &Connector) {
         if server_info.lame_duck_mode {
+            if let Some(server) = connector.server().cloned() {
+                self.events.emit(ConnectionEvent::LameDuck { server });
+            }
             connector.get_options().lame_duck_callback.call().await;
         }
     }
@@ -843,11 +1260,29 @@ impl Client {
     /// Reads messages from the server and dispatches them to subscribers.
     async fn dispatch(
         &self,
-        mut reader: impl tokio::io::AsyncBufRead + std::marker::Unpin,
+        mut reader: ServerOpReader<NatsStream>,
         connector: &mut Connector,
     ) -> io::Result<()> {
         // Handle operations received from the server.
-        while let Some(op) = proto::decode(&mut reader).await? {
+        loop {
+            let op = tokio::select! {
+                op = reader.next() => match op? {
+                    Some(op) => op,
+                    None => break,
+                },
+                _ = self.state.stale.notified() => {
+                    return Err(crate::Error::StaleConnection.into());
+                }
+                // Only a failure of the current connection counts, a stale
+                // notification is ignored once reconnected.
+                _ = self.state.status.failed.notified() => {
+                    if !self.state.status.connected.load(Ordering::Acquire) {
+                        return Err(crate::Error::ConnectionClosed.into());
+                    }
+                    continue;
+                }
+            };
+
             // Inject random delays when testing.
             inject_delay().await;
 
@@ -860,49 +1295,33 @@ impl Client {
 
             match op {
                 ServerOp::Info(server_info) => {
-                    for url in &server_info.connect_urls {
-                        connector.add_server(url.parse()?);
-                    }
+                    connector.update_servers(&server_info.connect_urls);
                     self.process_info(&server_info, connector).await;
                     *self.server_info.lock().await = server_info;
                 }
 
                 ServerOp::Ping => {
-                    // Respond with a PONG if connected.
-                    let mut write = self.state.write.lock().await;
-                    let read = self.state.read.lock().await;
-
-                    if let Some(w) = write.writer.as_mut() {
-                        proto::encode(w, ClientOp::Pong).await?;
-                        write.flush_kicker.try_send(()).ok();
-                    }
-
-                    // NB see locking protocol for state.write and state.read
-                    drop(read);
-                    drop(write);
+                    // Respond with a PONG, dropped by the writer task if not
+                    // connected.
+                    self.send_op(ClientOp::Pong).await?;
                 }
 
                 ServerOp::Pong => {
                     // If a PONG is received while disconnected, it came from a
                     // connection that isn't alive anymore and therefore doesn't
                     // correspond to the next expected PONG.
-                    let write = self.state.write.lock().await;
                     let mut read = self.state.read.lock().await;
 
                     // Clear any outstanding pings.
                     read.pings_out = 0;
 
-                    if write.writer.is_some() {
+                    if self.state.status.connected.load(Ordering::Acquire) {
                         // Take the next expected PONG and complete it by
                         // sending a message.
                         if let Some(pong) = read.pongs.pop_front() {
                             pong.try_send(()).ok();
                         }
                     }
-
-                    // NB see locking protocol for state.write and state.read
-                    drop(read);
-                    drop(write);
                 }
 
                 ServerOp::Msg {
@@ -911,15 +1330,18 @@ impl Client {
                     reply_to,
                     payload,
                 } => {
+                    self.inbound.add(payload.len());
+
                     // Ignore muted subscriptions
                     if self.state.meta.lock().await.mutes.get(&sid).is_some() {
                         continue;
                     }
 
-                    let read = self.state.read.lock().await;
+                    let mut read = self.state.read.lock().await;
 
                     // Send the message to matching subscription.
                     if let Some(subscription) = read.subscriptions.get(&sid) {
+                        let last = subscription.count_received();
                         let msg = Message {
                             subject,
                             reply: reply_to,
@@ -932,12 +1354,32 @@ impl Client {
                         // Preprocess and drop the message from the buffer if it the predicate
                         // returns true
                         if (&subscription.preprocess).process(sid, &msg).await {
+                            if last {
+                                read.subscriptions.remove(&sid);
+                            }
                             continue;
                         }
 
-                        // Send a message or drop it if the channel is
-                        // disconnected or full.
-                        subscription.messages.send(msg).await.unwrap();
+                        // Queue the message, or drop it if the subscriber is
+                        // too slow or gone.
+                        let delivery = subscription.deliver(msg).await;
+                        let slow = subscription.record(sid, &delivery);
+
+                        // The server unsubscribed after the last message, closing
+                        // the subscription ends it once the queued messages are read.
+                        if last {
+                            read.subscriptions.remove(&sid);
+                        }
+
+                        if let Some(err) = slow {
+                            drop(read);
+                            let si = self.server_info().await;
+                            connector
+                                .get_options()
+                                .error_callback
+                                .call(si, err.into())
+                                .await;
+                        }
                     }
                 }
 
@@ -948,14 +1390,17 @@ impl Client {
                     reply_to,
                     payload,
                 } => {
+                    self.inbound.add(payload.len());
+
                     // Ignore muted subscriptions
                     if self.state.meta.lock().await.mutes.get(&sid).is_some() {
                         continue;
                     }
 
-                    let read = self.state.read.lock().await;
+                    let mut read = self.state.read.lock().await;
                     // Send the message to matching subscription.
                     if let Some(subscription) = read.subscriptions.get(&sid) {
+                        let last = subscription.count_received();
                         let msg = Message {
                             subject,
                             reply: reply_to,
@@ -968,12 +1413,32 @@ impl Client {
                         // Preprocess and drop the message from the buffer if it the predicate
                         // returns true
                         if (subscription.preprocess).process(sid, &msg).await {
+                            if last {
+                                read.subscriptions.remove(&sid);
+                            }
                             continue;
                         }
 
-                        // Send a message or drop it if the channel is
-                        // disconnected or full.
-                        subscription.messages.send(msg).await.unwrap();
+                        // Queue the message, or drop it if the subscriber is
+                        // too slow or gone.
+                        let delivery = subscription.deliver(msg).await;
+                        let slow = subscription.record(sid, &delivery);
+
+                        // The server unsubscribed after the last message, closing
+                        // the subscription ends it once the queued messages are read.
+                        if last {
+                            read.subscriptions.remove(&sid);
+                        }
+
+                        if let Some(err) = slow {
+                            drop(read);
+                            let si = self.server_info().await;
+                            connector
+                                .get_options()
+                                .error_callback
+                                .call(si, err.into())
+                                .await;
+                        }
                     }
                 }
 
@@ -982,7 +1447,7 @@ impl Client {
                     connector
                         .get_options()
                         .error_callback
-                        .call(si, Error::new(ErrorKind::Other, msg))
+                        .call(si, crate::Error::from_server(msg).into())
                         .await;
                 }
 
@@ -992,7 +1457,7 @@ impl Client {
             }
         }
         // The stream of operation is broken, meaning the connection was lost.
-        Err(ErrorKind::ConnectionReset.into())
+        Err(crate::Error::ConnectionClosed.into())
     }
 }
 
@@ -1002,6 +1467,207 @@ impl fmt::Debug for Client {
     }
 }
 
+/// The writer task, which owns the write half of the connection.
+///
+/// Operations are encoded by the tasks issuing them and queued for the writer,
+/// which writes everything already queued before flushing once.
+struct Writer {
+    /// Buffered writer with an active connection.
+    ///
+    /// When `None`, the client is either reconnecting or closed.
+    writer: Option<BufWriter<NatsStream>>,
+
+    /// The reconnect buffer.
+    ///
+    /// When the client is reconnecting, PUB messages get buffered here. When
+    /// the connection is re-established, contents of the buffer are
+    /// flushed to the server.
+    buffer: Buffer,
+
+    /// Status shared with the client.
+    status: Arc<WriterStatus>,
+
+    /// Flushes to acknowledge after the next flush of the writer.
+    flushes: Vec<oneshot::Sender<()>>,
+}
+
+impl Writer {
+    /// Handles queued operations until the client is dropped.
+    async fn run(mut self, mut commands: tokio::sync::mpsc::Receiver<Command>) {
+        while let Some(command) = commands.recv().await {
+            self.handle(command).await;
+
+            // Coalesce operations that are already queued into one flush.
+            for _ in 1..WRITE_QUEUE_CAPACITY {
+                match commands.try_recv() {
+                    Ok(command) => self.handle(command).await,
+                    Err(_) => break,
+                }
+            }
+            self.flush().await;
+        }
+
+        // One final flush before shutting down.
+        if let Some(writer) = self.writer.as_mut() {
+            writer.shutdown().await.ok();
+        }
+    }
+
+    async fn handle(&mut self, command: Command) {
+        match command {
+            Command::Publish { chunks, ack } => {
+                // If reconnecting, or if writing fails, write into the buffer.
+                let mut res = Err(crate::Error::ConnectionClosed.into());
+                if self.writer.is_some() {
+                    res = self.write_chunks(&chunks).await;
+                }
+                if res.is_err() {
+                    res = self.store(&chunks).await;
+                }
+                match ack {
+                    Some(ack) => {
+                        ack.send(res).ok();
+                    }
+                    None => {
+                        if let Err(err) = res {
+                            error!("dropped a published message: {}", err);
+                        }
+                    }
+                }
+            }
+            Command::Control(bytes) => {
+                if self.writer.is_some() {
+                    self.write(&bytes).await.ok();
+                }
+            }
+            Command::Flush(ack) => self.flushes.push(ack),
+            Command::Connect {
+                writer,
+                preamble,
+                headers,
+                ack,
+            } => {
+                ack.send(self.connect(writer, &preamble, headers).await)
+                    .ok();
+            }
+            Command::Disconnect => {
+                if let Some(mut writer) = self.writer.take() {
+                    self.status.connected.store(false, Ordering::Release);
+                    writer.get_mut().shutdown().await;
+                }
+            }
+        }
+    }
+
+    /// Writes encoded operations into the writer.
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_chunks(&[bytes]).await
+    }
+
+    /// Writes encoded operations, split into chunks, into the writer.
+    ///
+    /// Chunks at least as large as the write buffer bypass it.
+    async fn write_chunks(&mut self, chunks: &[impl AsRef<[u8]>]) -> io::Result<()> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return Err(crate::Error::ConnectionClosed.into()),
+        };
+
+        // If writing fails, disconnect.
+        for chunk in chunks {
+            if let Err(err) = writer.write_all(chunk.as_ref()).await {
+                self.failed();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores encoded PUB operations in the reconnect buffer.
+    async fn store(&mut self, chunks: &[Bytes]) -> io::Result<()> {
+        if chunks.iter().any(|chunk| chunk.starts_with(b"HPUB ")) {
+            self.buffer.headers = true;
+        }
+        for chunk in chunks {
+            self.buffer.write_all(chunk).await?;
+        }
+        self.buffer.flush().await?;
+        self.status
+            .buffered
+            .store(self.buffer.flushed, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Continues with a new connection, after writing `preamble` and the
+    /// contents of the reconnect buffer into it.
+    ///
+    /// Buffered HPUB operations are dropped if the server does not support
+    /// `headers`. They can only be buffered before the first connect, when
+    /// the server was not known yet.
+    async fn connect(
+        &mut self,
+        mut writer: BufWriter<NatsStream>,
+        preamble: &[u8],
+        headers: bool,
+    ) -> io::Result<()> {
+        // Drop the current writer, if there is one.
+        self.disconnected();
+
+        writer.write_all(preamble).await?;
+
+        // Take out buffered operations.
+        let has_headers = mem::take(&mut self.buffer.headers);
+        let buffered = self.buffer.clear();
+        self.status.buffered.store(0, Ordering::Relaxed);
+
+        // Write buffered PUB operations into the new writer.
+        if has_headers && !headers {
+            let (buffered, dropped) = without_hpub(buffered);
+            if dropped > 0 {
+                error!(
+                    "dropped {} published messages: the server does not support headers",
+                    dropped
+                );
+            }
+            writer.write_all(&buffered).await?;
+        } else {
+            writer.write_all(buffered).await?;
+        }
+        writer.flush().await?;
+
+        // All good, continue with this connection.
+        self.writer = Some(writer);
+        self.status.connected.store(true, Ordering::Release);
+        self.status.has_connected.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Flushes the writer and acknowledges pending flushes.
+    async fn flush(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            // If flushing fails, disconnect.
+            if writer.flush().await.is_err() {
+                self.failed();
+            }
+        }
+        for ack in self.flushes.drain(..) {
+            ack.send(()).ok();
+        }
+    }
+
+    /// Drops the writer.
+    fn disconnected(&mut self) {
+        self.writer = None;
+        self.status.connected.store(false, Ordering::Release);
+    }
+
+    /// Drops the writer after the connection failed, and has the client reconnect.
+    fn failed(&mut self) {
+        self.disconnected();
+        self.status.failed.notify_one();
+    }
+}
+
 /// Reconnect buffer.
 ///
 /// If the connection was broken and the client is currently reconnecting, PUB
@@ -1023,6 +1689,9 @@ struct Buffer {
 
     /// Number of bytes marked as "flushed".
     flushed: usize,
+
+    /// Set if the buffer may contain HPUB messages.
+    headers: bool,
 }
 
 impl Buffer {
@@ -1032,6 +1701,7 @@ impl Buffer {
             bytes: vec![0_u8; size].into_boxed_slice(),
             written: 0,
             flushed: 0,
+            headers: false,
         }
     }
 
@@ -12,9 +12,10 @@
 // limitations under the License.
 
 use async_trait::async_trait;
+use bytes::BytesMut;
 use std::borrow::Borrow;
-use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt;
 use std::io::{self, Error, ErrorKind};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::ops::DerefMut;
@@ -22,18 +23,20 @@ use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::sync::Mutex;
 use url::Url;
 
 //use tokio_rustls::webpki::DnsNameRef;
 
 use crate::auth_utils;
-use crate::proto::{self, ClientOp, ServerOp};
+use crate::events::{ConnectionEvent, Events};
+use crate::proto::{self, ClientOp, Decoder, ServerOp, ServerOpReader};
+use crate::runtime;
 use crate::rustls::{ClientConfig, /* ClientConnection, */ ServerName};
 use crate::secure_wipe::SecureString;
 use crate::tokio_rustls::client::TlsStream;
+use crate::websocket::WebSocket;
 use crate::{connect::ConnectInfo, inject_io_failure, AuthStyle, Options, ServerInfo};
 
 /// Maintains a list of servers and establishes connections.
@@ -42,14 +45,46 @@ use crate::{connect::ConnectInfo, inject_io_failure, AuthStyle, Options, ServerI
 /// INFO messages, reconnect when the connection is lost, and do exponential
 /// backoff after failed connect attempts.
 pub(crate) struct Connector {
-    /// A map of servers and number of connect attempts.
-    attempts: HashMap<ServerAddress, usize>,
+    /// The servers to connect to, in the order they are tried without
+    /// randomization.
+    pool: Vec<Server>,
+
+    /// A snapshot of the server addresses in `pool`.
+    servers: Arc<tokio::sync::watch::Sender<Vec<ServerAddress>>>,
 
     /// Configured options for establishing connections.
     options: Arc<Options>,
 
     /// TLS config.
     tls_config: Arc<ClientConfig>,
+
+    /// The server of the last successful connection.
+    server: Option<ServerAddress>,
+
+    /// Connection lifecycle events.
+    events: Arc<Events>,
+}
+
+/// A server in the pool of a `Connector`.
+struct Server {
+    address: ServerAddress,
+
+    /// Number of connect attempts since the last successful connection.
+    reconnects: usize,
+
+    /// Set if the server was learned from an INFO message rather than
+    /// configured.
+    discovered: bool,
+}
+
+impl Server {
+    fn new(address: ServerAddress, discovered: bool) -> Server {
+        Server {
+            address,
+            reconnects: 0,
+            discovered,
+        }
+    }
 }
 
 /// load tls certs. This function uses blocking file io.
@@ -85,10 +120,10 @@ fn load_tls_certs(tls_options: &Arc<Options>) -> io::Result<ClientConfig> {
             tls_config
                 .with_single_cert(auth_utils::load_certs(cert)?, auth_utils::load_key(key)?)
                 .map_err(|err| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        format!("invalid client certificate and key pair: {}", err),
-                    )
+                    io::Error::from(crate::Error::validation(format!(
+                        "invalid client certificate and key pair: {}",
+                        err
+                    )))
                 })?
         } else {
             tls_config.with_no_client_auth()
@@ -101,42 +136,124 @@ impl Connector {
     pub(crate) async fn new(
         urls: Vec<ServerAddress>,
         options: Arc<Options>,
+        events: Arc<Events>,
+        servers: Arc<tokio::sync::watch::Sender<Vec<ServerAddress>>>,
     ) -> io::Result<Connector> {
         let tls_options = options.clone();
-        let tls_config =
-            tokio::task::spawn_blocking(move || load_tls_certs(&tls_options)).await??;
+        let tls_config = runtime::spawn_blocking(move || load_tls_certs(&tls_options)).await??;
 
-        let connector = Connector {
-            attempts: urls.into_iter().map(|url| (url, 0)).collect(),
+        let mut connector = Connector {
+            pool: Vec::new(),
+            servers,
             options,
             tls_config: Arc::new(tls_config),
+            server: None,
+            events,
         };
+        for url in urls {
+            connector.add_server(url, false);
+        }
+        connector.publish_servers();
         Ok(connector)
     }
 
-    /// Adds a URL to the list of servers.
-    pub(crate) fn add_server(&mut self, url: ServerAddress) {
-        self.attempts.insert(url, 0);
+    /// Adds a URL to the pool of servers, unless it is already known.
+    fn add_server(&mut self, url: ServerAddress, discovered: bool) {
+        if !self.pool.iter().any(|server| server.address == url) {
+            self.pool.push(Server::new(url, discovered));
+        }
+    }
+
+    /// Updates the pool with the URLs of an INFO message. New servers are
+    /// added, and discovered servers the cluster no longer reports are pruned.
+    ///
+    /// Discovered URLs without a scheme are reached the same way as the
+    /// current server, and URLs that fail to parse are skipped.
+    pub(crate) fn update_servers(&mut self, connect_urls: &[String]) {
+        // Some INFO messages, like lame duck notifications, carry no URLs.
+        if self.options.ignore_discovered_servers || connect_urls.is_empty() {
+            return;
+        }
+
+        let scheme = self
+            .server
+            .as_ref()
+            .map_or("nats", |server| server.0.scheme());
+        let urls: Vec<ServerAddress> = connect_urls
+            .iter()
+            .filter_map(|url| {
+                let url = if url.contains("://") {
+                    url.clone()
+                } else {
+                    format!("{}://{}", scheme, url)
+                };
+                match url.parse() {
+                    Ok(address) => Some(address),
+                    Err(err) => {
+                        log::warn!("ignoring discovered server {}: {}", url, err);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let current = self.server.as_ref();
+        self.pool.retain(|server| {
+            !server.discovered || Some(&server.address) == current || urls.contains(&server.address)
+        });
+        for url in urls {
+            self.add_server(url, true);
+        }
+        self.publish_servers();
+    }
+
+    /// Publishes the addresses of the pool for `Connection::servers`.
+    fn publish_servers(&self) {
+        let servers = self
+            .pool
+            .iter()
+            .map(|server| server.address.clone())
+            .collect();
+        self.servers.send_replace(servers);
     }
 
     pub(crate) fn get_options(&self) -> Arc<Options> {
         self.options.clone()
     }
 
-    /// Get the list of servers with enough reconnection attempts left
+    /// Returns the server of the last successful connection.
+    pub(crate) fn server(&self) -> Option<&ServerAddress> {
+        self.server.as_ref()
+    }
+
+    /// Get the list of servers to try in order, after pruning servers that
+    /// have no reconnection attempts left.
     fn get_servers(&mut self) -> io::Result<Vec<ServerAddress>> {
-        let servers: Vec<_> = self
-            .attempts
+        if let Some(max) = self.options.max_reconnects {
+            let before = self.pool.len();
+            self.pool.retain(|server| server.reconnects < max);
+            if self.pool.len() != before {
+                self.publish_servers();
+            }
+        }
+
+        let mut servers: Vec<_> = self
+            .pool
             .iter()
-            .filter_map(
-                |(server, reconnects)| match self.options.max_reconnects.as_ref() {
-                    None => Some(server),
-                    Some(max) if reconnects < max => Some(server),
-                    Some(_) => None,
-                },
-            )
-            .cloned()
+            .map(|server| server.address.clone())
             .collect();
+        if !self.options.no_randomize {
+            fastrand::shuffle(&mut servers);
+        }
+
+        // Try the server that was just lost last, so that a cluster going
+        // through a rolling restart does not see every client hit the same node.
+        if let Some(last) = self.server.as_ref() {
+            if let Some(pos) = servers.iter().position(|server| server == last) {
+                let last = servers.remove(pos);
+                servers.push(last);
+            }
+        }
 
         if servers.is_empty() {
             Err(Error::new(
@@ -161,29 +278,43 @@ impl Connector {
         let mut last_err = Error::new(ErrorKind::AddrNotAvailable, "no socket addresses");
 
         loop {
-            // Shuffle the list of servers.
-            let mut servers = self.get_servers()?;
-            fastrand::shuffle(&mut servers);
+            let servers = self.get_servers()?;
 
-            // Iterate over the server list in random order.
+            // Iterate over the server list, in random order unless disabled.
             for server in &servers {
                 // Calculate sleep duration for exponential backoff and bump the
                 // reconnect counter.
-                let reconnects = self.attempts.get_mut(server).unwrap();
-                let sleep_duration = self
-                    .options
-                    .reconnect_delay_callback
-                    .call(*reconnects)
-                    .await;
+                let entry = self.pool.iter_mut().find(|s| &s.address == server);
+                let reconnects = &mut entry.unwrap().reconnects;
+                let attempt = *reconnects;
                 *reconnects += 1;
+                let sleep_duration = self.options.reconnect_delay_callback.call(attempt).await;
 
-                let lookup_res = server.socket_addrs();
+                if use_backoff {
+                    self.events.emit(ConnectionEvent::Reconnecting {
+                        server: server.clone(),
+                        attempt: attempt + 1,
+                    });
+                }
 
-                let mut addrs = match lookup_res {
-                    Ok(addrs) => addrs.collect::<Vec<_>>(),
-                    Err(err) => {
-                        last_err = err;
-                        continue;
+                // A custom dialer resolves the server address itself.
+                let mut addrs = if self.options.dialer.is_some() {
+                    vec![None]
+                } else {
+                    // The system resolver blocks, so run it on the blocking pool.
+                    let resolving = server.clone();
+                    let resolved = runtime::spawn_blocking(move || {
+                        resolving
+                            .socket_addrs()
+                            .map(|addrs| addrs.map(Some).collect::<Vec<_>>())
+                    })
+                    .await;
+                    match resolved.and_then(|addrs| addrs) {
+                        Ok(addrs) => addrs,
+                        Err(err) => {
+                            last_err = err;
+                            continue;
+                        }
                     }
                 };
 
@@ -194,11 +325,17 @@ impl Connector {
                     // Sleep for some time if this is not the first connection
                     // attempt for this server.
                     if let Some(sleep_duration) = sleep_duration {
-                        tokio::time::sleep(sleep_duration).await;
+                        runtime::sleep(sleep_duration).await;
                     }
 
-                    // Try connecting to this address.
-                    let res = self.connect_addr(addr, server).await;
+                    // Try connecting to this address, giving up after the
+                    // connect timeout.
+                    let res = runtime::timeout(
+                        self.options.connect_timeout,
+                        self.connect_addr(addr, server),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err(crate::Error::TimedOut.into()));
 
                     // Check if connecting worked out.
                     let (server_info, stream) = match res {
@@ -209,12 +346,13 @@ impl Connector {
                         }
                     };
 
-                    // Add URLs discovered through the INFO message.
-                    for url in &server_info.connect_urls {
-                        self.add_server(url.parse()?);
+                    if let Some(entry) = self.pool.iter_mut().find(|s| &s.address == server) {
+                        entry.reconnects = 0;
                     }
+                    self.server = Some(server.clone());
 
-                    *self.attempts.get_mut(server).unwrap() = 0;
+                    // Add URLs discovered through the INFO message.
+                    self.update_servers(&server_info.connect_urls);
                     return Ok((server_info, stream));
                 }
             }
@@ -226,19 +364,12 @@ impl Connector {
         }
     }
 
-    /// Attempts to establish a connection to a single socket address.
-    async fn connect_addr(
+    /// Reads the INFO message of a plain connection and upgrades it to TLS if required.
+    async fn connect_plain(
         &self,
-        addr: SocketAddr,
+        mut stream: Box<dyn Transport>,
         server: &ServerAddress,
-    ) -> io::Result<(ServerInfo, NatsStream)> {
-        // Inject random I/O failures when testing.
-        inject_io_failure()?;
-
-        // Connect to the remote socket.
-        let mut stream = TcpStream::connect(addr).await?;
-        stream.set_nodelay(true)?;
-
+    ) -> io::Result<(ServerInfo, NatsStream, bool)> {
         // Expect an INFO message.
         let mut line = crate::SecureVec::with_capacity(1024);
         while !line.ends_with(b"\r\n") {
@@ -246,18 +377,7 @@ impl Connector {
             stream.read_exact(byte).await?;
             line.push(byte[0]);
         }
-        let server_info = match proto::decode(&line[..]).await? {
-            Some(ServerOp::Info(server_info)) => server_info,
-            Some(op) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("expected INFO, received: {:?}", op),
-                ));
-            }
-            None => {
-                return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed"));
-            }
-        };
+        let server_info = read_info(&line)?;
 
         // Check if TLS authentication is required:
         // - Has `self.options.tls_required(true)` been set?
@@ -267,27 +387,99 @@ impl Connector {
             self.options.tls_required || server.tls_required() || server_info.tls_required;
 
         // Upgrade to TLS if required.
-        let mut stream = if tls_required {
+        let stream = if tls_required {
             // Inject random I/O failures when testing.
             inject_io_failure()?;
 
-            // Connect using TLS.
-            let dns_name = ServerName::try_from(server_info.host.as_str())
-                .or_else(|_| ServerName::try_from(server.host()))
-                .map_err(|_| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "cannot determine hostname for TLS connection",
-                    )
-                })?;
-            NatsStream::new_tls(
-                tokio_rustls::TlsConnector::from(self.tls_config.clone())
-                    .connect(dns_name, stream)
-                    .await
-                    .map_err(|e| io::Error::new(io::ErrorKind::NotConnected, e))?,
+            NatsStream::new_tls(self.tls_connect(stream, &server_info, server).await?)
+        } else {
+            NatsStream::new_plain(stream)
+        };
+
+        Ok((server_info, stream, tls_required))
+    }
+
+    /// Performs the websocket handshake, over TLS for `wss://` servers, and reads
+    /// the INFO message.
+    async fn connect_websocket(
+        &self,
+        stream: Box<dyn Transport>,
+        server: &ServerAddress,
+    ) -> io::Result<(ServerInfo, NatsStream, bool)> {
+        // The websocket listener decides about TLS before any NATS protocol is spoken,
+        // so the INFO message cannot ask for it.
+        let tls_required = self.options.tls_required || server.tls_required();
+        let transport: Box<dyn Transport> = if tls_required {
+            // Inject random I/O failures when testing.
+            inject_io_failure()?;
+
+            Box::new(
+                self.tls_connect(stream, &ServerInfo::default(), server)
+                    .await?,
             )
         } else {
-            NatsStream::new_tcp(stream)
+            Box::new(stream)
+        };
+        let mut stream = NatsStream::new_websocket(WebSocket::connect(server, transport).await?);
+
+        // Expect an INFO message.
+        let mut line = crate::SecureVec::with_capacity(1024);
+        while !line.ends_with(b"\r\n") {
+            let byte = &mut [0];
+            stream.read_exact(byte).await?;
+            line.push(byte[0]);
+        }
+        let server_info = read_info(&line)?;
+
+        Ok((server_info, stream, tls_required))
+    }
+
+    /// Starts a TLS session on the stream.
+    async fn tls_connect(
+        &self,
+        stream: Box<dyn Transport>,
+        server_info: &ServerInfo,
+        server: &ServerAddress,
+    ) -> io::Result<TlsStream<Box<dyn Transport>>> {
+        let dns_name = ServerName::try_from(server_info.host.as_str())
+            .or_else(|_| ServerName::try_from(server.host()))
+            .map_err(|_| {
+                io::Error::from(crate::Error::validation(
+                    "cannot determine hostname for TLS connection",
+                ))
+            })?;
+        tokio_rustls::TlsConnector::from(self.tls_config.clone())
+            .connect(dns_name, stream)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::NotConnected, e))
+    }
+
+    /// Attempts to establish a connection to a single socket address, or through
+    /// the custom dialer if there is no address.
+    async fn connect_addr(
+        &self,
+        addr: Option<SocketAddr>,
+        server: &ServerAddress,
+    ) -> io::Result<(ServerInfo, NatsStream)> {
+        // Inject random I/O failures when testing.
+        inject_io_failure()?;
+
+        // Connect to the remote socket.
+        let stream: Box<dyn Transport> = match (addr, self.options.dialer.as_ref()) {
+            (Some(addr), _) => runtime::connect_tcp(addr, &self.options).await?,
+            (None, Some(dialer)) => dialer.dial(server).await?,
+            (None, None) => {
+                return Err(Error::new(
+                    ErrorKind::AddrNotAvailable,
+                    "no socket addresses",
+                ))
+            }
+        };
+
+        let (server_info, mut stream, tls_required) = if server.is_websocket() {
+            self.connect_websocket(stream, server).await?
+        } else {
+            self.connect_plain(stream, server).await?
         };
 
         // Data that will be formatted as a CONNECT message.
@@ -345,11 +537,11 @@ impl Connector {
         proto::encode(&mut stream, ClientOp::Ping).await?;
         stream.flush().await?;
 
-        let mut reader = BufReader::new(stream.clone());
+        let mut reader = ServerOpReader::new(stream.clone());
 
         // Wait for a PONG.
         loop {
-            match proto::decode(&mut reader).await? {
+            match reader.next().await? {
                 // If we get PONG, the server is happy and we're done
                 // connecting.
                 Some(ServerOp::Pong) => break,
@@ -382,46 +574,83 @@ impl Connector {
     }
 }
 
+/// Parses the INFO message a server sends when a connection is established.
+fn read_info(line: &[u8]) -> io::Result<ServerInfo> {
+    match Decoder::default().decode(&mut BytesMut::from(line))? {
+        Some(ServerOp::Info(server_info)) => Ok(server_info),
+        Some(op) => Err(Error::new(
+            ErrorKind::Other,
+            format!("expected INFO, received: {:?}", op),
+        )),
+        None => Err(Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+    }
+}
+
+/// A byte stream that the NATS protocol can run on, as returned by a [`Dialer`](crate::Dialer).
+///
+/// Implemented for all types that are `AsyncRead + AsyncWrite + Unpin + Send`, such as
+/// `tokio::net::TcpStream`, `tokio::net::UnixStream` or `tokio::io::DuplexStream`.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
 /// A raw NATS stream of bytes.
 ///
-/// The stream uses the TCP protocol, optionally secured by TLS.
+/// The stream runs on a transport, by default TCP, optionally secured by TLS, or a websocket.
 #[derive(Debug, Clone)]
 pub(crate) struct NatsStream {
     flavor: Arc<Flavor>,
 }
 
-#[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 enum Flavor {
-    Tcp(Mutex<TcpStream>),
-    Tls(Mutex<TlsStream<TcpStream>>), //Tls(Box<Mutex<TlsStream<TcpStream>>>),
+    Plain(Mutex<Box<dyn Transport>>),
+    Tls(Mutex<TlsStream<Box<dyn Transport>>>),
+    WebSocket(Mutex<WebSocket>),
+}
+
+impl fmt::Debug for Flavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Flavor::Plain(_) => f.write_str("Plain"),
+            Flavor::Tls(_) => f.write_str("Tls"),
+            Flavor::WebSocket(_) => f.write_str("WebSocket"),
+        }
+    }
 }
 
 impl NatsStream {
-    fn new_tcp(tcp: TcpStream) -> Self {
-        tcp.set_nodelay(true).ok(); // ignore err if not supported
+    fn new_plain(stream: Box<dyn Transport>) -> Self {
         Self {
-            flavor: Arc::new(Flavor::Tcp(Mutex::new(tcp))),
+            flavor: Arc::new(Flavor::Plain(Mutex::new(stream))),
         }
     }
 
-    fn new_tls(tls: TlsStream<TcpStream>) -> Self {
+    fn new_tls(tls: TlsStream<Box<dyn Transport>>) -> Self {
         Self {
             flavor: Arc::new(Flavor::Tls(Mutex::new(tls))),
         }
     }
 
+    fn new_websocket(websocket: WebSocket) -> Self {
+        Self {
+            flavor: Arc::new(Flavor::WebSocket(Mutex::new(websocket))),
+        }
+    }
+
     /// Will attempt to shutdown the underlying stream.
     pub(crate) async fn shutdown(&mut self) {
         match Arc::<Flavor>::get_mut(&mut self.flavor) {
-            Some(Flavor::Tcp(tcp)) => {
-                let tcp = tcp.get_mut();
-                let _ = tcp.shutdown().await;
+            Some(Flavor::Plain(stream)) => {
+                let _ = stream.get_mut().shutdown().await;
             }
             Some(Flavor::Tls(tls)) => {
                 let tls = tls.get_mut();
                 let _ = tls.get_mut().0.shutdown().await;
             }
+            Some(Flavor::WebSocket(websocket)) => {
+                let _ = websocket.get_mut().shutdown().await;
+            }
             None => {
                 // more than one Arc holder: can't shut down yet
                 log::warn!("connection shutdown deferred");
@@ -435,8 +664,8 @@ macro_rules! impl_poll_flavors {
         fn $fname(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
             let flavor: &Flavor = self.flavor.borrow();
             match flavor {
-                Flavor::Tcp(tcp) => {
-                    if let Ok(mut guard) = tcp.try_lock() {
+                Flavor::Plain(stream) => {
+                    if let Ok(mut guard) = stream.try_lock() {
                         Pin::new(guard.deref_mut()).$fname(cx)
                     } else {
                         Poll::Pending
@@ -449,6 +678,13 @@ macro_rules! impl_poll_flavors {
                         Poll::Pending
                     }
                 }
+                Flavor::WebSocket(websocket) => {
+                    if let Ok(mut guard) = websocket.try_lock() {
+                        Pin::new(guard.deref_mut()).$fname(cx)
+                    } else {
+                        Poll::Pending
+                    }
+                }
             }
         }
     };
@@ -462,8 +698,8 @@ impl AsyncRead for NatsStream {
     ) -> Poll<io::Result<()>> {
         let flavor: &Flavor = self.flavor.borrow();
         match flavor {
-            Flavor::Tcp(tcp) => {
-                if let Ok(mut guard) = tcp.try_lock() {
+            Flavor::Plain(stream) => {
+                if let Ok(mut guard) = stream.try_lock() {
                     Pin::new(guard.deref_mut()).poll_read(cx, buf)
                 } else {
                     Poll::Pending
@@ -476,6 +712,13 @@ impl AsyncRead for NatsStream {
                     Poll::Pending
                 }
             }
+            Flavor::WebSocket(websocket) => {
+                if let Ok(mut guard) = websocket.try_lock() {
+                    Pin::new(guard.deref_mut()).poll_read(cx, buf)
+                } else {
+                    Poll::Pending
+                }
+            }
         }
     }
 }
@@ -489,8 +732,8 @@ impl AsyncWrite for NatsStream {
     ) -> Poll<io::Result<usize>> {
         let flavor: &Flavor = self.flavor.borrow();
         match flavor {
-            Flavor::Tcp(tcp) => {
-                if let Ok(mut guard) = tcp.try_lock() {
+            Flavor::Plain(stream) => {
+                if let Ok(mut guard) = stream.try_lock() {
                     Pin::new(guard.deref_mut()).poll_write(cx, buf)
                 } else {
                     Poll::Pending
@@ -503,6 +746,13 @@ impl AsyncWrite for NatsStream {
                     Poll::Pending
                 }
             }
+            Flavor::WebSocket(websocket) => {
+                if let Ok(mut guard) = websocket.try_lock() {
+                    Pin::new(guard.deref_mut()).poll_write(cx, buf)
+                } else {
+                    Poll::Pending
+                }
+            }
         }
     }
 
@@ -538,10 +788,10 @@ impl FromStr for ServerAddress {
             format!("nats://{}", input).parse()
         }
         .map_err(|e| {
-            Error::new(
-                ErrorKind::InvalidInput,
-                format!("NATS server URL is invalid: {}", e),
-            )
+            io::Error::from(crate::Error::validation(format!(
+                "NATS server URL is invalid: {}",
+                e
+            )))
         })?;
 
         Self::from_url(url)
@@ -551,11 +801,11 @@ impl FromStr for ServerAddress {
 impl ServerAddress {
     /// Check if the URL is a valid NATS server address.
     pub fn from_url(url: Url) -> io::Result<Self> {
-        if url.scheme() != "nats" && url.scheme() != "tls" {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("invalid scheme for NATS server URL: {}", url.scheme()),
-            ));
+        if !matches!(url.scheme(), "nats" | "tls" | "ws" | "wss") {
+            return Err(io::Error::from(crate::Error::validation(format!(
+                "invalid scheme for NATS server URL: {}",
+                url.scheme()
+            ))));
         }
 
         Ok(Self(url))
@@ -568,7 +818,12 @@ impl ServerAddress {
 
     /// Returns if tls is required by the client for this server.
     pub fn tls_required(&self) -> bool {
-        self.0.scheme() == "tls"
+        matches!(self.0.scheme(), "tls" | "wss")
+    }
+
+    /// Returns if the server is reached through its websocket listener.
+    pub fn is_websocket(&self) -> bool {
+        matches!(self.0.scheme(), "ws" | "wss")
     }
 
     /// Returns if the server url had embedded username and password.
@@ -581,9 +836,13 @@ impl ServerAddress {
         self.0.host_str().unwrap()
     }
 
-    /// Returns the port.
+    /// Returns the port. Defaults to 4222, or to 80 and 443 for websockets.
     pub fn port(&self) -> u16 {
-        self.0.port().unwrap_or(4222)
+        if self.is_websocket() {
+            self.0.port_or_known_default().unwrap_or(80)
+        } else {
+            self.0.port().unwrap_or(4222)
+        }
     }
 
     /// Returns the optional username in the url.
@@ -0,0 +1,223 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured errors.
+//!
+//! The public API returns `io::Result` for compatibility. Errors produced by this
+//! crate carry an [`Error`] inside the `io::Error`, which can be recovered with
+//! `Error::from(io_error)`.
+//!
+//! # Example
+//! ```
+//! # #[tokio::main]
+//! # async fn main() -> std::io::Result<()> {
+//! let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+//! match nc.request("nobody-home", "hello").await.map_err(nats_aflowt::Error::from) {
+//!     Err(nats_aflowt::Error::NoResponders) => println!("nobody is listening"),
+//!     Err(err) => println!("request failed: {}", err),
+//!     Ok(resp) => println!("received {}", resp),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{error, fmt, io};
+
+use crate::jetstream::{self, ErrorCode};
+
+/// An error returned by the NATS client.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The JetStream API responded with an error.
+    JetStream(Box<jetstream::Error>),
+
+    /// The server sent a `-ERR` protocol message.
+    Server(String),
+
+    /// The server rejected a publish or subscribe because of missing permissions.
+    PermissionViolation(String),
+
+    /// No response arrived in time.
+    TimedOut,
+
+    /// There are no subscribers listening on the request subject.
+    NoResponders,
+
+    /// The connection has been closed.
+    ConnectionClosed,
+
+    /// The server did not answer the client's PINGs, so the connection is
+    /// considered broken. Reported through the error callback before the client
+    /// reconnects.
+    StaleConnection,
+
+    /// An argument or configuration value was rejected before anything was sent.
+    Validation(String),
+
+    /// A subscriber did not keep up and messages were dropped. Reported through
+    /// the error callback when a subscription starts dropping messages.
+    SlowConsumer {
+        /// The subscription id.
+        sid: u64,
+        /// The subscribed subject.
+        subject: String,
+        /// Total number of messages dropped for this subscription.
+        dropped: u64,
+    },
+
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Creates an error for a `-ERR` message received from the server.
+    pub(crate) fn from_server(message: String) -> Error {
+        if message.to_lowercase().starts_with("permissions violation") {
+            Error::PermissionViolation(message)
+        } else {
+            Error::Server(message)
+        }
+    }
+
+    /// Creates a validation error.
+    pub(crate) fn validation(message: impl Into<String>) -> Error {
+        Error::Validation(message.into())
+    }
+
+    /// Returns the `io::ErrorKind` this error converts into.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::JetStream(_) | Error::Server(_) | Error::SlowConsumer { .. } => {
+                io::ErrorKind::Other
+            }
+            Error::PermissionViolation(_) => io::ErrorKind::PermissionDenied,
+            Error::TimedOut => io::ErrorKind::TimedOut,
+            Error::NoResponders => io::ErrorKind::NotFound,
+            Error::ConnectionClosed => io::ErrorKind::NotConnected,
+            Error::StaleConnection => io::ErrorKind::ConnectionAborted,
+            Error::Validation(_) => io::ErrorKind::InvalidInput,
+            Error::Io(err) => err.kind(),
+        }
+    }
+
+    /// Returns the JetStream error code, if this is a JetStream API error.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::JetStream(err) => Some(err.error_code()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::JetStream(err) => write!(f, "jetstream error: {}", err),
+            Error::Server(message) | Error::PermissionViolation(message) => {
+                write!(f, "{}", message)
+            }
+            Error::TimedOut => write!(f, "timed out"),
+            Error::NoResponders => write!(f, "no responders"),
+            Error::ConnectionClosed => write!(f, "the connection is closed"),
+            Error::StaleConnection => write!(f, "stale connection"),
+            Error::Validation(message) => write!(f, "{}", message),
+            Error::SlowConsumer {
+                subject, dropped, ..
+            } => write!(
+                f,
+                "slow consumer on subject {}: {} messages dropped",
+                subject, dropped
+            ),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::JetStream(err) => Some(err.as_ref()),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<jetstream::Error> for Error {
+    fn from(err: jetstream::Error) -> Error {
+        Error::JetStream(Box::new(err))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        let is_typed = matches!(
+            err.get_ref(),
+            Some(inner) if inner.is::<Error>() || inner.is::<jetstream::Error>()
+        );
+        if !is_typed {
+            return Error::Io(err);
+        }
+
+        let inner = err.into_inner().expect("checked above");
+        match inner.downcast::<Error>() {
+            Ok(err) => *err,
+            Err(inner) => match inner.downcast::<jetstream::Error>() {
+                Ok(err) => Error::JetStream(err),
+                Err(_) => unreachable!("checked above"),
+            },
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = err.kind();
+        match err {
+            // JetStream errors keep `jetstream::Error` as the inner error so that
+            // downcasting the `io::Error` keeps working.
+            Error::JetStream(err) => io::Error::new(kind, *err),
+            Error::Io(err) => err,
+            err => io::Error::new(kind, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_roundtrip() {
+        let err: io::Error = Error::NoResponders.into();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "no responders");
+        assert!(matches!(Error::from(err), Error::NoResponders));
+
+        let err = io::Error::new(io::ErrorKind::BrokenPipe, "broken");
+        assert!(
+            matches!(Error::from(err), Error::Io(err) if err.kind() == io::ErrorKind::BrokenPipe)
+        );
+    }
+
+    #[test]
+    fn server_errors() {
+        let err = Error::from_server("Permissions Violation for Publish to \"foo\"".to_string());
+        assert!(matches!(err, Error::PermissionViolation(_)));
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let err = Error::from_server("Maximum Payload Violation".to_string());
+        assert!(matches!(err, Error::Server(_)));
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, pin::Pin, sync::Arc};
+
+use futures::Stream;
+use tokio::sync::{broadcast, watch};
+
+use crate::ServerAddress;
+
+/// Number of events buffered for each `Connection::events` stream. A stream that
+/// falls further behind skips the oldest events.
+const EVENTS_CAPACITY: usize = 64;
+
+/// The state of a connection, as returned by `Connection::state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connected to a server.
+    Connected,
+    /// The connection to the server was lost.
+    Disconnected,
+    /// Trying to connect to a server again.
+    Reconnecting,
+    /// Draining before closing the connection.
+    Draining,
+    /// The connection is closed and will not reconnect.
+    Closed,
+}
+
+/// A change of the connection's lifecycle, as yielded by `Connection::events`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// Connected to a server for the first time.
+    Connected {
+        /// The server connected to.
+        server: ServerAddress,
+    },
+    /// The connection to the server was lost.
+    Disconnected {
+        /// The server that was connected, if known.
+        server: Option<ServerAddress>,
+        /// The error that broke the connection, if any.
+        error: Option<Arc<io::Error>>,
+    },
+    /// Attempting to connect to a server after the connection was lost.
+    Reconnecting {
+        /// The server about to be tried.
+        server: ServerAddress,
+        /// Number of attempts made on this server since it was last connected.
+        attempt: usize,
+    },
+    /// Connected to a server again after the connection was lost.
+    Reconnected {
+        /// The server connected to.
+        server: ServerAddress,
+        /// Total number of reconnects of this connection.
+        reconnects: u64,
+    },
+    /// The server announced that it is entering lame duck mode.
+    LameDuck {
+        /// The server in lame duck mode.
+        server: ServerAddress,
+    },
+    /// The connection started draining.
+    Draining,
+    /// The connection is closed.
+    Closed,
+}
+
+impl ConnectionEvent {
+    /// Returns the state the connection is in after this event.
+    pub fn state(&self) -> Option<ConnectionState> {
+        match self {
+            ConnectionEvent::Connected { .. } | ConnectionEvent::Reconnected { .. } => {
+                Some(ConnectionState::Connected)
+            }
+            ConnectionEvent::Disconnected { .. } => Some(ConnectionState::Disconnected),
+            ConnectionEvent::Reconnecting { .. } => Some(ConnectionState::Reconnecting),
+            ConnectionEvent::Draining => Some(ConnectionState::Draining),
+            ConnectionEvent::Closed => Some(ConnectionState::Closed),
+            ConnectionEvent::LameDuck { .. } => None,
+        }
+    }
+}
+
+/// Tracks the connection state and publishes events to all listeners.
+#[derive(Debug)]
+pub(crate) struct Events {
+    state: watch::Sender<ConnectionState>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl Events {
+    pub(crate) fn new() -> Events {
+        Events {
+            state: watch::channel(ConnectionState::Disconnected).0,
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        }
+    }
+
+    /// Returns the current state.
+    pub(crate) fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Sets the current state without emitting an event.
+    pub(crate) fn set_state(&self, state: ConnectionState) {
+        self.state.send_replace(state);
+    }
+
+    /// Updates the state and notifies listeners. Nothing is emitted once closed.
+    pub(crate) fn emit(&self, event: ConnectionEvent) {
+        let mut open = false;
+        self.state.send_if_modified(|state| {
+            open = *state != ConnectionState::Closed;
+            match event.state() {
+                Some(next) if open && next != *state => {
+                    *state = next;
+                    true
+                }
+                _ => false,
+            }
+        });
+        if open {
+            self.events.send(event).ok();
+        }
+    }
+
+    /// Returns a stream of events emitted from now on, ending after the connection is closed.
+    pub(crate) fn stream(&self) -> Pin<Box<dyn Stream<Item = ConnectionEvent> + Send>> {
+        let mut receiver = self.events.subscribe();
+        let closed = self.state() == ConnectionState::Closed;
+        Box::pin(async_stream::stream! {
+            if closed {
+                yield ConnectionEvent::Closed;
+                return;
+            }
+            loop {
+                match receiver.recv().await {
+                    Ok(ConnectionEvent::Closed) => {
+                        yield ConnectionEvent::Closed;
+                        return;
+                    }
+                    Ok(event) => yield event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        })
+    }
+}
@@ -46,7 +46,7 @@ pub async fn inject_delay() {
 
     if fastrand::i32(..10) == 0 {
         let duration = fastrand::u64(..50);
-        tokio::time::sleep(Duration::from_millis(duration)).await;
+        crate::runtime::sleep(Duration::from_millis(duration)).await;
     }
 
     if fastrand::i32(..2) == 0 {
@@ -12,10 +12,9 @@
 // limitations under the License.
 
 use std::{
-    collections::{HashMap, HashSet},
     convert::TryFrom,
+    fmt,
     iter::{FromIterator, IntoIterator},
-    ops::Deref,
 };
 
 use log::trace;
@@ -24,9 +23,11 @@ const HEADER_LINE: &str = "NATS/1.0";
 const HEADER_LINE_LEN: usize = HEADER_LINE.len();
 
 /// Status
+#[deprecated(note = "the status is on the version line, use `HeaderMap::status`")]
 pub const STATUS: &str = "Status";
 
 /// Description
+#[deprecated(note = "the description is on the version line, use `HeaderMap::description`")]
 pub const DESCRIPTION: &str = "Description";
 
 /// Nats-Msg-Id
@@ -50,82 +51,150 @@ pub const NATS_LAST_CONSUMER: &str = "Nats-Last-Consumer";
 /// Nats-Consumer-Stalled
 pub const NATS_CONSUMER_STALLED: &str = "Nats-Consumer-Stalled";
 
-/// A multi-map from header name to a set of values for that header
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
-pub struct HeaderMap {
-    /// A multi-map from header name to a set of values for that header
-    pub inner: HashMap<String, HashSet<String>>,
+/// Nats-Pending-Messages
+pub const NATS_PENDING_MESSAGES: &str = "Nats-Pending-Messages";
+
+/// Nats-Pending-Bytes
+pub const NATS_PENDING_BYTES: &str = "Nats-Pending-Bytes";
+
+/// The status code a server puts on the version line of the headers, like
+/// `503` in `NATS/1.0 503`. See `Message::status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusCode {
+    /// `100`, an idle heartbeat or a flow control request.
+    Control,
+    /// `404`, there are no messages, e.g. for a pull request with `no_wait`.
+    NotFound,
+    /// `408`, a pull request expired.
+    Timeout,
+    /// `409`, a pull request was ended early, e.g. because its `max_bytes`
+    /// was exceeded or the consumer was deleted.
+    Conflict,
+    /// `503`, there were no responders for a request.
+    NoResponders,
+    /// Any other status code.
+    Other(u16),
 }
 
-impl FromIterator<(String, String)> for HeaderMap {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = (String, String)>,
-    {
-        let mut inner = HashMap::default();
-        for (k, v) in iter {
-            let entry = inner.entry(k).or_insert_with(HashSet::default);
-            entry.insert(v);
+impl StatusCode {
+    /// Returns the numeric status code.
+    pub fn as_u16(self) -> u16 {
+        match self {
+            StatusCode::Control => 100,
+            StatusCode::NotFound => 404,
+            StatusCode::Timeout => 408,
+            StatusCode::Conflict => 409,
+            StatusCode::NoResponders => 503,
+            StatusCode::Other(code) => code,
         }
-        HeaderMap { inner }
     }
 }
 
-macro_rules! build_map {
-    ( $iter:ident ) => {{
-        let mut inner = HashMap::default();
-        for (k, v) in $iter {
-            let k = k.to_string();
-            let v = v.to_string();
-            let entry = inner.entry(k).or_insert_with(HashSet::default);
-            entry.insert(v);
+impl From<u16> for StatusCode {
+    fn from(code: u16) -> StatusCode {
+        match code {
+            100 => StatusCode::Control,
+            404 => StatusCode::NotFound,
+            408 => StatusCode::Timeout,
+            409 => StatusCode::Conflict,
+            503 => StatusCode::NoResponders,
+            code => StatusCode::Other(code),
         }
-        HeaderMap { inner }
-    }};
+    }
 }
 
-impl<'a> FromIterator<(&'a String, &'a String)> for HeaderMap {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = (&'a String, &'a String)>,
-    {
-        build_map!(iter)
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_u16())
     }
 }
 
-impl<'a> FromIterator<&'a (&'a String, &'a String)> for HeaderMap {
+/// NATS message headers.
+///
+/// Header names are case-insensitive, a name may carry several values, and
+/// headers keep the order they were inserted or received in. A header block
+/// that was parsed is written back out byte for byte until it is modified, so
+/// headers pass through unchanged when a message is forwarded.
+///
+/// # Example
+/// ```
+/// let mut headers = nats_aflowt::header::HeaderMap::new();
+/// headers.insert("Accept", "application/json");
+/// headers.append("accept", "text/plain");
+///
+/// assert_eq!(headers.get("ACCEPT"), Some("application/json"));
+/// assert_eq!(
+///     headers.get_all("Accept").collect::<Vec<_>>(),
+///     vec!["application/json", "text/plain"]
+/// );
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct HeaderMap {
+    /// Whatever follows `NATS/1.0` on the version line, i.e. the inline status
+    /// and description, kept verbatim.
+    status_line: String,
+    /// Header names and values in order, kept verbatim.
+    entries: Vec<(String, String)>,
+    /// The header block this map was parsed from, until it is modified.
+    raw: Option<Vec<u8>>,
+}
+
+impl PartialEq for HeaderMap {
+    fn eq(&self, other: &HeaderMap) -> bool {
+        self.status_line.trim() == other.status_line.trim() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for HeaderMap {}
+
+impl<K, V> FromIterator<(K, V)> for HeaderMap
+where
+    K: ToString,
+    V: ToString,
+{
     fn from_iter<T>(iter: T) -> Self
     where
-        T: IntoIterator<Item = &'a (&'a String, &'a String)>,
+        T: IntoIterator<Item = (K, V)>,
     {
-        build_map!(iter)
+        let mut headers = HeaderMap::default();
+        headers.extend(iter);
+        headers
     }
 }
 
-impl<'a> FromIterator<(&'a str, &'a str)> for HeaderMap {
+impl<'a, K, V> FromIterator<&'a (K, V)> for HeaderMap
+where
+    K: ToString + 'a,
+    V: ToString + 'a,
+{
     fn from_iter<T>(iter: T) -> Self
     where
-        T: IntoIterator<Item = (&'a str, &'a str)>,
+        T: IntoIterator<Item = &'a (K, V)>,
     {
-        build_map!(iter)
+        iter.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
     }
 }
 
-impl<'a> FromIterator<&'a (&'a str, &'a str)> for HeaderMap {
-    fn from_iter<T>(iter: T) -> Self
+impl<K, V> Extend<(K, V)> for HeaderMap
+where
+    K: ToString,
+    V: ToString,
+{
+    fn extend<T>(&mut self, iter: T)
     where
-        T: IntoIterator<Item = &'a (&'a str, &'a str)>,
+        T: IntoIterator<Item = (K, V)>,
     {
-        build_map!(iter)
+        for (k, v) in iter {
+            self.append(k, v);
+        }
     }
 }
 
 fn parse_error<T, E: AsRef<str>>(e: E) -> std::io::Result<T> {
     trace!("header parse error: {}", e.as_ref());
-    Err(std::io::Error::new(
-        std::io::ErrorKind::InvalidInput,
-        e.as_ref(),
-    ))
+    Err(crate::Error::validation(e.as_ref()).into())
 }
 
 fn is_continuation(c: char) -> bool {
@@ -136,101 +205,185 @@ impl TryFrom<&[u8]> for HeaderMap {
     type Error = std::io::Error;
 
     fn try_from(buf: &[u8]) -> std::io::Result<Self> {
-        let mut inner = HashMap::default();
         let mut lines = if let Ok(line) = std::str::from_utf8(buf) {
             line.lines().peekable()
         } else {
             return parse_error("invalid header received");
         };
 
-        if let Some(line) = lines.next() {
-            if !line.starts_with(HEADER_LINE) {
-                return parse_error("version line does not begin with NATS/1.0");
-            }
-
-            if let Some(slice) = line.get(HEADER_LINE_LEN..).map(|s| s.trim()) {
-                match slice.split_once(' ') {
-                    Some((status, description)) => {
-                        if !status.is_empty() {
-                            let entry = inner
-                                .entry(STATUS.to_string())
-                                .or_insert_with(HashSet::default);
-                            entry.insert(status.trim().to_string());
-                        }
-
-                        if !description.is_empty() {
-                            let entry = inner
-                                .entry(DESCRIPTION.to_string())
-                                .or_insert_with(HashSet::default);
-                            entry.insert(description.trim().to_string());
-                        }
-                    }
-                    None => {
-                        if !slice.is_empty() {
-                            let entry = inner
-                                .entry(STATUS.to_string())
-                                .or_insert_with(HashSet::default);
-                            entry.insert(slice.to_string());
-                        }
-                    }
-                }
-            }
-        } else {
-            return parse_error("expected header information not present");
+        let status_line = match lines.next() {
+            Some(line) if line.starts_with(HEADER_LINE) => line[HEADER_LINE_LEN..].to_string(),
+            Some(_) => return parse_error("version line does not begin with NATS/1.0"),
+            None => return parse_error("expected header information not present"),
         };
 
+        let mut entries = Vec::new();
         while let Some(line) = lines.next() {
             if line.is_empty() {
                 continue;
             }
 
             if let Some((k, v)) = line.split_once(':') {
-                let entry = inner
-                    .entry(k.trim().to_string())
-                    .or_insert_with(HashSet::default);
-
-                let mut s = String::from(v.trim());
-                while let Some(v) = lines.next_if(|s| s.starts_with(is_continuation)) {
-                    s.push(' ');
-                    s.push_str(v.trim());
+                let mut v = v.to_string();
+                // Fold obsolete multi-line values into one line.
+                while let Some(line) = lines.next_if(|s| s.starts_with(is_continuation)) {
+                    v.truncate(v.trim_end().len());
+                    v.push(' ');
+                    v.push_str(line.trim());
                 }
 
-                entry.insert(s);
+                entries.push((k.to_string(), v));
             } else {
                 return parse_error("malformed header line");
             }
         }
 
-        Ok(HeaderMap { inner })
+        // Only a complete header block, ending with an empty line, can be
+        // written back as is.
+        let raw = if buf.ends_with(b"\n\n") || buf.ends_with(b"\n\r\n") {
+            Some(buf.to_vec())
+        } else {
+            None
+        };
+
+        Ok(HeaderMap {
+            status_line,
+            entries,
+            raw,
+        })
     }
 }
 
-impl Deref for HeaderMap {
-    type Target = HashMap<String, HashSet<String>>;
+impl HeaderMap {
+    /// Creates an empty `HeaderMap`.
+    pub fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
+
+    /// Sets the value of a header, replacing all of its current values. The
+    /// header keeps its position if it was already present.
+    pub fn insert(&mut self, name: impl ToString, value: impl ToString) {
+        let name = name.to_string();
+        let value = value.to_string();
+        self.raw = None;
+        match self.position(&name) {
+            Some(pos) => {
+                self.entries[pos].1 = value;
+                let mut index = 0;
+                self.entries.retain(|(k, _)| {
+                    let keep = index <= pos || !eq_name(k, &name);
+                    index += 1;
+                    keep
+                });
+            }
+            None => self.entries.push((name, value)),
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+    /// Adds a value to a header, after the values it already has.
+    pub fn append(&mut self, name: impl ToString, value: impl ToString) {
+        self.raw = None;
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// Returns the first value of a header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter().find(|(k, _)| eq_name(k, name)).map(|(_, v)| v)
+    }
+
+    /// Returns all values of a header, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter()
+            .filter(move |(k, _)| eq_name(k, name))
+            .map(|(_, v)| v)
+    }
+
+    /// Removes all values of a header, and returns the first one.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let first = self.get(name).map(str::to_string);
+        self.raw = None;
+        self.entries.retain(|(k, _)| !eq_name(k, name));
+        first
+    }
+
+    /// Returns `true` if the header is present.
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// Returns all header names and values, in order. Names repeat for every
+    /// value they carry.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.trim(), v.trim()))
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the status code on the version line, like `503` in
+    /// `NATS/1.0 503`.
+    pub fn status(&self) -> Option<&str> {
+        self.status_line.split_whitespace().next()
+    }
+
+    /// Returns the status description on the version line, like
+    /// `Idle Heartbeat` in `NATS/1.0 100 Idle Heartbeat`.
+    pub fn description(&self) -> Option<&str> {
+        let status = self.status()?;
+        let description = self.status_line.trim_start()[status.len()..].trim();
+        if description.is_empty() {
+            None
+        } else {
+            Some(description)
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|(k, _)| eq_name(k, name))
     }
-}
 
-impl HeaderMap {
     pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        if let Some(raw) = &self.raw {
+            return raw.clone();
+        }
+
         // `<version line>\r\n[headers]\r\n\r\n[payload]\r\n`
         let mut buf = vec![];
-        buf.extend_from_slice(b"NATS/1.0\r\n");
-        for (k, vs) in &self.inner {
-            for v in vs {
-                buf.extend_from_slice(k.trim().as_bytes());
-                buf.push(b':');
-                buf.extend_from_slice(v.trim().as_bytes());
-                buf.extend_from_slice(b"\r\n");
-            }
+        buf.extend_from_slice(HEADER_LINE.as_bytes());
+        buf.extend_from_slice(self.status_line.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+        for (k, v) in self.iter() {
+            buf.extend_from_slice(k.as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(v.as_bytes());
+            buf.extend_from_slice(b"\r\n");
         }
         buf.extend_from_slice(b"\r\n");
         buf
     }
 }
 
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Box<dyn Iterator<Item = (&'a str, &'a str)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// Compares header names, ignoring case.
+fn eq_name(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
 #[cfg(test)]
 mod try_from {
     use super::*;
@@ -239,48 +392,26 @@ mod try_from {
     fn inline_status() {
         // With single spacing.
         let headers = HeaderMap::try_from("NATS/1.0 100".as_bytes()).unwrap();
-
-        assert_eq!(
-            headers.inner.get(&STATUS.to_string()),
-            Some(&HashSet::from_iter(vec!["100".to_string(),]))
-        );
+        assert_eq!(headers.status(), Some("100"));
+        assert_eq!(headers.description(), None);
 
         // With double spacing.
         let headers = HeaderMap::try_from("NATS/1.0  100".as_bytes()).unwrap();
-
-        assert_eq!(
-            headers.inner.get(&STATUS.to_string()),
-            Some(&HashSet::from_iter(vec!["100".to_string(),]))
-        );
+        assert_eq!(headers.status(), Some("100"));
+        assert_eq!(headers.description(), None);
     }
 
     #[test]
     fn inline_status_with_description() {
         // With single spacing
         let headers = HeaderMap::try_from("NATS/1.0 100 Idle Heartbeat".as_bytes()).unwrap();
-
-        assert_eq!(
-            headers.inner.get(&STATUS.to_string()),
-            Some(&HashSet::from_iter(vec!["100".to_string()]))
-        );
-
-        assert_eq!(
-            headers.inner.get(&DESCRIPTION.to_string()),
-            Some(&HashSet::from_iter(vec!["Idle Heartbeat".to_string()]))
-        );
+        assert_eq!(headers.status(), Some("100"));
+        assert_eq!(headers.description(), Some("Idle Heartbeat"));
 
         // With double spacing.
         let headers = HeaderMap::try_from("NATS/1.0  100  Idle Heartbeat".as_bytes()).unwrap();
-
-        assert_eq!(
-            headers.inner.get(&STATUS.to_string()),
-            Some(&HashSet::from_iter(vec!["100".to_string()]))
-        );
-
-        assert_eq!(
-            headers.inner.get(&DESCRIPTION.to_string()),
-            Some(&HashSet::from_iter(vec!["Idle Heartbeat".to_string()]))
-        );
+        assert_eq!(headers.status(), Some("100"));
+        assert_eq!(headers.description(), Some("Idle Heartbeat"));
     }
 
     #[test]
@@ -296,20 +427,9 @@ mod try_from {
         )
         .unwrap();
 
-        assert_eq!(
-            headers.inner.get(&"X-Test-A".to_string()),
-            Some(&HashSet::from_iter(vec!["a".to_string()]))
-        );
-
-        assert_eq!(
-            headers.inner.get(&"X-Test-B".to_string()),
-            Some(&HashSet::from_iter(vec!["b".to_string()]))
-        );
-
-        assert_eq!(
-            headers.inner.get(&"X-Test-C".to_string()),
-            Some(&HashSet::from_iter(vec!["c".to_string()]))
-        );
+        assert_eq!(headers.get("X-Test-A"), Some("a"));
+        assert_eq!(headers.get("X-Test-B"), Some("b"));
+        assert_eq!(headers.get("X-Test-C"), Some("c"));
     }
 
     #[test]
@@ -319,15 +439,8 @@ mod try_from {
         )
         .unwrap();
 
-        assert_eq!(
-            headers.inner.get(&"Accept-Encoding".to_string()),
-            Some(&HashSet::from_iter(vec!["json".to_string()]))
-        );
-
-        assert_eq!(
-            headers.inner.get(&"Authorization".to_string()),
-            Some(&HashSet::from_iter(vec!["s3cr3t".to_string()]))
-        );
+        assert_eq!(headers.get("Accept-Encoding"), Some("json"));
+        assert_eq!(headers.get("Authorization"), Some("s3cr3t"));
     }
 
     #[test]
@@ -336,10 +449,7 @@ mod try_from {
             HeaderMap::try_from("NATS/1.0 200\r\nX-Test: one,\r\n\ttwo,\r\n\tthree\r\n".as_bytes())
                 .unwrap();
 
-        assert_eq!(
-            headers.inner.get(&"X-Test".to_string()),
-            Some(&HashSet::from_iter(vec!["one, two, three".to_string()]))
-        );
+        assert_eq!(headers.get("X-Test"), Some("one, two, three"));
     }
 
     #[test]
@@ -348,9 +458,112 @@ mod try_from {
             HeaderMap::try_from("NATS/1.0 200\r\nX-Test: one,\r\n two,\r\n three\r\n".as_bytes())
                 .unwrap();
 
+        assert_eq!(headers.get("X-Test"), Some("one, two, three"));
+    }
+
+    #[test]
+    fn multiple_values() {
+        let headers =
+            HeaderMap::try_from("NATS/1.0\r\nX-Test: a\r\nX-Other: b\r\nx-test: c\r\n".as_bytes())
+                .unwrap();
+
+        assert_eq!(headers.get("X-TEST"), Some("a"));
         assert_eq!(
-            headers.inner.get(&"X-Test".to_string()),
-            Some(&HashSet::from_iter(vec!["one, two, three".to_string()]))
+            headers.get_all("x-test").collect::<Vec<_>>(),
+            vec!["a", "c"]
         );
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![("X-Test", "a"), ("X-Other", "b"), ("x-test", "c")]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for buf in [
+            "NATS/1.0\r\n\r\n",
+            "NATS/1.0 503\r\n\r\n",
+            "NATS/1.0  100  Idle Heartbeat\r\nNats-Last-Consumer: 4\r\n\r\n",
+            "NATS/1.0\r\nB: 1\r\nA:2\r\nb:  3 \r\nC:\r\n\r\n",
+            "NATS/1.0 200\r\nX-Test: one,\r\n\ttwo,\r\n  three\r\nA: 1\r\n\r\n",
+            "NATS/1.0\nX-Test: a\nX-Other:b\n\n",
+        ] {
+            let headers = HeaderMap::try_from(buf.as_bytes()).unwrap();
+            assert_eq!(String::from_utf8(headers.to_bytes()).unwrap(), buf);
+            assert_eq!(
+                HeaderMap::try_from(&headers.to_bytes()[..]).unwrap(),
+                headers
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod header_map {
+    use super::*;
+
+    #[test]
+    fn insert_replaces_values_in_place() {
+        let mut headers = HeaderMap::new();
+        headers.append("A", "1");
+        headers.append("B", "2");
+        headers.append("a", "3");
+        headers.insert("A", "4");
+
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![("A", "4"), ("B", "2")]
+        );
+        assert_eq!(headers.len(), 2);
+    }
+
+    #[test]
+    fn remove() {
+        let mut headers: HeaderMap = [("A", "1"), ("B", "2"), ("a", "3")].iter().collect();
+
+        assert_eq!(headers.remove("a"), Some("1".to_string()));
+        assert!(!headers.contains_key("A"));
+        assert_eq!(headers.remove("A"), None);
+        assert_eq!(headers.iter().collect::<Vec<_>>(), vec![("B", "2")]);
+    }
+
+    #[test]
+    fn to_bytes_keeps_order() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Nats-Msg-Id", "1");
+        headers.append("X-Multi", "a");
+        headers.append("X-Multi", "b");
+
+        assert_eq!(
+            headers.to_bytes(),
+            b"NATS/1.0\r\nNats-Msg-Id: 1\r\nX-Multi: a\r\nX-Multi: b\r\n\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn modified_headers_are_written_anew() {
+        let mut headers =
+            HeaderMap::try_from("NATS/1.0\nX-Test: one,\n two\nA:1\n\n".as_bytes()).unwrap();
+        headers.append("B", "2");
+
+        assert_eq!(
+            headers.to_bytes(),
+            b"NATS/1.0\r\nX-Test: one, two\r\nA: 1\r\nB: 2\r\n\r\n".to_vec()
+        );
+    }
+}
+
+#[cfg(test)]
+mod status_code {
+    use super::*;
+
+    #[test]
+    fn from_u16() {
+        for code in [100, 200, 404, 408, 409, 503] {
+            assert_eq!(StatusCode::from(code).as_u16(), code);
+        }
+        assert_eq!(StatusCode::from(503), StatusCode::NoResponders);
+        assert_eq!(StatusCode::from(200), StatusCode::Other(200));
+        assert_eq!(StatusCode::Timeout.to_string(), "408");
     }
 }
@@ -106,7 +106,7 @@ use pin_project::pin_project;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::VecDeque,
     convert::TryFrom,
     error, fmt,
     fmt::Debug,
@@ -114,9 +114,9 @@ use std::{
     pin::Pin,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, PoisonError,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::sync::Mutex;
 #[cfg(feature = "otel")]
@@ -124,27 +124,43 @@ use tracing::{debug, error};
 
 const ORDERED_IDLE_HEARTBEAT: Duration = Duration::from_nanos(5_000_000_000);
 
+/// Default number of asynchronous publishes that may wait for their ack.
+const DEFAULT_PUBLISH_ASYNC_MAX_PENDING: usize = 4000;
+
+/// Default time to wait for the ack of an asynchronous publish.
+const DEFAULT_PUBLISH_ASYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+mod publish_async;
+mod pull_subscription;
 mod push_subscription;
 mod types;
 
+use publish_async::AsyncPublisher;
+pub use publish_async::PublishAckFuture;
+pub use pull_subscription::PullSubscription;
 pub use push_subscription::PushSubscription;
 pub use types::*;
 
 use crate::{
     header::{self, HeaderMap},
-    Connection, Message,
+    runtime, Connection, IntoPayload, Message,
 };
 
 /// `JetStream` options
 #[derive(Clone)]
 pub struct JetStreamOptions {
     pub(crate) api_prefix: String,
+    pub(crate) publish_async_max_pending: usize,
 }
 
 impl Debug for JetStreamOptions {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         f.debug_map()
             .entry(&"api_prefix", &self.api_prefix)
+            .entry(
+                &"publish_async_max_pending",
+                &self.publish_async_max_pending,
+            )
             .finish()
     }
 }
@@ -153,6 +169,7 @@ impl Default for JetStreamOptions {
     fn default() -> JetStreamOptions {
         JetStreamOptions {
             api_prefix: "$JS.API.".to_string(),
+            publish_async_max_pending: DEFAULT_PUBLISH_ASYNC_MAX_PENDING,
         }
     }
 }
@@ -203,6 +220,22 @@ impl JetStreamOptions {
             self.api_prefix(format!("$JS.{}.API", domain))
         }
     }
+
+    /// Set the maximum number of asynchronous publishes waiting for their ack.
+    /// Once the limit is reached, `JetStream::publish_async` waits for acks to arrive
+    /// before publishing more messages.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let options = nats_aflowt::jetstream::JetStreamOptions::new()
+    ///   .publish_async_max_pending(256);
+    /// ```
+    #[must_use]
+    pub fn publish_async_max_pending(mut self, max_pending: usize) -> Self {
+        self.publish_async_max_pending = max_pending.max(1);
+        self
+    }
 }
 
 /// `ApiResponse` is a standard response from the `JetStream` JSON Api
@@ -546,11 +579,23 @@ struct SubscriptionPreprocessor {
     consumer_config: ConsumerConfig,
     stream_name: String,
     shared_sid: Arc<AtomicU64>,
+
+    /// Set for pull subscriptions. Their idle heartbeats are consumed here, and
+    /// consumer sequences are not checked, as other subscribers may pull in between.
+    pull: bool,
+
+    /// When the last message or idle heartbeat arrived.
+    last_activity: Arc<std::sync::Mutex<Instant>>,
 }
 
 impl crate::client::Preprocessor for SubscriptionPreprocessor {
     fn process<'proc>(&'proc self, sid: u64, message: &'proc Message) -> BoxFuture<'proc, bool> {
         Box::pin(async move {
+            *self
+                .last_activity
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+
             if message.is_flow_control() {
                 return false;
             }
@@ -560,12 +605,8 @@ impl crate::client::Preprocessor for SubscriptionPreprocessor {
                 let maybe_consumer_stalled = message
                     .headers
                     .as_ref()
-                    .and_then(|headers| {
-                        headers
-                            .get(header::NATS_CONSUMER_STALLED)
-                            .map(|set| set.iter().next().cloned())
-                    })
-                    .flatten();
+                    .and_then(|headers| headers.get(header::NATS_CONSUMER_STALLED))
+                    .map(str::to_string);
 
                 if let Some(consumer_stalled) = maybe_consumer_stalled {
                     debug!("publish on stalled idle heartbeat");
@@ -582,12 +623,12 @@ impl crate::client::Preprocessor for SubscriptionPreprocessor {
                 let maybe_consumer_seq = message
                     .headers
                     .as_ref()
-                    .and_then(|headers| {
-                        headers
-                            .get(header::NATS_LAST_CONSUMER)
-                            .map(|set| set.iter().next().cloned())
-                    })
-                    .flatten();
+                    .and_then(|headers| headers.get(header::NATS_LAST_CONSUMER))
+                    .map(str::to_string);
+
+                if self.pull {
+                    return true;
+                }
 
                 if let Some(consumer_seq) = maybe_consumer_seq {
                     let consumer_seq = consumer_seq.parse::<u64>().unwrap();
@@ -610,6 +651,9 @@ impl crate::client::Preprocessor for SubscriptionPreprocessor {
             }
 
             // Track messages for sequence mismatches.
+            if self.pull {
+                return false;
+            }
             if let Some(message_info) = message.jetstream_message_info() {
                 let mut sequence_info = self.sequence_pair.lock().await;
                 if message_info.consumer_seq != sequence_info.consumer_seq + 1 {
@@ -639,6 +683,7 @@ impl crate::client::Preprocessor for SubscriptionPreprocessor {
 pub struct JetStream {
     pub(crate) connection: Connection,
     pub(crate) options: JetStreamOptions,
+    async_publisher: Arc<std::sync::Mutex<Option<Arc<AsyncPublisher>>>>,
 }
 
 impl JetStream {
@@ -647,11 +692,12 @@ impl JetStream {
         Self {
             connection,
             options,
+            async_publisher: Arc::new(std::sync::Mutex::new(None)),
         }
     }
 
     /// Publishes a message to `JetStream`
-    pub async fn publish(&self, subject: &str, data: impl AsRef<[u8]>) -> io::Result<PublishAck> {
+    pub async fn publish(&self, subject: &str, data: impl IntoPayload) -> io::Result<PublishAck> {
         self.publish_with_options_or_headers(subject, None, None, data)
             .await
     }
@@ -660,7 +706,7 @@ impl JetStream {
     pub async fn publish_with_options(
         &self,
         subject: &str,
-        data: impl AsRef<[u8]>,
+        data: impl IntoPayload,
         options: &PublishOptions,
     ) -> io::Result<PublishAck> {
         self.publish_with_options_or_headers(subject, Some(options), None, data)
@@ -673,7 +719,7 @@ impl JetStream {
             &message.subject,
             None,
             message.headers.as_ref(),
-            &message.data,
+            message.data.clone(),
         )
         .await
     }
@@ -688,66 +734,33 @@ impl JetStream {
             &message.subject,
             Some(options),
             message.headers.as_ref(),
-            &message.data,
+            message.data.clone(),
         )
         .await
     }
 
     /// Publishes a message to `JetStream` with the given options and/or headers.
+    #[cfg_attr(
+        feature = "otel",
+        tracing::instrument(
+            name = "jetstream.publish",
+            skip_all,
+            fields(
+                otel.kind = "producer",
+                subject,
+                stream = tracing::field::Empty,
+                seq = tracing::field::Empty,
+            )
+        )
+    )]
     pub(crate) async fn publish_with_options_or_headers(
         &self,
         subject: &str,
         maybe_options: Option<&PublishOptions>,
         maybe_headers: Option<&HeaderMap>,
-        msg: impl AsRef<[u8]>,
+        msg: impl IntoPayload,
     ) -> io::Result<PublishAck> {
-        let maybe_headers = if let Some(options) = maybe_options {
-            let mut headers = maybe_headers.map_or_else(HeaderMap::default, HeaderMap::clone);
-
-            if let Some(v) = options.id.as_ref() {
-                let entry = headers
-                    .inner
-                    .entry(header::NATS_MSG_ID.to_string())
-                    .or_insert_with(HashSet::default);
-                entry.insert(v.to_string());
-            }
-
-            if let Some(v) = options.expected_last_msg_id.as_ref() {
-                let entry = headers
-                    .inner
-                    .entry(header::NATS_EXPECTED_LAST_MSG_ID.to_string())
-                    .or_insert_with(HashSet::default);
-                entry.insert(v.to_string());
-            }
-
-            if let Some(v) = options.expected_stream.as_ref() {
-                let entry = headers
-                    .inner
-                    .entry(header::NATS_EXPECTED_STREAM.to_string())
-                    .or_insert_with(HashSet::default);
-                entry.insert(v.to_string());
-            }
-
-            if let Some(v) = options.expected_last_sequence.as_ref() {
-                let entry = headers
-                    .inner
-                    .entry(header::NATS_EXPECTED_LAST_SEQUENCE.to_string())
-                    .or_insert_with(HashSet::default);
-                entry.insert(v.to_string());
-            }
-
-            if let Some(v) = options.expected_last_subject_sequence.as_ref() {
-                let entry = headers
-                    .inner
-                    .entry(header::NATS_EXPECTED_LAST_SUBJECT_SEQUENCE.to_string())
-                    .or_insert_with(HashSet::default);
-                entry.insert(v.to_string());
-            }
-
-            Some(headers)
-        } else {
-            maybe_headers.cloned()
-        };
+        let maybe_headers = publish_headers(maybe_options, maybe_headers);
 
         let maybe_timeout = maybe_options.and_then(|options| options.timeout);
 
@@ -758,7 +771,15 @@ impl JetStream {
 
         let res: ApiResponse<PublishAck> = serde_json::de::from_slice(&res_msg.data)?;
         match res {
-            ApiResponse::Ok(pub_ack) => Ok(pub_ack),
+            ApiResponse::Ok(pub_ack) => {
+                #[cfg(feature = "otel")]
+                {
+                    let span = tracing::Span::current();
+                    span.record("stream", pub_ack.stream.as_str());
+                    span.record("seq", pub_ack.sequence);
+                }
+                Ok(pub_ack)
+            }
             ApiResponse::Err { error, .. } => {
                 log::error!(
                     "failed to parse API response: {:?}",
@@ -770,6 +791,156 @@ impl JetStream {
         }
     }
 
+    /// Publishes a message to `JetStream` without waiting for the server to acknowledge it.
+    ///
+    /// Returns once the message is handed to the connection, with a future that resolves
+    /// to the `PublishAck`. Acks of all asynchronous publishes are received on a single
+    /// wildcard subscription. If `JetStreamOptions::publish_async_max_pending` acks are
+    /// outstanding, this waits until some of them arrive.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # let name = format!("publish_async_{}", rand::random::<u64>());
+    /// # context.add_stream(name.as_str()).await?;
+    /// let mut acks = Vec::new();
+    /// for i in 0..100 {
+    ///     acks.push(context.publish_async(&name, format!("{}", i)).await?);
+    /// }
+    /// for ack in acks {
+    ///     println!("stored in sequence {}", ack.await?.sequence);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn publish_async(
+        &self,
+        subject: &str,
+        data: impl IntoPayload,
+    ) -> io::Result<PublishAckFuture> {
+        self.publish_async_with_options_or_headers(subject, None, None, data)
+            .await
+    }
+
+    /// Publishes a message to `JetStream` with the given options without waiting for the
+    /// server to acknowledge it. The `timeout` of the options limits how long the returned
+    /// future waits for the ack.
+    pub async fn publish_async_with_options(
+        &self,
+        subject: &str,
+        data: impl IntoPayload,
+        options: &PublishOptions,
+    ) -> io::Result<PublishAckFuture> {
+        self.publish_async_with_options_or_headers(subject, Some(options), None, data)
+            .await
+    }
+
+    /// Publishes a `Message` to `JetStream` without waiting for the server to acknowledge it.
+    pub async fn publish_message_async(&self, message: &Message) -> io::Result<PublishAckFuture> {
+        self.publish_async_with_options_or_headers(
+            &message.subject,
+            None,
+            message.headers.as_ref(),
+            message.data.clone(),
+        )
+        .await
+    }
+
+    async fn publish_async_with_options_or_headers(
+        &self,
+        subject: &str,
+        maybe_options: Option<&PublishOptions>,
+        maybe_headers: Option<&HeaderMap>,
+        msg: impl IntoPayload,
+    ) -> io::Result<PublishAckFuture> {
+        let maybe_headers = publish_headers(maybe_options, maybe_headers);
+        let timeout = maybe_options
+            .and_then(|options| options.timeout)
+            .unwrap_or(DEFAULT_PUBLISH_ASYNC_TIMEOUT);
+
+        let publisher = self.async_publisher().await?;
+        let (reply, ack) = publisher.register(timeout).await?;
+        if let Err(err) = self
+            .connection
+            .publish_with_reply_or_headers(subject, Some(&reply), maybe_headers.as_ref(), msg)
+            .await
+        {
+            publisher.forget(&reply);
+            return Err(err);
+        }
+
+        Ok(ack)
+    }
+
+    /// Returns the publisher tracking asynchronous acks, starting a new one if there is
+    /// none yet or the previous one was closed.
+    async fn async_publisher(&self) -> io::Result<Arc<AsyncPublisher>> {
+        if let Some(publisher) = self.current_async_publisher() {
+            return Ok(publisher);
+        }
+
+        let started = AsyncPublisher::start(
+            &self.connection.0.client,
+            self.options.publish_async_max_pending,
+        )
+        .await?;
+
+        // Another publish may have started one in the meantime, keep whichever came first.
+        let mut slot = self.async_publisher.lock().unwrap();
+        match slot.as_ref() {
+            Some(publisher) if !publisher.is_closed() => Ok(publisher.clone()),
+            _ => {
+                *slot = Some(started.clone());
+                Ok(started)
+            }
+        }
+    }
+
+    fn current_async_publisher(&self) -> Option<Arc<AsyncPublisher>> {
+        self.async_publisher
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|publisher| !publisher.is_closed())
+            .cloned()
+    }
+
+    /// Returns the number of asynchronous publishes still waiting for their ack.
+    pub fn publish_async_pending(&self) -> usize {
+        self.current_async_publisher()
+            .map_or(0, |publisher| publisher.pending())
+    }
+
+    /// Waits until every message published with `publish_async` has been acknowledged,
+    /// or has failed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # let name = format!("publish_async_complete_{}", rand::random::<u64>());
+    /// # context.add_stream(name.as_str()).await?;
+    /// for i in 0..100 {
+    ///     context.publish_async(&name, format!("{}", i)).await?;
+    /// }
+    /// context.publish_async_complete().await;
+    /// assert_eq!(context.publish_async_pending(), 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn publish_async_complete(&self) {
+        if let Some(publisher) = self.current_async_publisher() {
+            publisher.wait_complete().await;
+        }
+    }
+
     /// Create an ephemeral push consumer subscription.
     ///
     /// # Example
@@ -877,7 +1048,7 @@ impl JetStream {
         }
 
         let context = self.clone();
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             let new_deliver_subject = context.connection.new_inbox();
             let result = context
                 .connection
@@ -915,13 +1086,12 @@ impl JetStream {
         // If no stream name is specified the subject cannot be empty.
         if subject.is_empty()
             && maybe_options
-                .map(|options| options.stream_name.as_ref())
+                .and_then(|options| options.stream_name.as_ref())
                 .is_none()
         {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "Subject required",
-            ));
+            )));
         }
 
         let wants_idle_heartbeat =
@@ -933,18 +1103,16 @@ impl JetStream {
         if maybe_queue.is_some() {
             // Queue subscriber cannot have idle heartbeats
             if wants_idle_heartbeat {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
+                return Err(io::Error::from(crate::Error::validation(
                     "queue subscription doesn't support idle heartbeat",
-                ));
+                )));
             }
 
             // Nor flow control since messages will randomly dispatched to members.
             if wants_flow_control {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
+                return Err(io::Error::from(crate::Error::validation(
                     "queue subscription doesn't support flow control",
-                ));
+                )));
             }
         };
 
@@ -952,99 +1120,84 @@ impl JetStream {
         if let Some(options) = maybe_options.filter(|options| options.ordered) {
             // Check for queue subscription.
             if maybe_queue.is_some() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "queues not be set for an ordered consumer",
-                ));
+                )));
             }
 
             // Check for durable name
             if options.durable_name.is_some() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "durable name can not be set for an ordered consumer",
-                ));
+                )));
             }
 
             // Check for bound consumers.
             if options.consumer_name.is_some() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "can not bind existing consumer for an ordered consumer",
-                ));
+                )));
             }
 
             // Check for ack policy.
             if options.ack_policy.is_some() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "ack policy can not be set for an ordered consumer",
-                ));
+                )));
             }
 
             // Check for max deliver.
             if options.max_deliver.is_some() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "max deliver can not be set for an ordered consumer",
-                ));
+                )));
             }
 
             // Check for deliver subject (we pick our own).
             if options.deliver_subject.is_some() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "deliver subject can not be set for an ordered consumer",
-                ));
+                )));
             }
         }
 
         let process_consumer_info = |info: ConsumerInfo| {
             // Make sure this new subject matches or is a subset.
             if !info.config.filter_subject.is_empty() && subject != info.config.filter_subject {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "subject does not match consumer",
-                ));
+                )));
             }
 
             if let Some(deliver_group) = info.config.deliver_group.as_ref() {
                 if let Some(queue) = &maybe_queue {
                     if deliver_group != queue {
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
+                        return Err(io::Error::from(crate::Error::validation(format!(
                                 "cannot create a queue subscription {} for a consumer with a deliver group {}",
                                 queue, deliver_group
-                            ),
-                        ));
+                            ))));
                     }
                 } else {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "cannot create a subscription for a consumer with a deliver group {}",
-                            deliver_group
-                        ),
-                    ));
+                    return Err(io::Error::from(crate::Error::validation(format!(
+                        "cannot create a subscription for a consumer with a deliver group {}",
+                        deliver_group
+                    ))));
                 }
             } else {
                 // Prevent a user from attempting to create a queue subscription on
                 // a consumer that was not created with a deliver group.
                 if maybe_queue.is_some() {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
+                    return Err(io::Error::from(crate::Error::validation(
                         "cannot create a queue subscription for a consumer without a deliver group",
-                    ));
+                    )));
                 }
 
                 // Need to reject a non queue subscription to a non queue consumer if the consumer
                 // is already bound.
                 if maybe_queue.is_some() && info.push_bound {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
+                    return Err(io::Error::from(crate::Error::validation(
                         "consumer is already bound to a subscription",
-                    ));
+                    )));
                 }
             }
 
@@ -1053,139 +1206,107 @@ impl JetStream {
                 if options.durable_name.is_some()
                     && options.durable_name != info.config.durable_name
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests durable name to be {:?}, but consumer's value is {:?}", options.durable_name, info.config.durable_name
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests durable name to be {:?}, but consumer's value is {:?}", options.durable_name, info.config.durable_name
+                        ))));
                 }
 
                 if options.description.is_some() && options.description != info.config.description {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests description to be {:?}, but consumer's value is {:?}", options.description, info.config.description
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests description to be {:?}, but consumer's value is {:?}", options.description, info.config.description
+                        ))));
                 }
 
                 if options.deliver_policy.is_some()
                     && options.deliver_policy.unwrap() != info.config.deliver_policy
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests deliver policy to be {:?}, but consumer's value is {:?}", options.deliver_policy, info.config.deliver_policy
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests deliver policy to be {:?}, but consumer's value is {:?}", options.deliver_policy, info.config.deliver_policy
+                        ))));
                 }
 
                 if options.opt_start_seq.is_some()
                     && options.opt_start_seq != info.config.opt_start_seq
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests optional start sequence to be {:?}, but consumer's value is {:?}", options.opt_start_seq, info.config.opt_start_seq
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests optional start sequence to be {:?}, but consumer's value is {:?}", options.opt_start_seq, info.config.opt_start_seq
+                        ))));
                 }
 
                 if options.opt_start_time.is_some()
                     && options.opt_start_time != info.config.opt_start_time
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests optional start time to be {:?}, but consumer's value is {:?}", options.opt_start_time, info.config.opt_start_time
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests optional start time to be {:?}, but consumer's value is {:?}", options.opt_start_time, info.config.opt_start_time
+                        ))));
                 }
 
                 if options.ack_policy.is_some()
                     && options.ack_policy.unwrap() != info.config.ack_policy
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests ack policy to be {:?}, but consumer's value is {:?}", options.ack_policy, info.config.ack_policy
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests ack policy to be {:?}, but consumer's value is {:?}", options.ack_policy, info.config.ack_policy
+                        ))));
                 }
 
                 if options.ack_wait.is_some() && options.ack_wait.unwrap() != info.config.ack_wait {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "configuration requests ack wait to be {:?}, but consumer's value is {:?}",
-                            options.ack_wait, info.config.ack_wait
-                        ),
-                    ));
+                    return Err(io::Error::from(crate::Error::validation(format!(
+                        "configuration requests ack wait to be {:?}, but consumer's value is {:?}",
+                        options.ack_wait, info.config.ack_wait
+                    ))));
                 }
 
                 if options.max_deliver.is_some()
                     && options.max_deliver.unwrap() != info.config.max_deliver
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests max deliver to be {:?}, but consumer's value is {:?}", options.max_deliver, info.config.max_deliver
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests max deliver to be {:?}, but consumer's value is {:?}", options.max_deliver, info.config.max_deliver
+                        ))));
                 }
 
                 if options.replay_policy.is_some()
                     && options.replay_policy.unwrap() != info.config.replay_policy
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests replay policy to be {:?}, but consumer's value is {:?}", options.replay_policy, info.config.replay_policy
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests replay policy to be {:?}, but consumer's value is {:?}", options.replay_policy, info.config.replay_policy
+                        ))));
                 }
 
                 if options.rate_limit.is_some()
                     && options.rate_limit.unwrap() != info.config.rate_limit
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests rate limit to be {:?}, but consumer's value is {:?}", options.rate_limit, info.config.rate_limit
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests rate limit to be {:?}, but consumer's value is {:?}", options.rate_limit, info.config.rate_limit
+                        ))));
                 }
 
                 if options.sample_frequency.is_some()
                     && options.sample_frequency.unwrap() != info.config.sample_frequency
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests sample frequency to be {:?}, but consumer's value is {:?}", options.sample_frequency, info.config.sample_frequency
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests sample frequency to be {:?}, but consumer's value is {:?}", options.sample_frequency, info.config.sample_frequency
+                        ))));
                 }
 
                 if options.max_waiting.is_some()
                     && options.max_waiting.unwrap() != info.config.max_waiting
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests max waiting to be {:?}, but consumer's value is {:?}", options.max_waiting, info.config.max_waiting
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests max waiting to be {:?}, but consumer's value is {:?}", options.max_waiting, info.config.max_waiting
+                        ))));
                 }
 
                 if options.max_ack_pending.is_some()
                     && options.max_ack_pending.unwrap() != info.config.max_ack_pending
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests max ack pending to be {:?}, but consumer's value is {:?}", options.max_ack_pending, info.config.max_ack_pending
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests max ack pending to be {:?}, but consumer's value is {:?}", options.max_ack_pending, info.config.max_ack_pending
+                        ))));
                 }
 
                 // For flow control, we want to fail if the user explicit wanted it, but
                 // it is not set in the existing consumer. If it is not asked by the user,
                 // the library still handles it and so no reason to fail.
                 if options.flow_control.is_some() && !info.config.flow_control {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("configuration requests flow control to be {:?}, but consumer's value is {:?}", options.flow_control, info.config.flow_control
-                        )));
+                    return Err(io::Error::from(crate::Error::validation(format!("configuration requests flow control to be {:?}, but consumer's value is {:?}", options.flow_control, info.config.flow_control
+                        ))));
                 }
 
                 if options.idle_heartbeat.is_some()
                     && options.idle_heartbeat.unwrap() != info.config.idle_heartbeat
                 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "configuration requests heartbeat to be {:?}, but consumer's value is {:?}",
-                            options.idle_heartbeat, info.config.idle_heartbeat
-                        ),
-                    ));
+                    return Err(io::Error::from(crate::Error::validation(format!(
+                        "configuration requests heartbeat to be {:?}, but consumer's value is {:?}",
+                        options.idle_heartbeat, info.config.idle_heartbeat
+                    ))));
                 }
             }
 
@@ -1229,7 +1350,7 @@ impl JetStream {
                         if let Some(inner) = err.into_inner() {
                             if let Ok(err) = inner.downcast::<Error>() {
                                 if err.error_code() == ErrorCode::ConsumerNotFound {
-                                    return Err(io::Error::new(io::ErrorKind::Other, err));
+                                    return Err(crate::Error::from(*err).into());
                                 }
                             }
                         }
@@ -1318,10 +1439,9 @@ impl JetStream {
             .and_then(|consumer_info| consumer_info.config.deliver_subject.clone())
             .or_else(|| consumer_config.deliver_subject.clone())
             .ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::Other,
+                io::Error::from(crate::Error::validation(
                     "must use pull subscribe to bind to pull based consumer",
-                )
+                ))
             })?;
 
         let preprocessor = SubscriptionPreprocessor {
@@ -1330,6 +1450,8 @@ impl JetStream {
             consumer_config: consumer_config.clone(),
             stream_name: stream_name.clone(),
             shared_sid: shared_sid.clone(),
+            pull: false,
+            last_activity: Arc::new(std::sync::Mutex::new(Instant::now())),
         };
         // Create a subscription with that subject.
         let (mut sid, mut receiver) = self
@@ -1361,7 +1483,7 @@ impl JetStream {
                     if let Some(inner) = err.into_inner() {
                         if let Ok(err) = inner.downcast::<Error>() {
                             if err.error_code() != ErrorCode::ConsumerNameExist {
-                                return Err(io::Error::new(io::ErrorKind::Other, err));
+                                return Err(crate::Error::from(*err).into());
                             }
                         }
                     }
@@ -1379,10 +1501,9 @@ impl JetStream {
                         .deliver_subject
                         .as_ref()
                         .ok_or_else(|| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
+                            io::Error::from(crate::Error::validation(
                                 "must use pull subscribe to bind to pull based consumer",
-                            )
+                            ))
                         })?;
 
                     // Create a new subscription with the new subject.
@@ -1416,6 +1537,158 @@ impl JetStream {
         ))
     }
 
+    /// Creates an ephemeral pull consumer subscription.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # let sub_name = format!("pull_{}", rand::random::<u64>());
+    /// # context.add_stream(sub_name.as_str()).await?;
+    /// # context.publish(&sub_name, "hello").await?;
+    /// #
+    /// let subscription = context.pull_subscribe(&sub_name).await?;
+    /// let messages = subscription.fetch(10, Duration::from_secs(1)).await?;
+    /// # assert_eq!(messages.len(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn pull_subscribe(&self, subject: &str) -> io::Result<PullSubscription> {
+        self.do_pull_subscribe(subject, None).await
+    }
+
+    /// Creates a pull consumer subscription with options.
+    ///
+    /// If said consumer is named and already exists, this will attempt to bind this consumer to
+    /// that one, else will attempt to create a new internally managed consumer resource.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nats_aflowt::jetstream::PullSubscribeOptions;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let js = nats_aflowt::jetstream::new(nc);
+    /// let sub = js.pull_subscribe_with_options("foo",
+    ///       &PullSubscribeOptions::bind("existing_stream".to_string(),
+    ///       "existing_consumer".to_string())).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn pull_subscribe_with_options(
+        &self,
+        subject: &str,
+        options: &PullSubscribeOptions,
+    ) -> io::Result<PullSubscription> {
+        self.do_pull_subscribe(subject, Some(options)).await
+    }
+
+    async fn do_pull_subscribe(
+        &self,
+        subject: &str,
+        maybe_options: Option<&PullSubscribeOptions>,
+    ) -> io::Result<PullSubscription> {
+        // If no stream name is specified the subject cannot be empty.
+        if subject.is_empty()
+            && maybe_options
+                .and_then(|options| options.stream_name.as_ref())
+                .is_none()
+        {
+            return Err(io::Error::from(crate::Error::validation(
+                "Subject required",
+            )));
+        }
+
+        // Find the stream mapped to the subject if not bound to a stream already.
+        let stream_name = match maybe_options.and_then(|options| options.stream_name.to_owned()) {
+            Some(stream_name) => stream_name,
+            None => self.stream_name_by_subject(subject).await?,
+        };
+
+        let maybe_durable_name = maybe_options.and_then(|options| {
+            options.durable_name.clone().or_else(|| {
+                options
+                    .consumer_config
+                    .as_ref()
+                    .and_then(|config| config.durable_name.clone())
+            })
+        });
+
+        let maybe_consumer_name = maybe_options
+            .and_then(|options| options.consumer_name.clone())
+            .or_else(|| maybe_durable_name.clone());
+
+        // If we do have a consumer name, look it up so we can bind to it.
+        let bind_only = maybe_options.map_or(false, |options| options.bind_only);
+        let maybe_consumer_info = match maybe_consumer_name.as_ref() {
+            Some(consumer_name) => match self.consumer_info(&stream_name, consumer_name).await {
+                Ok(info) => Some(info),
+                Err(err) if bind_only || err.kind() != io::ErrorKind::Other => return Err(err),
+                Err(_) => None,
+            },
+            None => None,
+        };
+
+        let (consumer_info, consumer_ownership) = match maybe_consumer_info {
+            Some(info) => {
+                if info.config.deliver_subject.is_some() {
+                    return Err(io::Error::from(crate::Error::validation(
+                        "cannot pull subscribe to push based consumer",
+                    )));
+                }
+                (info, ConsumerOwnership::No)
+            }
+            None => {
+                let mut config = maybe_options
+                    .and_then(|options| options.consumer_config.clone())
+                    .unwrap_or_default();
+                config.deliver_subject = None;
+                config.deliver_group = None;
+                config.durable_name = maybe_durable_name;
+                if config.filter_subject.is_empty() {
+                    config.filter_subject = subject.to_string();
+                }
+
+                let info = self.add_consumer(&stream_name, &config).await?;
+                (info, ConsumerOwnership::Yes)
+            }
+        };
+
+        // Replies to each pull request use a unique subject below this inbox.
+        let inbox = self.connection.new_inbox();
+        let last_activity = Arc::new(std::sync::Mutex::new(Instant::now()));
+        let preprocessor = SubscriptionPreprocessor {
+            sequence_pair: Arc::new(Mutex::new(SequencePair::default())),
+            context: self.clone(),
+            consumer_config: consumer_info.config.clone(),
+            stream_name: consumer_info.stream_name.clone(),
+            shared_sid: Arc::new(AtomicU64::new(0)),
+            pull: true,
+            last_activity: last_activity.clone(),
+        };
+        let (sid, receiver) = self
+            .connection
+            .0
+            .client
+            .subscribe_with_preprocessor(format!("{}.*", inbox), None, Box::pin(preprocessor))
+            .await?;
+
+        Ok(PullSubscription::new(
+            sid,
+            inbox,
+            consumer_info,
+            consumer_ownership,
+            receiver,
+            last_activity,
+            self.clone(),
+        ))
+    }
+
     /// Create a `JetStream` stream.
     pub async fn add_stream<S>(&self, stream_config: S) -> io::Result<StreamInfo>
     where
@@ -1423,10 +1696,9 @@ impl JetStream {
     {
         let config: StreamConfig = stream_config.into();
         if config.name.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
         let subject: String = format!("{}STREAM.CREATE.{}", self.api_prefix(), config.name);
         let req = serde_json::ser::to_vec(&config)?;
@@ -1436,10 +1708,9 @@ impl JetStream {
     /// Update a `JetStream` stream.
     pub async fn update_stream(&self, config: &StreamConfig) -> io::Result<StreamInfo> {
         if config.name.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
         let subject: String = format!("{}STREAM.UPDATE.{}", self.api_prefix(), config.name);
         let req = serde_json::ser::to_vec(&config)?;
@@ -1492,10 +1763,9 @@ impl JetStream {
     {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
         let subject: String = format!("{}CONSUMER.LIST.{}", self.api_prefix(), stream);
 
@@ -1513,10 +1783,9 @@ impl JetStream {
     pub async fn stream_info<S: AsRef<str>>(&self, stream: S) -> io::Result<StreamInfo> {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
         let subject: String = format!("{}STREAM.INFO.{}", self.api_prefix(), stream);
         self.js_request(&subject, b"").await
@@ -1526,10 +1795,9 @@ impl JetStream {
     pub async fn purge_stream<S: AsRef<str>>(&self, stream: S) -> io::Result<PurgeResponse> {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
         let subject = format!("{}STREAM.PURGE.{}", self.api_prefix(), stream);
         self.js_request(&subject, b"").await
@@ -1543,10 +1811,9 @@ impl JetStream {
     ) -> io::Result<PurgeResponse> {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
 
         let subject = format!("{}STREAM.PURGE.{}", self.api_prefix(), stream);
@@ -1566,10 +1833,9 @@ impl JetStream {
     ) -> io::Result<StreamMessage> {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
 
         let subject = format!("{}STREAM.MSG.GET.{}", self.api_prefix(), stream);
@@ -1596,10 +1862,9 @@ impl JetStream {
     ) -> io::Result<StreamMessage> {
         let stream_name: &str = stream_name.as_ref();
         if stream_name.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
 
         let subject = format!("{}STREAM.MSG.GET.{}", self.api_prefix(), stream_name);
@@ -1626,10 +1891,9 @@ impl JetStream {
     ) -> io::Result<bool> {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
 
         let req = serde_json::ser::to_vec(&DeleteRequest {
@@ -1648,10 +1912,9 @@ impl JetStream {
     pub async fn delete_stream<S: AsRef<str>>(&self, stream: S) -> io::Result<bool> {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
 
         let subject = format!("{}STREAM.DELETE.{}", self.api_prefix(), stream);
@@ -1669,10 +1932,9 @@ impl JetStream {
         let config = ConsumerConfig::from(config);
         let stream = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
 
         let subject = if let Some(ref durable_name) = config.durable_name {
@@ -1703,17 +1965,15 @@ impl JetStream {
     {
         let stream = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
         let consumer = consumer.as_ref();
         if consumer.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the consumer name must not be empty",
-            ));
+            )));
         }
 
         let subject = format!(
@@ -1736,10 +1996,9 @@ impl JetStream {
     {
         let stream: &str = stream.as_ref();
         if stream.is_empty() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "the stream name must not be empty",
-            ));
+            )));
         }
         let consumer: &str = consumer.as_ref();
         let subject: String = format!("{}CONSUMER.INFO.{}.{}", self.api_prefix(), stream, consumer);
@@ -1780,6 +2039,40 @@ impl JetStream {
     }
 }
 
+/// Merges the expectations in `maybe_options` into the message headers.
+fn publish_headers(
+    maybe_options: Option<&PublishOptions>,
+    maybe_headers: Option<&HeaderMap>,
+) -> Option<HeaderMap> {
+    if let Some(options) = maybe_options {
+        let mut headers = maybe_headers.map_or_else(HeaderMap::default, HeaderMap::clone);
+
+        if let Some(v) = options.id.as_ref() {
+            headers.insert(header::NATS_MSG_ID, v);
+        }
+
+        if let Some(v) = options.expected_last_msg_id.as_ref() {
+            headers.insert(header::NATS_EXPECTED_LAST_MSG_ID, v);
+        }
+
+        if let Some(v) = options.expected_stream.as_ref() {
+            headers.insert(header::NATS_EXPECTED_STREAM, v);
+        }
+
+        if let Some(v) = options.expected_last_sequence.as_ref() {
+            headers.insert(header::NATS_EXPECTED_LAST_SEQUENCE, v);
+        }
+
+        if let Some(v) = options.expected_last_subject_sequence.as_ref() {
+            headers.insert(header::NATS_EXPECTED_LAST_SUBJECT_SEQUENCE, v);
+        }
+
+        Some(headers)
+    } else {
+        maybe_headers.cloned()
+    }
+}
+
 /// Creates a new `JetStream` context using the given `Connection` and default options.
 ///
 pub fn new(nc: Connection) -> JetStream {
@@ -0,0 +1,271 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::sync::{oneshot, Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    client::Client,
+    jetstream::{ApiResponse, PublishAck},
+    message::Message,
+    runtime, Error, SubscriptionReceiver,
+};
+
+/// How often expired acks are swept, and how quickly the dispatcher notices that it is
+/// no longer used.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An ack that has not arrived yet.
+struct PendingAck {
+    sender: oneshot::Sender<io::Result<PublishAck>>,
+    deadline: Instant,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Tracks the acks of messages published with `JetStream::publish_async`.
+///
+/// Every message is published with its own reply subject under `inbox`, and the
+/// server's acks are matched back to the waiting futures by that subject.
+pub(crate) struct AsyncPublisher {
+    /// Subject prefix the reply subjects of all publishes are generated under.
+    inbox: String,
+
+    /// Senders waiting for their ack, keyed by reply subject.
+    pending: Mutex<HashMap<String, PendingAck>>,
+
+    /// Limits the number of outstanding acks.
+    permits: Arc<Semaphore>,
+
+    /// Notified every time the last outstanding ack completes.
+    drained: Notify,
+
+    /// Set once acks can no longer arrive, a new publisher has to be started.
+    closed: AtomicBool,
+}
+
+impl fmt::Debug for AsyncPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("AsyncPublisher")
+            .field("inbox", &self.inbox)
+            .finish()
+    }
+}
+
+impl AsyncPublisher {
+    /// Creates a publisher with its own ack inbox, and spawns the task delivering acks to it.
+    pub(crate) async fn start(client: &Client, max_pending: usize) -> io::Result<Arc<Self>> {
+        let inbox = client.new_inbox();
+        let (sid, receiver) = client.subscribe(&format!("{}.*", inbox), None).await?;
+
+        let publisher = Arc::new(AsyncPublisher {
+            inbox,
+            pending: Mutex::new(HashMap::new()),
+            permits: Arc::new(Semaphore::new(max_pending)),
+            drained: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+
+        let weak = Arc::downgrade(&publisher);
+        let client = client.clone();
+        runtime::spawn(async move {
+            dispatch(weak, receiver).await;
+            client.unsubscribe(sid).await.ok();
+        });
+
+        Ok(publisher)
+    }
+
+    /// Waits until there is room for another outstanding ack, then registers it.
+    /// Returns the reply subject to publish with and the future resolving to the ack.
+    pub(crate) async fn register(
+        &self,
+        timeout: Duration,
+    ) -> io::Result<(String, PublishAckFuture)> {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("publish permits are never closed");
+
+        let reply = format!("{}.{}", self.inbox, nuid::next());
+        let (sender, receiver) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap();
+        // Checked under the lock, so nothing is registered after `close` drained everything.
+        if self.is_closed() {
+            return Err(Error::ConnectionClosed.into());
+        }
+        pending.insert(
+            reply.clone(),
+            PendingAck {
+                sender,
+                deadline: Instant::now() + timeout,
+                _permit: permit,
+            },
+        );
+
+        Ok((reply, PublishAckFuture { receiver }))
+    }
+
+    /// Stops waiting for the ack for `reply`, whose message could not be published.
+    pub(crate) fn forget(&self, reply: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.remove(reply).is_some() && pending.is_empty() {
+            self.drained.notify_waiters();
+        }
+    }
+
+    /// Returns `true` once the ack subscription has ended.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of acks that have not arrived yet.
+    pub(crate) fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Waits until every outstanding ack has completed.
+    pub(crate) async fn wait_complete(&self) {
+        loop {
+            let drained = self.drained.notified();
+            if self.pending() == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+
+    fn complete(&self, reply: &str, result: io::Result<PublishAck>) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(ack) = pending.remove(reply) {
+            ack.sender.send(result).ok();
+            if pending.is_empty() {
+                self.drained.notify_waiters();
+            }
+        }
+    }
+
+    fn expire(&self) {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return;
+        }
+
+        let expired: Vec<String> = pending
+            .iter()
+            .filter(|(_, ack)| ack.deadline <= now)
+            .map(|(reply, _)| reply.clone())
+            .collect();
+        for reply in expired {
+            if let Some(ack) = pending.remove(&reply) {
+                ack.sender.send(Err(Error::TimedOut.into())).ok();
+            }
+        }
+
+        if pending.is_empty() {
+            self.drained.notify_waiters();
+        }
+    }
+
+    /// Fails every outstanding ack, none of them can arrive anymore.
+    fn close(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        self.closed.store(true, Ordering::Release);
+        for (_, ack) in pending.drain() {
+            ack.sender.send(Err(Error::ConnectionClosed.into())).ok();
+        }
+        self.drained.notify_waiters();
+    }
+}
+
+/// Delivers incoming acks and expires overdue ones, for as long as the publisher
+/// is in use and its ack subscription open.
+async fn dispatch(publisher: Weak<AsyncPublisher>, receiver: SubscriptionReceiver<Message>) {
+    let mut next_sweep = Instant::now() + SWEEP_INTERVAL;
+    loop {
+        let next = tokio::select! {
+            next = receiver.recv() => next,
+            _ = runtime::sleep_until(next_sweep) => {
+                next_sweep += SWEEP_INTERVAL;
+                match publisher.upgrade() {
+                    Some(publisher) => publisher.expire(),
+                    None => return,
+                }
+                continue;
+            }
+        };
+
+        let message = match next {
+            Some(message) => message,
+            None => break,
+        };
+        let publisher = match publisher.upgrade() {
+            Some(publisher) => publisher,
+            None => return,
+        };
+
+        let result = if message.is_no_responders() {
+            Err(Error::NoResponders.into())
+        } else {
+            match serde_json::de::from_slice::<ApiResponse<PublishAck>>(&message.data) {
+                Ok(ApiResponse::Ok(ack)) => Ok(ack),
+                Ok(ApiResponse::Err { error, .. }) => {
+                    Err(io::Error::new(io::ErrorKind::Other, error))
+                }
+                Err(err) => Err(err.into()),
+            }
+        };
+        publisher.complete(&message.subject, result);
+    }
+
+    // The subscription ended, fail everything still waiting for its ack.
+    if let Some(publisher) = publisher.upgrade() {
+        publisher.close();
+    }
+}
+
+/// A future resolving to the `PublishAck` of a message published with
+/// `JetStream::publish_async`.
+///
+/// Dropping the future does not cancel the publish, the ack is still tracked
+/// by `JetStream::publish_async_complete`.
+#[derive(Debug)]
+pub struct PublishAckFuture {
+    receiver: oneshot::Receiver<io::Result<PublishAck>>,
+}
+
+impl Future for PublishAckFuture {
+    type Output = io::Result<PublishAck>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(Error::ConnectionClosed.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,700 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::Stream;
+use std::{
+    convert::TryFrom,
+    io,
+    pin::Pin,
+    sync::{Arc, PoisonError},
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    header::{self, StatusCode},
+    jetstream::{
+        AckPolicy, BatchOptions, ConsumerInfo, ConsumerOwnership, JetStream, NextRequest,
+        PullStreamOptions,
+    },
+    message::Message,
+    runtime, Error,
+};
+
+/// Extra time we wait for the server beyond the requested expiration.
+const EXPIRES_GRACE: Duration = Duration::from_millis(500);
+
+/// Status description sent when the next message would exceed `max_bytes`.
+const MAX_BYTES_EXCEEDED: &str = "Message Size Exceeds MaxBytes";
+
+/// Status descriptions after which no more messages will ever be delivered.
+const CONSUMER_GONE: [&str; 2] = ["Consumer Deleted", "Consumer is push based"];
+
+/// Something that happened while waiting for the next message of a pull stream.
+enum StreamEvent {
+    Message(Option<Box<Message>>),
+    Reconnected,
+    /// Two idle heartbeats may have been missed, unless one arrived meanwhile.
+    HeartbeatDeadline,
+    Closed,
+}
+
+#[derive(Debug)]
+pub(crate) struct Inner {
+    /// Subscription ID of the inbox wildcard subscription.
+    pub(crate) sid: u64,
+
+    /// Inbox prefix, each pull request replies to a unique subject below it.
+    pub(crate) inbox: String,
+
+    /// MSG operations received from the server.
+    pub(crate) messages: crate::SubscriptionReceiver<Message>,
+
+    /// Serializes fetches so that batches are not interleaved.
+    pub(crate) fetch_lock: Mutex<()>,
+
+    /// Name of the stream associated with the subscription.
+    pub(crate) stream: String,
+
+    /// Name of the consumer associated with the subscription.
+    pub(crate) consumer: String,
+
+    /// Ack policy used in while processing messages.
+    pub(crate) consumer_ack_policy: AckPolicy,
+
+    /// Indicates if we own the consumer and are responsible for deleting it or not.
+    pub(crate) consumer_ownership: ConsumerOwnership,
+
+    /// When the last message or idle heartbeat arrived, kept by the subscription's
+    /// preprocessor.
+    pub(crate) last_activity: Arc<std::sync::Mutex<Instant>>,
+
+    /// Client associated with subscription.
+    pub(crate) context: JetStream,
+}
+
+impl Inner {
+    /// Marks the start of a pull request, from which heartbeats are expected.
+    fn reset_activity(&self) {
+        *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+    }
+
+    /// Returns when the subscription has missed two idle heartbeats in a row.
+    fn heartbeat_deadline(&self, idle_heartbeat: Duration) -> Instant {
+        *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            + idle_heartbeat * 2
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let client = self.context.connection.0.client.clone();
+        let sid = self.sid;
+        let context = if self.consumer_ownership == ConsumerOwnership::Yes {
+            Some((
+                self.context.clone(),
+                self.stream.clone(),
+                self.consumer.clone(),
+            ))
+        } else {
+            None
+        };
+        runtime::spawn(async move {
+            client.unsubscribe(sid).await.ok();
+            // Delete the consumer, if we own it.
+            if let Some((context, stream, consumer)) = context {
+                context.delete_consumer(&stream, &consumer).await.ok();
+            }
+        });
+    }
+}
+
+/// A `PullSubscription` fetches batches of `Message`s from a pull based consumer.
+#[derive(Clone, Debug)]
+pub struct PullSubscription(pub(crate) Arc<Inner>);
+
+impl PullSubscription {
+    /// Creates a subscription.
+    pub(crate) fn new(
+        sid: u64,
+        inbox: String,
+        consumer_info: ConsumerInfo,
+        consumer_ownership: ConsumerOwnership,
+        messages: crate::SubscriptionReceiver<Message>,
+        last_activity: Arc<std::sync::Mutex<Instant>>,
+        context: JetStream,
+    ) -> PullSubscription {
+        PullSubscription(Arc::new(Inner {
+            sid,
+            inbox,
+            messages,
+            fetch_lock: Mutex::new(()),
+            stream: consumer_info.stream_name,
+            consumer: consumer_info.name,
+            consumer_ack_policy: consumer_info.config.ack_policy,
+            consumer_ownership,
+            last_activity,
+            context,
+        }))
+    }
+
+    /// Fetch up to `batch` messages, waiting at most `expires` for the batch to fill up.
+    /// Returns the messages received, which may be fewer than `batch`, or none at all.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # let name = format!("fetch_{}", rand::random::<u64>());
+    /// # context.add_stream(name.as_str()).await?;
+    /// # context.publish(&name, "hello").await?;
+    /// let subscription = context.pull_subscribe(&name).await?;
+    /// for message in subscription.fetch(10, Duration::from_secs(1)).await? {
+    ///     println!("Received {}", message);
+    ///     message.ack().await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn fetch(&self, batch: usize, expires: Duration) -> io::Result<Vec<Message>> {
+        self.fetch_with_options(BatchOptions {
+            batch,
+            expires: Some(expires),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Fetch up to `batch` messages or `max_bytes` of payload, whichever limit is reached first,
+    /// waiting at most `expires` for the batch to fill up.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # let name = format!("fetch_bytes_{}", rand::random::<u64>());
+    /// # context.add_stream(name.as_str()).await?;
+    /// # context.publish(&name, "hello").await?;
+    /// let subscription = context.pull_subscribe(&name).await?;
+    /// let messages = subscription
+    ///     .fetch_with_max_bytes(100, 1024, Duration::from_secs(1))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn fetch_with_max_bytes(
+        &self,
+        batch: usize,
+        max_bytes: usize,
+        expires: Duration,
+    ) -> io::Result<Vec<Message>> {
+        self.fetch_with_options(BatchOptions {
+            batch,
+            expires: Some(expires),
+            max_bytes: Some(max_bytes),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Fetch up to `batch` messages that are available right now, without waiting
+    /// for more to arrive.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # let name = format!("fetch_no_wait_{}", rand::random::<u64>());
+    /// # context.add_stream(name.as_str()).await?;
+    /// let subscription = context.pull_subscribe(&name).await?;
+    /// let messages = subscription.fetch_no_wait(10).await?;
+    /// assert!(messages.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn fetch_no_wait(&self, batch: usize) -> io::Result<Vec<Message>> {
+        self.fetch_with_options(BatchOptions {
+            batch,
+            no_wait: true,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Fetch a batch of messages as described by `options`.
+    ///
+    /// The batch ends when it is full, when the server reports that no more messages are
+    /// available (status 404) or that the request expired (status 408), or when the next
+    /// message would exceed `max_bytes`. Other status messages, such as 409 when the
+    /// consumer is deleted or has too many waiting requests, are returned as errors if no
+    /// messages have been received yet. Idle heartbeats are consumed internally, and a
+    /// `TimedOut` error is returned if two heartbeats in a row are missed before any
+    /// message has been received.
+    pub async fn fetch_with_options<B: Into<BatchOptions>>(
+        &self,
+        options: B,
+    ) -> io::Result<Vec<Message>> {
+        let options = options.into();
+        if options.batch == 0 {
+            return Err(io::Error::from(crate::Error::validation(
+                "batch size must be greater than zero",
+            )));
+        }
+
+        let _guard = self.0.fetch_lock.lock().await;
+
+        let reply = format!("{}.{}", self.0.inbox, nuid::next());
+        self.request_batch(&reply, &options).await?;
+        self.0.reset_activity();
+
+        let deadline = match (options.expires, options.no_wait) {
+            (Some(expires), _) => Some(Instant::now() + expires + EXPIRES_GRACE),
+            (None, true) => {
+                let client = &self.0.context.connection.0.client;
+                Some(Instant::now() + client.options.flush_timeout)
+            }
+            (None, false) => None,
+        };
+
+        let mut messages = Vec::with_capacity(options.batch);
+        loop {
+            // Idle heartbeats are consumed by the preprocessor, which tracks their arrival.
+            let heartbeat_deadline = options
+                .idle_heartbeat
+                .map(|idle_heartbeat| self.0.heartbeat_deadline(idle_heartbeat));
+            let wait_until = match (deadline, heartbeat_deadline) {
+                (Some(deadline), Some(hb)) => Some(deadline.min(hb)),
+                (deadline, hb) => deadline.or(hb),
+            };
+
+            let next = match wait_until {
+                Some(wait_until) => match runtime::timeout(
+                    wait_until.saturating_duration_since(Instant::now()),
+                    self.0.messages.recv(),
+                )
+                .await
+                {
+                    Ok(next) => next,
+                    Err(_) if matches!(deadline, Some(deadline) if Instant::now() >= deadline) => {
+                        if messages.is_empty() {
+                            return Err(crate::Error::TimedOut.into());
+                        }
+                        return Ok(messages);
+                    }
+                    // A heartbeat arrived in the meantime.
+                    Err(_)
+                        if matches!(options.idle_heartbeat,
+                            Some(idle_heartbeat) if self.0.heartbeat_deadline(idle_heartbeat) > Instant::now()) =>
+                    {
+                        continue
+                    }
+                    // Missed idle heartbeat, keep what has been received so far.
+                    Err(_) if !messages.is_empty() => return Ok(messages),
+                    Err(_) => return Err(Error::TimedOut.into()),
+                },
+                None => self.0.messages.recv().await,
+            };
+
+            let message = match next {
+                Some(message) => message,
+                None if messages.is_empty() => {
+                    return Err(io::Error::new(io::ErrorKind::Other, "fetch: unsubscribed"))
+                }
+                None => return Ok(messages),
+            };
+
+            let status = match message.status() {
+                Some(status) => status,
+                None => {
+                    messages.push(message);
+                    if messages.len() >= options.batch {
+                        return Ok(messages);
+                    }
+                    continue;
+                }
+            };
+
+            // Status messages left over from an earlier request are of no interest.
+            if message.subject != reply {
+                continue;
+            }
+
+            let description = message.description().unwrap_or_default();
+            match status {
+                // No messages, or the request expired.
+                StatusCode::NotFound | StatusCode::Timeout => return Ok(messages),
+                StatusCode::Conflict if description == MAX_BYTES_EXCEEDED => return Ok(messages),
+                StatusCode::NoResponders => return Err(crate::Error::NoResponders.into()),
+                _ if !messages.is_empty() => return Ok(messages),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("fetch: {} {}", status, description),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Returns a stream of messages that keeps pulling from the consumer using the
+    /// default `PullStreamOptions`. Same as `messages_with_options(Default::default())`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use futures::stream::StreamExt;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # context.add_stream("pull_messages").await?;
+    /// let mut messages = context.pull_subscribe("pull_messages").await?.messages();
+    /// while let Some(message) = messages.next().await {
+    ///     let message = message?;
+    ///     println!("Received {}", message);
+    ///     message.ack().await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn messages(self) -> Pin<Box<dyn Stream<Item = io::Result<Message>> + Send>> {
+        self.messages_with_options(PullStreamOptions::default())
+    }
+
+    /// Returns a stream of messages that keeps a window of pull requests outstanding,
+    /// requesting more messages once the number of undelivered messages or bytes drops
+    /// below the configured threshold.
+    ///
+    /// Missed idle heartbeats and server errors that do not end the consumer, such as
+    /// exceeding `max_waiting`, are yielded as errors and the stream keeps going. After a
+    /// missed heartbeat or a reconnect, the stream issues new pull requests because the
+    /// server forgets pending requests of a lost connection. The stream ends when the
+    /// subscription is closed or the consumer is deleted.
+    ///
+    /// While the stream is alive, calls to `fetch` on clones of this subscription wait.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use futures::stream::StreamExt;
+    /// use nats_aflowt::jetstream::PullStreamOptions;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # context.add_stream("pull_messages_options").await?;
+    /// let mut messages = context
+    ///     .pull_subscribe("pull_messages_options")
+    ///     .await?
+    ///     .messages_with_options(PullStreamOptions {
+    ///         max_messages: 100,
+    ///         max_bytes: Some(1024 * 1024),
+    ///         ..Default::default()
+    ///     });
+    /// while let Some(message) = messages.next().await {
+    ///     message?.ack().await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn messages_with_options(
+        self,
+        options: PullStreamOptions,
+    ) -> Pin<Box<dyn Stream<Item = io::Result<Message>> + Send>> {
+        Box::pin(self.into_stream(options))
+    }
+
+    // convert into unpinned stream
+    fn into_stream(self, options: PullStreamOptions) -> impl Stream<Item = io::Result<Message>> {
+        async_stream::stream! {
+            if options.max_messages == 0 {
+                yield Err(io::Error::from(crate::Error::validation("max messages must be greater than zero")));
+                return;
+            }
+            if options.idle_heartbeat.is_zero() || options.idle_heartbeat >= options.expires {
+                yield Err(io::Error::from(crate::Error::validation("idle heartbeat must be greater than zero and less than expires")));
+                return;
+            }
+
+            let _guard = self.0.fetch_lock.lock().await;
+            let mut reconnects = self.0.context.connection.0.client.reconnects();
+            let reply = format!("{}.{}", self.0.inbox, nuid::next());
+
+            let threshold_messages = options
+                .threshold_messages
+                .unwrap_or(options.max_messages / 2);
+            let threshold_bytes = options
+                .max_bytes
+                .map(|max_bytes| options.threshold_bytes.unwrap_or(max_bytes / 2));
+
+            // Messages and bytes requested from the server but not delivered yet.
+            let mut pending_messages = 0;
+            let mut pending_bytes = 0;
+
+            loop {
+                let wants_messages = pending_messages <= threshold_messages;
+                let wants_bytes =
+                    matches!(threshold_bytes, Some(threshold) if pending_bytes <= threshold);
+                if (wants_messages || wants_bytes) && pending_messages < options.max_messages {
+                    let batch = BatchOptions {
+                        batch: options.max_messages - pending_messages,
+                        expires: Some(options.expires),
+                        no_wait: false,
+                        max_bytes: options
+                            .max_bytes
+                            .map(|max_bytes| max_bytes.saturating_sub(pending_bytes)),
+                        idle_heartbeat: Some(options.idle_heartbeat),
+                    };
+                    if let Err(err) = self.request_batch(&reply, &batch).await {
+                        yield Err(err);
+                        return;
+                    }
+                    pending_messages = options.max_messages;
+                    pending_bytes = options.max_bytes.unwrap_or_default();
+                    self.0.reset_activity();
+                }
+
+                // Idle heartbeats are consumed by the preprocessor, which tracks their arrival.
+                let heartbeat_deadline = self.0.heartbeat_deadline(options.idle_heartbeat);
+
+                let event = tokio::select! {
+                    next = self.0.messages.recv() => StreamEvent::Message(next.map(Box::new)),
+                    changed = reconnects.changed() => match changed {
+                        Ok(()) => StreamEvent::Reconnected,
+                        Err(_) => StreamEvent::Closed,
+                    },
+                    _ = runtime::sleep_until(heartbeat_deadline) => StreamEvent::HeartbeatDeadline,
+                };
+
+                let message = match event {
+                    StreamEvent::Message(Some(message)) => *message,
+                    StreamEvent::Message(None) | StreamEvent::Closed => return,
+                    StreamEvent::Reconnected => {
+                        pending_messages = 0;
+                        pending_bytes = 0;
+                        continue;
+                    }
+                    StreamEvent::HeartbeatDeadline
+                        if self.0.heartbeat_deadline(options.idle_heartbeat) > Instant::now() =>
+                    {
+                        continue;
+                    }
+                    StreamEvent::HeartbeatDeadline => {
+                        pending_messages = 0;
+                        pending_bytes = 0;
+                        yield Err(Error::TimedOut.into());
+                        continue;
+                    }
+                };
+
+                let status = match message.status() {
+                    Some(status) => status,
+                    None => {
+                        pending_messages = pending_messages.saturating_sub(1);
+                        pending_bytes = pending_bytes.saturating_sub(message_size(&message));
+                        yield Ok(message);
+                        continue;
+                    }
+                };
+
+                // Status messages of other requests are of no interest.
+                if message.subject != reply {
+                    continue;
+                }
+
+                let description = message.description().unwrap_or_default();
+                match status {
+                    // A pull request ended, release whatever it had outstanding.
+                    StatusCode::NotFound | StatusCode::Timeout | StatusCode::Conflict => {
+                        let released_messages = message_header(&message, header::NATS_PENDING_MESSAGES)
+                            .and_then(|pending| pending.parse::<usize>().ok());
+                        let released_bytes = message_header(&message, header::NATS_PENDING_BYTES)
+                            .and_then(|pending| pending.parse::<usize>().ok());
+                        match released_messages {
+                            Some(released) => {
+                                pending_messages = pending_messages.saturating_sub(released);
+                                pending_bytes = pending_bytes
+                                    .saturating_sub(released_bytes.unwrap_or_default());
+                            }
+                            // Older servers do not tell, so start over.
+                            None => {
+                                pending_messages = 0;
+                                pending_bytes = 0;
+                            }
+                        }
+
+                        if status == StatusCode::Conflict && description != MAX_BYTES_EXCEEDED {
+                            let gone = CONSUMER_GONE.contains(&description);
+                            yield Err(io::Error::new(
+                                io::ErrorKind::Other,
+                                format!("pull stream: {} {}", status, description),
+                            ));
+                            if gone {
+                                return;
+                            }
+                        }
+                    }
+                    StatusCode::NoResponders => {
+                        yield Err(crate::Error::NoResponders.into());
+                        return;
+                    }
+                    _ => {
+                        yield Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("pull stream: {} {}", status, description),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends a pull request for the next batch, with replies going to `reply`.
+    async fn request_batch(&self, reply: &str, options: &BatchOptions) -> io::Result<()> {
+        let request = NextRequest {
+            batch: options.batch,
+            expires: options.expires.map(duration_to_nanos),
+            no_wait: options.no_wait,
+            max_bytes: options.max_bytes,
+            idle_heartbeat: options.idle_heartbeat.map(duration_to_nanos),
+        };
+        let subject = format!(
+            "{}CONSUMER.MSG.NEXT.{}.{}",
+            self.0.context.api_prefix(),
+            self.0.stream,
+            self.0.consumer
+        );
+
+        self.0
+            .context
+            .connection
+            .publish_with_reply_or_headers(
+                &subject,
+                Some(reply),
+                None,
+                serde_json::to_vec(&request)?,
+            )
+            .await
+    }
+
+    /// Sends a request to fetch current information about the target consumer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// #
+    /// # context.add_stream("pull_info").await?;
+    /// let subscription = context.pull_subscribe("pull_info").await?;
+    /// let info = subscription.consumer_info().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn consumer_info(&self) -> io::Result<ConsumerInfo> {
+        self.0
+            .context
+            .consumer_info(&self.0.stream, &self.0.consumer)
+            .await
+    }
+
+    /// Returns the ack policy of the consumer, messages need to be acknowledged
+    /// unless it is `AckPolicy::None`.
+    pub fn ack_policy(&self) -> AckPolicy {
+        self.0.consumer_ack_policy
+    }
+
+    /// Unsubscribe from the inbox and delete the consumer, if it was created by this
+    /// subscription. Any messages not yet fetched are discarded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let client = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// # let context = nats_aflowt::jetstream::new(client);
+    /// # context.add_stream("pull_unsubscribe").await?;
+    /// #
+    /// let subscription = context.pull_subscribe("pull_unsubscribe").await?;
+    /// subscription.unsubscribe().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn unsubscribe(self) -> io::Result<()> {
+        self.0
+            .context
+            .connection
+            .0
+            .client
+            .unsubscribe(self.0.sid)
+            .await?;
+
+        // Discard all queued messages.
+        while self.0.messages.try_recv().await.is_some() {}
+
+        // Delete the consumer, if we own it.
+        if self.0.consumer_ownership == ConsumerOwnership::Yes {
+            self.0
+                .context
+                .delete_consumer(&self.0.stream, &self.0.consumer)
+                .await
+                .ok();
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the first value of the header `name`, if present.
+fn message_header(message: &Message, name: &str) -> Option<String> {
+    message
+        .headers
+        .as_ref()
+        .and_then(|headers| headers.get(name))
+        .map(str::to_string)
+}
+
+/// Approximates the size the server accounts for a message when applying `max_bytes`.
+fn message_size(message: &Message) -> usize {
+    message.subject.len()
+        + message.reply.as_ref().map_or(0, String::len)
+        + message
+            .headers
+            .as_ref()
+            .map_or(0, |headers| headers.to_bytes().len())
+        + message.data.len()
+}
+
+fn duration_to_nanos(duration: Duration) -> usize {
+    usize::try_from(duration.as_nanos()).unwrap_or(usize::MAX)
+}
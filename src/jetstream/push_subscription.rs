@@ -21,7 +21,7 @@ use std::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
-    thread,
+    task::{Context, Poll},
     time::Duration,
 };
 #[cfg(feature = "otel")]
@@ -30,7 +30,8 @@ use tracing::error;
 use crate::{
     jetstream::{AckPolicy, ConsumerInfo, ConsumerOwnership, JetStream},
     message::Message,
-    DEFAULT_FLUSH_TIMEOUT,
+    runtime,
+    subscription::{delivered, NextMessage},
 };
 
 #[derive(Debug)]
@@ -70,7 +71,7 @@ impl Drop for Inner {
         } else {
             None
         };
-        let _ = tokio::spawn(async move {
+        runtime::spawn(async move {
             client.unsubscribe(sid.load(Ordering::Relaxed)).await.ok();
             // Delete the consumer, if we own it.
             if let Some((context, stream, consumer)) = context {
@@ -82,8 +83,11 @@ impl Drop for Inner {
 
 /// A `PushSubscription` receives `Message`s published
 /// to specific NATS `Subject`s.
+///
+/// A `PushSubscription` is also a `Stream` of its messages, without flow
+/// control and heartbeat messages.
 #[derive(Clone, Debug)]
-pub struct PushSubscription(pub(crate) Arc<Inner>);
+pub struct PushSubscription(pub(crate) Arc<Inner>, NextMessage);
 
 impl PushSubscription {
     /// Creates a subscription.
@@ -94,15 +98,18 @@ impl PushSubscription {
         messages: crate::SubscriptionReceiver<Message>,
         context: JetStream,
     ) -> PushSubscription {
-        PushSubscription(Arc::new(Inner {
-            sid,
-            stream: consumer_info.stream_name,
-            consumer: consumer_info.name,
-            consumer_ack_policy: consumer_info.config.ack_policy,
-            consumer_ownership,
-            messages,
-            context,
-        }))
+        PushSubscription(
+            Arc::new(Inner {
+                sid,
+                stream: consumer_info.stream_name,
+                consumer: consumer_info.name,
+                consumer_ack_policy: consumer_info.config.ack_policy,
+                consumer_ownership,
+                messages,
+                context,
+            }),
+            NextMessage::default(),
+        )
     }
 
     /// Preprocesses the given message.
@@ -149,7 +156,7 @@ impl PushSubscription {
                     if self.should_skip(&message).await {
                         continue;
                     }
-                    return Some(message);
+                    return Some(delivered(message));
                 }
                 None => return None,
             }
@@ -183,7 +190,7 @@ impl PushSubscription {
                     if self.should_skip(&message).await {
                         continue;
                     }
-                    return Some(message);
+                    return Some(delivered(message));
                 }
                 None => {
                     return None;
@@ -213,25 +220,20 @@ impl PushSubscription {
     /// ```
     pub async fn next_timeout(&self, timeout: Duration) -> io::Result<Message> {
         loop {
-            match tokio::time::timeout(timeout, self.0.messages.recv()).await {
+            match runtime::timeout(timeout, self.0.messages.recv()).await {
                 Ok(Some(message)) => {
                     if self.should_skip(&message).await {
                         continue;
                     }
-                    return Ok(message);
+                    return Ok(delivered(message));
                 }
                 Ok(None) => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::TimedOut,
-                        "next_timeout: timed out",
-                    ))
-                }
-                Err(_) => {
                     return Err(io::Error::new(
                         io::ErrorKind::Other,
                         "next_timeout: unsubscribed",
                     ))
                 }
+                Err(_) => return Err(crate::Error::TimedOut.into()),
             }
         }
     }
@@ -255,18 +257,8 @@ impl PushSubscription {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn messages(self) -> Pin<Box<dyn Stream<Item = Message>>> {
-        Box::pin(self.into_stream())
-    }
-
-    // convert the to unpinned stream
-    #[doc(hidden)]
-    fn into_stream(self) -> impl Stream<Item = Message> {
-        async_stream::stream! {
-            while let Some(message) = self.next().await {
-                yield message;
-            }
-        }
+    pub fn messages(self) -> Pin<Box<dyn Stream<Item = Message> + Send>> {
+        Box::pin(self)
     }
 
     /// Returns a pinned message stream.
@@ -289,8 +281,8 @@ impl PushSubscription {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Message>>> {
-        Box::pin(self.into_stream())
+    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Message> + Send>> {
+        Box::pin(self)
     }
 
     /// Attach a closure to handle messages. This closure will execute in a
@@ -323,14 +315,19 @@ impl PushSubscription {
         // dropped it will not unsubscribe from the server.
         let sub = self.clone();
         let handler = Arc::new(handler);
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             while let Some(m) = sub.next().await {
                 let handler = handler.clone();
-                let _ = tokio::task::spawn_blocking(move || {
-                    if let Err(e) = handler(m) {
-                        // TODO(dlc) - Capture for last error?
-                        log::error!("Error in callback! {:?}", e);
-                    }
+                runtime::spawn(async move {
+                    let _ = runtime::spawn_blocking(move || {
+                        #[cfg(feature = "otel")]
+                        let _span = m.span().entered();
+                        if let Err(e) = handler(m) {
+                            // TODO(dlc) - Capture for last error?
+                            log::error!("Error in callback! {:?}", e);
+                        }
+                    })
+                    .await;
                 });
             }
         });
@@ -383,11 +380,16 @@ impl PushSubscription {
     {
         let sub = self.clone();
         let handler = Arc::new(Box::new(handler));
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             while let Some(m) = sub.next().await {
                 let handler = handler.clone();
-                let _ = tokio::spawn(async move {
-                    if let Err(e) = handler(m).await {
+                runtime::spawn(async move {
+                    #[cfg(feature = "otel")]
+                    let span = m.span();
+                    let handled = handler(m);
+                    #[cfg(feature = "otel")]
+                    let handled = tracing::Instrument::instrument(handled, span);
+                    if let Err(e) = handled.await {
                         // TODO(dlc) - Capture for last error?
                         log::error!("Error in callback! {:?}", e);
                     }
@@ -397,7 +399,7 @@ impl PushSubscription {
         self
     }
 
-    /// Attach a closure to process and acknowledge messages. This closure will execute in a separate thread.
+    /// Attach a closure to process and acknowledge messages. This closure will execute on the blocking thread pool.
     ///
     /// The result of this call is a `Handler`
     /// which can not be iterated and must be unsubscribed or closed directly to
@@ -429,31 +431,32 @@ impl PushSubscription {
         // This will allow us to not have to capture the return. When it is
         // dropped it will not unsubscribe from the server.
         let sub = self.clone();
-        let handler = Arc::new(Box::new(handler));
-        thread::Builder::new()
-            .name(format!(
-                "nats_push_subscriber_{}_{}",
-                self.0.consumer, self.0.stream
-            ))
-            .spawn(move || {
-                futures::executor::block_on(async {
-                    while let Some(message) = sub.next().await {
-                        let handler = handler.clone();
-                        let _ = tokio::spawn(async move {
-                            if let Err(err) = handler(&message) {
+        let handler = Arc::new(handler);
+        runtime::spawn(async move {
+            while let Some(message) = sub.next().await {
+                let handler = handler.clone();
+                // just in case the handler blocks, we need to use blocking thread pool
+                runtime::spawn(async move {
+                    let processed = runtime::spawn_blocking(move || {
+                        #[cfg(feature = "otel")]
+                        let _span = message.span().entered();
+                        if let Err(err) = handler(&message) {
+                            log::error!("Error in callback! {:?}", err);
+                        }
+                        message
+                    })
+                    .await;
+
+                    if let Ok(message) = processed {
+                        if consumer_ack_policy != AckPolicy::None {
+                            if let Err(err) = message.ack().await {
                                 log::error!("Error in callback! {:?}", err);
                             }
-
-                            if consumer_ack_policy != AckPolicy::None {
-                                if let Err(err) = message.ack().await {
-                                    log::error!("Error in callback! {:?}", err);
-                                }
-                            }
-                        });
+                        }
                     }
-                })
-            })
-            .expect("threads should be spawnable");
+                });
+            }
+        });
         Handler { subscription: self }
     }
 
@@ -490,19 +493,27 @@ impl PushSubscription {
     {
         let ack_policy = self.0.consumer_ack_policy;
         match self.next().await {
-            Some(next) => tokio::task::spawn(async move {
-                // calling f() may block the tokio executor if f blocks.
-                // the executor is not blocked for the call to next() or the ack()
-                let result = f(&next);
-                if ack_policy != AckPolicy::None {
-                    if let Err(e) = next.ack().await {
-                        error!("ack error: {}", e);
+            Some(next) => {
+                let (sender, receiver) = tokio::sync::oneshot::channel();
+                runtime::spawn(async move {
+                    // calling f() may block the executor if f blocks.
+                    // the executor is not blocked for the call to next() or the ack()
+                    let result = {
+                        #[cfg(feature = "otel")]
+                        let _span = next.span().entered();
+                        f(&next)
+                    };
+                    if ack_policy != AckPolicy::None {
+                        if let Err(e) = next.ack().await {
+                            error!("ack error: {}", e);
+                        }
                     }
-                }
-                result
-            })
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("join error: {}", e)))?,
+                    sender.send(result).ok();
+                });
+                receiver.await.map_err(|_| {
+                    io::Error::new(io::ErrorKind::Other, "process: handler panicked")
+                })?
+            }
             _ => Err(io::Error::new(
                 io::ErrorKind::Other,
                 "process: unsubscribed",
@@ -593,13 +604,8 @@ impl PushSubscription {
     /// ```
     pub async fn unsubscribe(self) -> io::Result<()> {
         // Drain
-        self.0
-            .context
-            .connection
-            .0
-            .client
-            .flush(DEFAULT_FLUSH_TIMEOUT)
-            .await?;
+        let client = &self.0.context.connection.0.client;
+        client.flush(client.options.flush_timeout).await?;
 
         self.0
             .context
@@ -684,13 +690,8 @@ impl PushSubscription {
     /// ```
     pub async fn drain(&mut self) -> io::Result<()> {
         // Unsubscribe
-        self.0
-            .context
-            .connection
-            .0
-            .client
-            .flush(DEFAULT_FLUSH_TIMEOUT)
-            .await?;
+        let client = &self.0.context.connection.0.client;
+        client.flush(client.options.flush_timeout).await?;
 
         self.0
             .context
@@ -716,6 +717,18 @@ impl PushSubscription {
     }
 }
 
+impl Stream for PushSubscription {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        let PushSubscription(inner, next) = self.get_mut();
+        next.poll(cx, || {
+            let subscription = PushSubscription(inner.clone(), NextMessage::default());
+            Box::pin(async move { subscription.next().await })
+        })
+    }
+}
+
 /// A `Handler` may be used to unsubscribe a handler thread.
 pub struct Handler {
     subscription: PushSubscription,
@@ -637,6 +637,12 @@ impl AsRef<[u8]> for AckKind {
     }
 }
 
+impl crate::IntoPayload for AckKind {
+    fn into_payload(self) -> bytes::Bytes {
+        bytes::Bytes::copy_from_slice(self.as_ref())
+    }
+}
+
 /// Information about a consumer
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct ConsumerInfo {
@@ -700,6 +706,38 @@ pub(crate) struct NextRequest {
     /// Consumer has reached MaxAckPending limits.
     #[serde(default, skip_serializing_if = "is_default")]
     pub no_wait: bool,
+    /// The optional maximum number of bytes the server will deliver for this request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    /// The optional number of nanoseconds between idle heartbeats the server will send
+    /// while this request is pending.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_heartbeat: Option<usize>,
+}
+
+/// Options for a single batch fetched from a pull consumer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// The maximum number of messages to fetch.
+    pub batch: usize,
+    /// How long the server should keep the request pending waiting for messages.
+    pub expires: Option<Duration>,
+    /// If set, the server responds immediately with whatever messages are available
+    /// instead of waiting for the batch to fill up.
+    pub no_wait: bool,
+    /// The maximum number of payload bytes to fetch.
+    pub max_bytes: Option<usize>,
+    /// Interval of idle heartbeats the server should send while the request is pending.
+    pub idle_heartbeat: Option<Duration>,
+}
+
+impl From<usize> for BatchOptions {
+    fn from(batch: usize) -> BatchOptions {
+        BatchOptions {
+            batch,
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -934,6 +972,95 @@ impl SubscribeOptions {
     }
 }
 
+/// Options for the continuous message stream of a pull consumer.
+///
+/// The stream keeps up to `max_messages` (and `max_bytes`, if set) requested from the
+/// server at any time, and asks for more once the number of outstanding messages drops
+/// to `threshold_messages`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PullStreamOptions {
+    /// The maximum number of messages requested but not yet delivered.
+    pub max_messages: usize,
+    /// The maximum number of bytes requested but not yet delivered.
+    pub max_bytes: Option<usize>,
+    /// Re-pull once the number of outstanding messages drops to this value.
+    /// Defaults to half of `max_messages`.
+    pub threshold_messages: Option<usize>,
+    /// Re-pull once the number of outstanding bytes drops to this value.
+    /// Defaults to half of `max_bytes`.
+    pub threshold_bytes: Option<usize>,
+    /// How long each pull request stays pending on the server.
+    pub expires: Duration,
+    /// Interval of idle heartbeats sent by the server, must be smaller than `expires`.
+    pub idle_heartbeat: Duration,
+}
+
+impl Default for PullStreamOptions {
+    fn default() -> PullStreamOptions {
+        PullStreamOptions {
+            max_messages: 500,
+            max_bytes: None,
+            threshold_messages: None,
+            threshold_bytes: None,
+            expires: Duration::from_secs(30),
+            idle_heartbeat: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Options for pull subscriptions
+#[derive(Debug, Default, Clone)]
+pub struct PullSubscribeOptions {
+    pub(crate) bind_only: bool,
+    pub(crate) stream_name: Option<String>,
+    pub(crate) consumer_name: Option<String>,
+    pub(crate) durable_name: Option<String>,
+    pub(crate) consumer_config: Option<ConsumerConfig>,
+}
+
+impl PullSubscribeOptions {
+    /// Creates a new set of default pull subscription options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds to an existing pull consumer from a stream without attempting to create one.
+    pub fn bind(stream_name: String, consumer_name: String) -> Self {
+        Self {
+            stream_name: Some(stream_name),
+            consumer_name: Some(consumer_name),
+            bind_only: true,
+            ..Default::default()
+        }
+    }
+
+    /// Binds the consumer to a stream explicitly based on a name.
+    ///
+    /// When a stream name is not specified, the subject is used to look up the stream name.
+    pub fn bind_stream(stream_name: String) -> Self {
+        Self {
+            stream_name: Some(stream_name),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the durable name for the created consumer.
+    #[must_use]
+    pub fn durable_name(mut self, consumer: String) -> Self {
+        self.durable_name = Some(consumer);
+        self
+    }
+
+    /// Sets the configuration used when a new consumer is created.
+    ///
+    /// The `deliver_subject` of the configuration is ignored, pull consumers never have one.
+    #[must_use]
+    pub fn consumer_config(mut self, config: ConsumerConfig) -> Self {
+        self.consumer_config = Some(config);
+        self
+    }
+}
+
 /// Options for publishing
 #[derive(Debug, Default, Clone)]
 pub struct PublishOptions {
@@ -28,7 +28,6 @@ use crate::{
 use crate::{message::Message, Stream};
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::HashSet;
 
 /// Configuration values for key value stores.
 #[derive(Debug, Default)]
@@ -74,19 +73,11 @@ pub enum Operation {
 
 // Helper to extract key value operation from message headers
 fn kv_operation_from_maybe_headers(maybe_headers: Option<&HeaderMap>) -> Operation {
-    if let Some(headers) = maybe_headers {
-        if let Some(set) = headers.get(KV_OPERATION) {
-            if set.get(KV_OPERATION_DELETE).is_some() {
-                return Operation::Delete;
-            }
-
-            if set.get(KV_OPERATION_PURGE).is_some() {
-                return Operation::Purge;
-            }
-        }
+    match maybe_headers.and_then(|headers| headers.get(KV_OPERATION)) {
+        Some(KV_OPERATION_DELETE) => Operation::Delete,
+        Some(KV_OPERATION_PURGE) => Operation::Purge,
+        _ => Operation::Put,
     }
-
-    Operation::Put
 }
 
 fn kv_operation_from_stream_message(message: &StreamMessage) -> Operation {
@@ -135,17 +126,15 @@ impl JetStream {
     /// ```
     pub async fn key_value(&self, bucket: &str) -> io::Result<Store> {
         if !self.connection.is_server_compatible_version(2, 6, 2).await {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::from(crate::Error::validation(
                 "key-value requires at least server version 2.6.2",
-            ));
+            )));
         }
 
         if !is_valid_bucket_name(bucket) {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "invalid bucket name",
-            ));
+            )));
         }
 
         let stream_name = format!("KV_{}", bucket);
@@ -154,10 +143,9 @@ impl JetStream {
         // Do some quick sanity checks that this is a correctly formed stream for KV.
         // Max msgs per subject should be > 0.
         if stream_info.config.max_msgs_per_subject < 1 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::from(crate::Error::validation(
                 "bucket not valid key-value store",
-            ));
+            )));
         }
 
         let prefix = format!("$KV.{}.", bucket);
@@ -192,14 +180,15 @@ impl JetStream {
     /// ```
     pub async fn create_key_value(&self, config: &Config) -> io::Result<Store> {
         if !self.connection.is_server_compatible_version(2, 6, 2).await {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::from(crate::Error::validation(
                 "key-value requires at least server version 2.6.2",
-            ));
+            )));
         }
 
         if !is_valid_bucket_name(&config.bucket) {
-            return Err(io::Error::new(io::ErrorKind::Other, "invalid bucket name"));
+            return Err(io::Error::from(crate::Error::validation(
+                "invalid bucket name",
+            )));
         }
 
         self.account_info().await?;
@@ -207,10 +196,9 @@ impl JetStream {
         // Default to 1 for history. Max is 64 for now.
         let history = if config.history > 0 {
             if config.history > MAX_HISTORY {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::from(crate::Error::validation(
                     "history limited to a max of 64",
-                ));
+                )));
             }
 
             config.history
@@ -273,14 +261,15 @@ impl JetStream {
     ///
     pub async fn delete_key_value(&self, bucket: &str) -> io::Result<()> {
         if !self.connection.is_server_compatible_version(2, 6, 2).await {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::from(crate::Error::validation(
                 "key-value requires at least server version 2.6.2",
-            ));
+            )));
         }
 
         if !is_valid_bucket_name(bucket) {
-            return Err(io::Error::new(io::ErrorKind::Other, "invalid bucket name"));
+            return Err(io::Error::from(crate::Error::validation(
+                "invalid bucket name",
+            )));
         }
 
         let stream_name = format!("KV_{}", bucket);
@@ -355,7 +344,7 @@ impl Store {
     /// ```
     pub async fn entry(&self, key: &str) -> io::Result<Option<Entry>> {
         if !is_valid_key(key) {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid key"));
+            return Err(io::Error::from(crate::Error::validation("invalid key")));
         }
 
         let mut subject = String::new();
@@ -453,14 +442,14 @@ impl Store {
     /// ```
     pub async fn put(&self, key: &str, value: impl AsRef<[u8]>) -> io::Result<u64> {
         if !is_valid_key(key) {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid key"));
+            return Err(io::Error::from(crate::Error::validation("invalid key")));
         }
 
         let mut subject = String::new();
         subject.push_str(&self.prefix);
         subject.push_str(key);
 
-        let publish_ack = self.context.publish(&subject, value).await?;
+        let publish_ack = self.context.publish(&subject, value.as_ref()).await?;
 
         Ok(publish_ack.sequence)
     }
@@ -533,7 +522,7 @@ impl Store {
         revision: u64,
     ) -> io::Result<u64> {
         if !is_valid_key(key) {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid key"));
+            return Err(io::Error::from(crate::Error::validation("invalid key")));
         }
 
         let mut subject = String::new();
@@ -541,12 +530,7 @@ impl Store {
         subject.push_str(key);
 
         let mut headers = HeaderMap::default();
-        let entry = headers
-            .inner
-            .entry(header::NATS_EXPECTED_LAST_SUBJECT_SEQUENCE.to_string())
-            .or_insert_with(HashSet::default);
-
-        entry.insert(revision.to_string());
+        headers.insert(header::NATS_EXPECTED_LAST_SUBJECT_SEQUENCE, revision);
 
         let message = Message::new(&subject, None, value, Some(headers));
         let publish_ack = self.context.publish_message(&message).await?;
@@ -578,7 +562,7 @@ impl Store {
     /// ```
     pub async fn delete(&self, key: &str) -> io::Result<()> {
         if !is_valid_key(key) {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid key"));
+            return Err(io::Error::from(crate::Error::validation("invalid key")));
         }
 
         let mut subject = String::new();
@@ -586,12 +570,7 @@ impl Store {
         subject.push_str(key);
 
         let mut headers = HeaderMap::default();
-        let entry = headers
-            .inner
-            .entry(KV_OPERATION.to_string())
-            .or_insert_with(HashSet::default);
-
-        entry.insert(KV_OPERATION_DELETE.to_string());
+        headers.insert(KV_OPERATION, KV_OPERATION_DELETE);
 
         let message = Message::new(&subject, None, b"", Some(headers));
         self.context.publish_message(&message).await?;
@@ -623,7 +602,7 @@ impl Store {
     /// ```
     pub async fn purge(&self, key: &str) -> io::Result<()> {
         if !is_valid_key(key) {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid key"));
+            return Err(io::Error::from(crate::Error::validation("invalid key")));
         }
 
         let mut subject = String::new();
@@ -631,19 +610,8 @@ impl Store {
         subject.push_str(key);
 
         let mut headers = HeaderMap::default();
-        let purge_entry = headers
-            .inner
-            .entry(KV_OPERATION.to_string())
-            .or_insert_with(HashSet::default);
-
-        purge_entry.insert(KV_OPERATION_PURGE.to_string());
-
-        let rollup_entry = headers
-            .inner
-            .entry(NATS_ROLLUP.to_string())
-            .or_insert_with(HashSet::default);
-
-        rollup_entry.insert(ROLLUP_SUBJECT.to_string());
+        headers.insert(KV_OPERATION, KV_OPERATION_PURGE);
+        headers.insert(NATS_ROLLUP, ROLLUP_SUBJECT);
 
         let message = Message::new(&subject, None, b"", Some(headers));
         self.context.publish_message(&message).await?;
@@ -677,7 +645,7 @@ impl Store {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn keys(&self) -> io::Result<Pin<Box<dyn Stream<Item = String>>>> {
+    pub async fn keys(&self) -> io::Result<Pin<Box<dyn Stream<Item = String> + Send>>> {
         let mut subject = String::new();
         subject.push_str(&self.prefix);
         subject.push_str(ALL_KEYS);
@@ -736,7 +704,10 @@ impl Store {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn history(&self, key: &str) -> io::Result<Pin<Box<dyn Stream<Item = Entry>>>> {
+    pub async fn history(
+        &self,
+        key: &str,
+    ) -> io::Result<Pin<Box<dyn Stream<Item = Entry> + Send>>> {
         let mut subject = String::new();
         subject.push_str(&self.prefix);
         subject.push_str(key);
@@ -762,7 +733,7 @@ impl Store {
     }
 
     /// Returns a stream which iterates over each entry as they happen.
-    pub async fn watch_all(&self) -> io::Result<Pin<Box<dyn Stream<Item = Entry>>>> {
+    pub async fn watch_all(&self) -> io::Result<Pin<Box<dyn Stream<Item = Entry> + Send>>> {
         self.watch(">").await
     }
 
@@ -770,7 +741,7 @@ impl Store {
     pub async fn watch<T: AsRef<str>>(
         &self,
         key: T,
-    ) -> io::Result<Pin<Box<dyn Stream<Item = Entry>>>> {
+    ) -> io::Result<Pin<Box<dyn Stream<Item = Entry> + Send>>> {
         let subject = format!("{}{}", self.prefix, key.as_ref());
 
         let subscription = self
@@ -801,13 +772,13 @@ impl Store {
 /// An iterator used to iterate through the keys of a bucket.
 pub struct Keys {
     prefix: String,
-    subscription: Pin<Box<dyn Stream<Item = Message>>>,
+    subscription: Pin<Box<dyn Stream<Item = Message> + Send>>,
     done: bool,
 }
 
 impl Keys {
     /// Returns stream of keys
-    pub fn stream(self) -> Pin<Box<dyn Stream<Item = String>>> {
+    pub fn stream(self) -> Pin<Box<dyn Stream<Item = String> + Send>> {
         Box::pin(self.into_stream())
     }
 
@@ -855,13 +826,13 @@ impl Keys {
 pub struct History {
     bucket: String,
     prefix: String,
-    subscription: Pin<Box<dyn Stream<Item = Message>>>,
+    subscription: Pin<Box<dyn Stream<Item = Message> + Send>>,
     done: bool,
 }
 
 impl History {
     /// Converts to stream of history entries
-    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Entry>>> {
+    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Entry> + Send>> {
         Box::pin(self.into_stream())
     }
 
@@ -886,7 +857,7 @@ impl History {
                         yield Entry {
                             bucket: self.bucket.clone(),
                             key,
-                            value: message.data.clone(),
+                            value: message.data.to_vec(),
                             revision: info.stream_seq,
                             created: info.published,
                             delta: info.pending,
@@ -908,12 +879,12 @@ impl History {
 pub struct Watch {
     bucket: String,
     prefix: String,
-    subscription: Pin<Box<dyn Stream<Item = Message>>>,
+    subscription: Pin<Box<dyn Stream<Item = Message> + Send>>,
 }
 
 impl Watch {
     /// Convert to stream of entries
-    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Entry>>> {
+    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Entry> + Send>> {
         Box::pin(self.into_stream())
     }
 
@@ -934,7 +905,7 @@ impl Watch {
                         yield Entry {
                             bucket: self.bucket.clone(),
                             key,
-                            value: message.data.clone(),
+                            value: message.data.to_vec(),
                             revision: info.stream_seq,
                             created: info.published,
                             delta: info.pending,
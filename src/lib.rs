@@ -196,12 +196,22 @@ mod auth_utils;
 mod client;
 mod connect;
 mod connector;
+mod error;
+mod events;
 pub mod header;
 mod message;
 mod options;
+#[cfg(feature = "otel")]
+mod otel;
 mod proto;
+mod publisher;
+mod request_mux;
+mod runtime;
 mod secure_wipe;
+mod statistics;
 mod subscription;
+mod websocket;
+pub use bytes::Bytes; // re-export, the type of `Message::data`
 pub use futures::{future::BoxFuture, Stream}; // re-export of futures::Stream
 pub mod jetstream;
 pub mod kv;
@@ -232,16 +242,23 @@ pub type Headers = HeaderMap;
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::{
-    io::{self, Error, ErrorKind},
+    io::{self, ErrorKind},
+    pin::Pin,
     sync::Arc,
     time::{Duration, Instant},
 };
 
-pub use connector::{IntoServerList, ServerAddress};
+pub use connector::{IntoServerList, ServerAddress, Transport};
+pub use error::Error;
+pub use events::{ConnectionEvent, ConnectionState};
 pub use jetstream::JetStreamOptions;
-pub use message::Message;
-pub use options::{AsyncCall, AsyncCallRet, AsyncErrorCallback, Options};
-pub use subscription::{Handler, Subscription, SubscriptionReceiver};
+pub use message::{IntoPayload, Message};
+pub use options::{AsyncCall, AsyncCallRet, AsyncErrorCallback, Dialer, Options};
+pub use publisher::Publisher;
+pub use statistics::{Statistics, SubscriptionStatistics};
+pub use subscription::{
+    Handler, SlowConsumerPolicy, Subscription, SubscriptionOptions, SubscriptionReceiver,
+};
 
 /// A re-export of the `tokio_rustls` crate used in this crate,
 /// for use in cases where manual client configurations
@@ -255,11 +272,11 @@ pub use connect::ConnectInfo;
 use client::Client;
 use header::HeaderMap;
 use options::AuthStyle;
+use request_mux::RequestMux;
 use secure_wipe::{SecureString, SecureVec};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const LANG: &str = "rust";
-const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
 
 lazy_static! {
     static ref VERSION_RE: Regex = Regex::new(r#"\Av?([0-9]+)\.?([0-9]+)?\.?([0-9]+)?"#).unwrap();
@@ -339,6 +356,9 @@ pub struct Connection(pub(crate) Arc<Inner>);
 #[derive(Clone, Debug)]
 struct Inner {
     client: Client,
+
+    /// Shared reply subscription for requests, created on first use.
+    request_mux: Arc<tokio::sync::OnceCell<Arc<RequestMux>>>,
 }
 
 impl Drop for Inner {
@@ -415,10 +435,23 @@ impl Connection {
         I: IntoServerList,
     {
         crate::init_tracing();
+        if !crate::options::is_valid_inbox_prefix(&options.inbox_prefix) {
+            return Err(io::Error::from(Error::validation("invalid inbox prefix")));
+        }
+        if options.max_pings_outstanding == 0 {
+            return Err(io::Error::from(Error::validation(
+                "max_pings_outstanding must be at least 1",
+            )));
+        }
         let urls = urls.into_server_list()?;
         let client = Client::connect(urls, options).await?;
-        client.flush(DEFAULT_FLUSH_TIMEOUT).await?;
-        Ok(Connection(Arc::new(Inner { client })))
+        if !client.options.retry_on_failed_connect {
+            client.flush(client.options.flush_timeout).await?;
+        }
+        Ok(Connection(Arc::new(Inner {
+            client,
+            request_mux: Arc::new(tokio::sync::OnceCell::new()),
+        })))
     }
 
     /// Create a subscription for the given NATS connection.
@@ -451,18 +484,66 @@ impl Connection {
         self.do_subscribe(subject, Some(queue.to_string())).await
     }
 
+    /// Create a subscription with pending limits and a slow consumer policy.
+    ///
+    /// When a limit is reached the policy decides whether reading from the connection
+    /// waits for the subscriber or messages are dropped. When a subscription starts
+    /// dropping messages, an `Error::SlowConsumer` is passed to the error callback.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// use nats_aflowt::{SlowConsumerPolicy, SubscriptionOptions};
+    /// let sub = nc
+    ///     .subscribe_with_options(
+    ///         "foo",
+    ///         SubscriptionOptions::new()
+    ///             .pending_messages(100)
+    ///             .slow_consumer_policy(SlowConsumerPolicy::DropNew),
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn subscribe_with_options(
+        &self,
+        subject: &str,
+        options: SubscriptionOptions,
+    ) -> io::Result<Subscription> {
+        let (sid, receiver) = self
+            .0
+            .client
+            .subscribe_with_options(subject, &options)
+            .await?;
+        Ok(Subscription::new(
+            sid,
+            subject.to_string(),
+            receiver,
+            self.0.client.clone(),
+        ))
+    }
+
     /// Publish a message on the given subject.
     ///
+    /// The message is encoded right away and queued for the connection's writer
+    /// task, which writes out everything queued by then with a single flush.
+    ///
+    /// An owned payload, like `Bytes`, is queued without copying it. Payloads
+    /// larger than the write buffer go straight to the socket.
+    ///
     /// # Example
     /// ```
     /// # #[tokio::main]
     /// # async fn main() -> std::io::Result<()> {
     /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
     /// nc.publish("foo", "Hello World!").await?;
+    /// nc.publish("foo", nats_aflowt::Bytes::from_static(b"Hello World!")).await?;
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn publish(&self, subject: &str, msg: impl AsRef<[u8]>) -> io::Result<()> {
+    pub async fn publish(&self, subject: &str, msg: impl IntoPayload) -> io::Result<()> {
         self.publish_with_reply_or_headers(subject, None, None, msg)
             .await
     }
@@ -485,15 +566,82 @@ impl Connection {
         &self,
         subject: &str,
         reply: &str,
-        msg: impl AsRef<[u8]>,
+        msg: impl IntoPayload,
     ) -> io::Result<()> {
         self.0
             .client
-            .publish(subject, Some(reply), None, msg.as_ref())
+            .publish(subject, Some(reply), None, msg.into_payload())
             .await
     }
 
-    /// Create a new globally unique inbox which can be used for replies.
+    /// Publishes a batch of messages.
+    ///
+    /// All messages are queued to the writer task as one command and flushed
+    /// once, which is much faster than publishing them one by one. Fails without publishing anything if a message has
+    /// headers and the server does not support them.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// let events = (0..1000).map(|i| nats_aflowt::Message::new("events", None, i.to_string(), None));
+    /// nc.publish_batch(events).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn publish_batch(
+        &self,
+        messages: impl IntoIterator<Item = Message>,
+    ) -> io::Result<()> {
+        let messages: Vec<Message> = messages.into_iter().collect();
+        self.0.client.publish_batch(&messages).await
+    }
+
+    /// Attempts to publish a batch of messages without blocking.
+    ///
+    /// Messages are queued from the front of the batch for as long as the
+    /// connection's writer task has room for them. Returns the number of messages
+    /// published, or `None` if none could be published without blocking.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// let batch = vec![nats_aflowt::Message::new("events", None, "hello", None)];
+    /// if let Some(published) = nc.try_publish_batch(&batch).await {
+    ///     println!("published {} messages", published?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_publish_batch(&self, messages: &[Message]) -> Option<io::Result<usize>> {
+        self.0.client.try_publish_batch(messages).await
+    }
+
+    /// Returns a `Publisher` that collects messages and publishes them as a batch.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// let mut publisher = nc.publisher();
+    /// for i in 0..1000 {
+    ///     publisher.publish("events", i.to_string());
+    /// }
+    /// publisher.send().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publisher(&self) -> Publisher {
+        Publisher::new(self.0.client.clone())
+    }
+
+    /// Create a new globally unique inbox which can be used for replies. The
+    /// inbox starts with the prefix set by `Options::inbox_prefix`, `_INBOX` by
+    /// default.
     ///
     /// # Example
     /// ```
@@ -506,7 +654,7 @@ impl Connection {
     /// # }
     /// ```
     pub fn new_inbox(&self) -> String {
-        format!("_INBOX.{}", nuid::next())
+        self.0.client.new_inbox()
     }
 
     /// Publish a message on the given subject as a request and receive the
@@ -524,7 +672,7 @@ impl Connection {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn request(&self, subject: &str, msg: impl AsRef<[u8]>) -> io::Result<Message> {
+    pub async fn request(&self, subject: &str, msg: impl IntoPayload) -> io::Result<Message> {
         self.request_with_headers_or_timeout(subject, None, None, msg)
             .await
     }
@@ -547,43 +695,95 @@ impl Connection {
     pub async fn request_timeout(
         &self,
         subject: &str,
-        msg: impl AsRef<[u8]>,
+        msg: impl IntoPayload,
         timeout: Duration,
     ) -> io::Result<Message> {
         self.request_with_headers_or_timeout(subject, None, Some(timeout), msg)
             .await
     }
 
+    #[cfg_attr(
+        feature = "otel",
+        tracing::instrument(name = "request", skip_all, fields(otel.kind = "client", subject))
+    )]
     async fn request_with_headers_or_timeout(
         &self,
         subject: &str,
         maybe_headers: Option<&HeaderMap>,
         maybe_timeout: Option<Duration>,
-        msg: impl AsRef<[u8]>,
+        msg: impl IntoPayload,
+    ) -> io::Result<Message> {
+        let result = if self.0.client.options.old_request_style {
+            self.request_with_own_inbox(subject, maybe_headers, maybe_timeout, msg)
+                .await
+        } else {
+            self.request_with_shared_inbox(subject, maybe_headers, maybe_timeout, msg)
+                .await
+        };
+
+        // Check for no responder status.
+        if let Ok(msg) = result.as_ref() {
+            if msg.is_no_responders() {
+                return Err(Error::NoResponders.into());
+            }
+        }
+
+        result
+    }
+
+    /// Sends a request on its own short lived reply subscription.
+    async fn request_with_own_inbox(
+        &self,
+        subject: &str,
+        maybe_headers: Option<&HeaderMap>,
+        maybe_timeout: Option<Duration>,
+        msg: impl IntoPayload,
     ) -> io::Result<Message> {
         // Publish a request.
         let reply = self.new_inbox();
         let sub = self.subscribe(&reply).await?;
+        sub.auto_unsubscribe(1).await?;
         self.publish_with_reply_or_headers(subject, Some(reply.as_str()), maybe_headers, msg)
             .await?;
 
         // Wait for the response
-        let result = if let Some(timeout) = maybe_timeout {
+        if let Some(timeout) = maybe_timeout {
             sub.next_timeout(timeout).await
         } else if let Some(msg) = sub.next().await {
             Ok(msg)
         } else {
-            Err(ErrorKind::ConnectionReset.into())
-        };
-
-        // Check for no responder status.
-        if let Ok(msg) = result.as_ref() {
-            if msg.is_no_responders() {
-                return Err(Error::new(ErrorKind::NotFound, "no responders"));
-            }
+            Err(crate::Error::ConnectionClosed.into())
         }
+    }
 
-        result
+    /// Sends a request with a reply subject below the connection's shared request inbox.
+    async fn request_with_shared_inbox(
+        &self,
+        subject: &str,
+        maybe_headers: Option<&HeaderMap>,
+        maybe_timeout: Option<Duration>,
+        msg: impl IntoPayload,
+    ) -> io::Result<Message> {
+        let mux = self
+            .0
+            .request_mux
+            .get_or_try_init(|| RequestMux::start(&self.0.client))
+            .await?;
+
+        // Publish a request. The pending request is forgotten when it is dropped,
+        // whether that is after the response, a failure, a timeout or cancellation.
+        let (reply, mut pending) = mux.register()?;
+        self.publish_with_reply_or_headers(subject, Some(reply.as_str()), maybe_headers, msg)
+            .await?;
+
+        // Wait for the response
+        if let Some(timeout) = maybe_timeout {
+            runtime::timeout(timeout, pending.response())
+                .await
+                .map_err(|_| io::Error::from(Error::TimedOut))?
+        } else {
+            pending.response().await
+        }
     }
 
     /// Publish a message on the given subject as a request and allow multiple
@@ -604,7 +804,7 @@ impl Connection {
     pub async fn request_multi(
         &self,
         subject: &str,
-        msg: impl AsRef<[u8]>,
+        msg: impl IntoPayload,
     ) -> io::Result<Subscription> {
         // Publish a request.
         let reply = self.new_inbox();
@@ -617,10 +817,13 @@ impl Connection {
     }
 
     /// Flush a NATS connection by sending a `PING` protocol and waiting for the
-    /// responding `PONG`. Will fail with `TimedOut` if the server does not
-    /// respond with in 10 seconds. Will fail with `NotConnected` if the
-    /// server is not currently connected. Will fail with `BrokenPipe` if
-    /// the connection to the server is lost.
+    /// responding `PONG`. While reconnecting, the `PING` is answered once
+    /// reconnected.
+    ///
+    /// Will fail with `Error::TimedOut` if the server does not respond within
+    /// `Options::flush_timeout`. Will fail with `Error::ConnectionClosed` if the
+    /// connection to the server is lost before the `PONG` arrives or the
+    /// connection has been closed.
     ///
     /// # Example
     /// ```
@@ -632,14 +835,17 @@ impl Connection {
     /// # }
     /// ```
     pub async fn flush(&self) -> io::Result<()> {
-        self.flush_timeout(DEFAULT_FLUSH_TIMEOUT).await
+        self.flush_timeout(self.0.client.options.flush_timeout)
+            .await
     }
 
     /// Flush a NATS connection by sending a `PING` protocol and waiting for the
-    /// responding `PONG`. Will fail with `TimedOut` if the server takes
-    /// longer than this duration to respond. Will fail with `NotConnected`
-    /// if the server is not currently connected. Will fail with
-    /// `BrokenPipe` if the connection to the server is lost.
+    /// responding `PONG`, like `flush` but with the given timeout.
+    ///
+    /// Will fail with `Error::TimedOut` if the server takes longer than
+    /// `duration` to respond. Will fail with `Error::ConnectionClosed` if the
+    /// connection to the server is lost before the `PONG` arrives or the
+    /// connection has been closed.
     ///
     /// # Example
     /// ```
@@ -673,10 +879,86 @@ impl Connection {
     /// # }
     /// ```
     pub async fn close(self) {
-        self.0.client.flush(DEFAULT_FLUSH_TIMEOUT).await.ok();
+        self.0
+            .client
+            .flush(self.0.client.options.flush_timeout)
+            .await
+            .ok();
         self.0.client.close().await;
     }
 
+    /// Returns the current state of the connection.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// assert_eq!(nc.state(), nats_aflowt::ConnectionState::Connected);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn state(&self) -> ConnectionState {
+        self.0.client.events.state()
+    }
+
+    /// Returns a stream of the connection's lifecycle events, starting with the
+    /// next event. The stream ends after the connection is closed. A stream that
+    /// is not polled for a while skips the oldest events.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use futures::stream::StreamExt;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// let mut events = nc.events();
+    /// while let Some(event) = events.next().await {
+    ///     println!("connection event: {:?}", event);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(&self) -> Pin<Box<dyn Stream<Item = ConnectionEvent> + Send>> {
+        self.0.client.events.stream()
+    }
+
+    /// Returns the pool of servers the connection uses to reconnect, including
+    /// servers discovered through the cluster.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// for server in nc.servers() {
+    ///     println!("{}:{}", server.host(), server.port());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn servers(&self) -> Vec<ServerAddress> {
+        self.0.client.servers()
+    }
+
+    /// Returns a snapshot of the connection's message, byte and reconnect counters,
+    /// including the delivered and dropped counts of each subscription.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// nc.publish("foo", "hello").await?;
+    /// let stats = nc.statistics().await;
+    /// println!("published {} messages", stats.out_msgs);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn statistics(&self) -> Statistics {
+        self.0.client.statistics().await
+    }
+
     /// Calculates the round trip time between this client and the server,
     /// if the server is currently connected. Fails with `TimedOut` if
     /// the server takes more than 10 seconds to respond.
@@ -740,7 +1022,7 @@ impl Connection {
         let info = self.0.client.server_info().await;
 
         match info.client_ip.as_str() {
-            "" => Err(Error::new(
+            "" => Err(io::Error::new(
                 ErrorKind::Other,
                 &*format!(
                     "client_ip was not provided by the server. It is \
@@ -751,7 +1033,7 @@ impl Connection {
             )),
             ip => match ip.parse() {
                 Ok(addr) => Ok(addr),
-                Err(_) => Err(Error::new(
+                Err(_) => Err(io::Error::new(
                     ErrorKind::InvalidData,
                     &*format!(
                         "client_ip provided by the server cannot be parsed. \
@@ -815,13 +1097,21 @@ impl Connection {
     /// # }
     /// ```
     pub async fn drain(&self) -> io::Result<()> {
-        self.0.client.flush(DEFAULT_FLUSH_TIMEOUT).await?;
+        self.0.client.events.emit(ConnectionEvent::Draining);
+        self.0
+            .client
+            .flush(self.0.client.options.flush_timeout)
+            .await?;
         self.0.client.close().await;
         Ok(())
     }
 
     /// Publish a message which may have a reply subject or headers set.
     ///
+    /// With the `otel` feature, the trace context of the current span is added
+    /// as `traceparent` and `tracestate` headers, unless `headers` already has a
+    /// `traceparent` or the server does not support headers.
+    ///
     /// # Example
     /// ```no_run
     /// # #[tokio::main]
@@ -838,16 +1128,20 @@ impl Connection {
     /// # Ok(())
     /// # }
     /// ```
+    #[cfg_attr(
+        feature = "otel",
+        tracing::instrument(name = "publish", skip_all, fields(otel.kind = "producer", subject))
+    )]
     pub async fn publish_with_reply_or_headers(
         &self,
         subject: &str,
         reply: Option<&str>,
         headers: Option<&HeaderMap>,
-        msg: impl AsRef<[u8]>,
+        msg: impl IntoPayload,
     ) -> io::Result<()> {
         self.0
             .client
-            .publish(subject, reply, headers, msg.as_ref())
+            .publish(subject, reply, headers, msg.into_payload())
             .await
     }
 
@@ -883,11 +1177,11 @@ impl Connection {
         subject: &str,
         reply: Option<&str>,
         headers: Option<&HeaderMap>,
-        msg: impl AsRef<[u8]>,
+        msg: impl IntoPayload,
     ) -> Option<io::Result<()>> {
         self.0
             .client
-            .try_publish(subject, reply, headers, msg.as_ref())
+            .try_publish(subject, reply, headers, msg.into_payload())
             .await
     }
 }
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use crate::jetstream::AckKind;
+use bytes::Bytes;
 use std::{
     fmt, io,
     sync::{
@@ -23,11 +24,50 @@ use time::OffsetDateTime;
 
 use crate::{
     client::Client,
-    header::{self, HeaderMap},
+    header::{HeaderMap, StatusCode},
 };
 
 pub(crate) const MESSAGE_NOT_BOUND: &str = "message not bound to a connection";
 
+/// A message payload that can be published.
+///
+/// Owned buffers, `Bytes` in particular, are handed to the connection without
+/// being copied. Borrowed data is copied once.
+pub trait IntoPayload {
+    /// Converts into the payload to publish.
+    fn into_payload(self) -> Bytes;
+}
+
+impl IntoPayload for Bytes {
+    fn into_payload(self) -> Bytes {
+        self
+    }
+}
+
+impl IntoPayload for Vec<u8> {
+    fn into_payload(self) -> Bytes {
+        Bytes::from(self)
+    }
+}
+
+impl IntoPayload for String {
+    fn into_payload(self) -> Bytes {
+        Bytes::from(self)
+    }
+}
+
+impl<const N: usize> IntoPayload for [u8; N] {
+    fn into_payload(self) -> Bytes {
+        Bytes::copy_from_slice(&self)
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> IntoPayload for &T {
+    fn into_payload(self) -> Bytes {
+        Bytes::copy_from_slice(self.as_ref())
+    }
+}
+
 /// A message received on a subject.
 #[derive(Clone)]
 pub struct Message {
@@ -39,7 +79,10 @@ pub struct Message {
     pub reply: Option<String>,
 
     /// The message contents.
-    pub data: Vec<u8>,
+    ///
+    /// Received payloads share the connection's read buffer, so cloning the
+    /// message or slicing its data does not copy.
+    pub data: Bytes,
 
     /// Optional headers associated with this `Message`.
     pub headers: Option<HeaderMap>,
@@ -68,90 +111,83 @@ impl Message {
         Message {
             subject: subject.to_string(),
             reply: reply.map(String::from),
-            data: data.as_ref().to_vec(),
+            data: Bytes::copy_from_slice(data.as_ref()),
             headers,
             ..Default::default()
         }
     }
 
     /// Respond to a request message.
-    pub async fn respond(&self, msg: impl AsRef<[u8]>) -> io::Result<()> {
+    pub async fn respond(&self, msg: impl IntoPayload) -> io::Result<()> {
         let reply = self.reply.as_ref().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidInput, "No reply subject to reply to")
+            io::Error::from(crate::Error::validation("No reply subject to reply to"))
         })?;
         let client = self
             .client
             .as_ref()
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, MESSAGE_NOT_BOUND))?;
         client
-            .publish(reply.as_str(), None, None, msg.as_ref())
+            .publish(reply.as_str(), None, None, msg.into_payload())
             .await?;
         Ok(())
     }
 
+    /// Returns the status code the server set on the message, if any. Status
+    /// messages carry no payload and tell about the state of a request or
+    /// subscription, like `StatusCode::NoResponders` in reply to a request
+    /// nobody listens to, or `StatusCode::Timeout` when a pull request expires.
+    ///
+    /// # Example
+    /// ```
+    /// # use nats_aflowt::header::StatusCode;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// let sub = nc.subscribe("foo").await?;
+    /// if let Some(msg) = sub.next().await {
+    ///     match msg.status() {
+    ///         None => println!("received {:?}", msg.data),
+    ///         Some(StatusCode::Control) => println!("heartbeat"),
+    ///         Some(status) => println!("status {} {:?}", status, msg.description()),
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn status(&self) -> Option<StatusCode> {
+        self.headers
+            .as_ref()
+            .and_then(HeaderMap::status)
+            .and_then(|status| status.parse::<u16>().ok())
+            .map(StatusCode::from)
+    }
+
+    /// Returns the description that goes with the status code, like
+    /// `Idle Heartbeat` or `No Messages`.
+    pub fn description(&self) -> Option<&str> {
+        self.headers.as_ref().and_then(HeaderMap::description)
+    }
+
     /// Determine if the message is a no responders response from the server.
     pub fn is_no_responders(&self) -> bool {
-        if !self.data.is_empty() {
-            return false;
-        }
-        if let Some(hdrs) = &self.headers {
-            if let Some(set) = hdrs.get(header::STATUS) {
-                if set.get("503").is_some() {
-                    return true;
-                }
-            }
-        }
-        false
+        self.data.is_empty() && self.status() == Some(StatusCode::NoResponders)
     }
 
     // Helper for detecting flow control messages.
     pub(crate) fn is_flow_control(&self) -> bool {
-        if !self.data.is_empty() {
-            return false;
-        }
-
-        if let Some(headers) = &self.headers {
-            if let Some(set) = headers.get(header::STATUS) {
-                if set.get("100").is_none() {
-                    return false;
-                }
-            }
-
-            if let Some(set) = headers.get(header::DESCRIPTION) {
-                if set.get("Flow Control").is_some() {
-                    return true;
-                }
-
-                if set.get("FlowControl Request").is_some() {
-                    return true;
-                }
-            }
-        }
-
-        false
+        self.data.is_empty()
+            && self.status() == Some(StatusCode::Control)
+            && matches!(
+                self.description(),
+                Some("Flow Control" | "FlowControl Request")
+            )
     }
 
     // Helper for detecting idle heartbeat messages.
     pub(crate) fn is_idle_heartbeat(&self) -> bool {
-        if !self.data.is_empty() {
-            return false;
-        }
-
-        if let Some(headers) = &self.headers {
-            if let Some(set) = headers.get(header::STATUS) {
-                if set.get("100").is_none() {
-                    return false;
-                }
-            }
-
-            if let Some(set) = headers.get(header::DESCRIPTION) {
-                if set.get("Idle Heartbeat").is_some() {
-                    return true;
-                }
-            }
-        }
-
-        false
+        self.data.is_empty()
+            && self.status() == Some(StatusCode::Control)
+            && self.description() == Some("Idle Heartbeat")
     }
 
     /// Acknowledge a `JetStream` message with a default acknowledgement.
@@ -162,7 +198,17 @@ impl Message {
     ///
     /// Returns immediately if this message has already been
     /// double-acked.
+    #[cfg_attr(
+        feature = "otel",
+        tracing::instrument(
+            name = "ack",
+            skip_all,
+            fields(subject = %self.subject, stream = tracing::field::Empty, seq = tracing::field::Empty)
+        )
+    )]
     pub async fn ack(&self) -> io::Result<()> {
+        #[cfg(feature = "otel")]
+        crate::otel::record_jetstream_info(&tracing::Span::current(), self);
         if self.double_acked.load(Ordering::Acquire) {
             return Ok(());
         }
@@ -174,7 +220,17 @@ impl Message {
     /// server acks your ack, use the `double_ack` method instead.
     ///
     /// Does not check whether this message has already been double-acked.
+    #[cfg_attr(
+        feature = "otel",
+        tracing::instrument(
+            name = "ack",
+            skip_all,
+            fields(subject = %self.subject, stream = tracing::field::Empty, seq = tracing::field::Empty)
+        )
+    )]
     pub async fn ack_kind(&self, ack_kind: AckKind) -> io::Result<()> {
+        #[cfg(feature = "otel")]
+        crate::otel::record_jetstream_info(&tracing::Span::current(), self);
         self.respond(ack_kind).await
     }
 
@@ -183,16 +239,25 @@ impl Message {
     /// See `AckKind` documentation for details of what each variant means.
     ///
     /// Returns immediately if this message has already been double-acked.
+    #[cfg_attr(
+        feature = "otel",
+        tracing::instrument(
+            name = "ack",
+            skip_all,
+            fields(subject = %self.subject, stream = tracing::field::Empty, seq = tracing::field::Empty)
+        )
+    )]
     pub async fn double_ack(&self, ack_kind: AckKind) -> io::Result<()> {
+        #[cfg(feature = "otel")]
+        crate::otel::record_jetstream_info(&tracing::Span::current(), self);
         if self.double_acked.load(Ordering::Acquire) {
             return Ok(());
         }
         let original_reply = match self.reply.as_ref() {
             None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
+                return Err(io::Error::from(crate::Error::validation(
                     "No reply subject available (not a JetStream message)",
-                ))
+                )))
             }
             Some(original_reply) => original_reply,
         };
@@ -207,10 +272,10 @@ impl Message {
             if retries == 2 {
                 log::warn!("double_ack is retrying until the server connection is reestablished");
             }
-            let ack_reply = format!("_INBOX.{}", nuid::next());
+            let ack_reply = client.new_inbox();
             let sub_ret = client.subscribe(&ack_reply, None).await;
             if sub_ret.is_err() {
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                crate::runtime::sleep(std::time::Duration::from_millis(100)).await;
                 continue;
             }
             let (sid, receiver) = sub_ret?;
@@ -218,10 +283,15 @@ impl Message {
                 crate::Subscription::new(sid, ack_reply.to_string(), receiver, client.clone());
 
             let pub_ret = client
-                .publish(original_reply, Some(&ack_reply), None, ack_kind.as_ref())
+                .publish(
+                    original_reply,
+                    Some(&ack_reply),
+                    None,
+                    ack_kind.as_ref().into_payload(),
+                )
                 .await;
             if pub_ret.is_err() {
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                crate::runtime::sleep(std::time::Duration::from_millis(100)).await;
                 continue;
             }
             if sub
@@ -235,6 +305,18 @@ impl Message {
         }
     }
 
+    /// Returns a span for processing this message, whose parent is the trace
+    /// context the publisher added to its headers.
+    ///
+    /// Enter it, or instrument a future with it, so that work done for this
+    /// message is part of the publisher's trace. Handlers attached with
+    /// `with_handler` or `with_async_handler` already run inside it.
+    #[cfg(feature = "otel")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "otel")))]
+    pub fn span(&self) -> tracing::Span {
+        crate::otel::process_span(self)
+    }
+
     /// Returns the `JetStream` message ID
     /// if this is a `JetStream` message.
     /// Returns `None` if this is not
@@ -370,7 +452,7 @@ impl Default for Message {
         Message {
             subject: String::from(""),
             reply: None,
-            data: Vec::new(),
+            data: Bytes::new(),
             headers: None,
             client: None,
             double_acked: Arc::new(AtomicBool::new(false)),
@@ -25,9 +25,7 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
-    cmp,
-    collections::HashSet,
-    io,
+    cmp, io,
     pin::Pin,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -136,17 +134,15 @@ impl JetStream {
     /// ```
     pub async fn create_object_store(&self, config: &Config) -> io::Result<ObjectStore> {
         if !self.connection.is_server_compatible_version(2, 6, 2).await {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::from(crate::Error::validation(
                 "object-store requires at least server version 2.6.2",
-            ));
+            )));
         }
 
         if !is_valid_bucket_name(&config.bucket) {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "invalid bucket name",
-            ));
+            )));
         }
 
         let bucket_name = config.bucket.clone();
@@ -194,17 +190,15 @@ impl JetStream {
     /// ```
     pub async fn object_store(&self, bucket_name: &str) -> io::Result<ObjectStore> {
         if !self.connection.is_server_compatible_version(2, 6, 2).await {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::from(crate::Error::validation(
                 "object-store requires at least server version 2.6.2",
-            ));
+            )));
         }
 
         if !is_valid_bucket_name(bucket_name) {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "invalid bucket name",
-            ));
+            )));
         }
 
         let stream_name = format!("OBJ_{}", bucket_name);
@@ -312,14 +306,14 @@ struct ObjectBytes {
 /// Represents an object stored in a bucket.
 pub struct Object {
     info: ObjectInfo,
-    subscription: Pin<Box<dyn Stream<Item = Message>>>,
+    subscription: Pin<Box<dyn Stream<Item = Message> + Send>>,
     bytes: Arc<Mutex<ObjectBytes>>,
     has_pending_messages: Arc<AtomicBool>,
 }
 
 impl Object {
     pub(crate) fn new(
-        subscription: Pin<Box<dyn Stream<Item = Message>>>,
+        subscription: Pin<Box<dyn Stream<Item = Message> + Send>>,
         info: ObjectInfo,
     ) -> Self {
         Object {
@@ -433,10 +427,9 @@ impl ObjectStore {
         // Lookup the stream to get the bound subject.
         let object_name = sanitize_object_name(object_name);
         if !is_valid_object_name(&object_name) {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "invalid object name",
-            ));
+            )));
         }
 
         // Grab last meta value we have.
@@ -503,10 +496,9 @@ impl ObjectStore {
         let object_meta: ObjectMeta = meta.into();
         let object_name = sanitize_object_name(&object_meta.name);
         if !is_valid_object_name(&object_name) {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
+            return Err(io::Error::from(crate::Error::validation(
                 "invalid object name",
-            ));
+            )));
         }
 
         // Fetch any existing object info, if there is any for later use.
@@ -552,12 +544,7 @@ impl ObjectStore {
 
         let data = serde_json::to_vec(&object_info)?;
         let mut headers = HeaderMap::default();
-        let entry = headers
-            .inner
-            .entry(NATS_ROLLUP.to_string())
-            .or_insert_with(HashSet::default);
-
-        entry.insert(ROLLUP_SUBJECT.to_string());
+        headers.insert(NATS_ROLLUP, ROLLUP_SUBJECT);
 
         let message = Message::new(&subject, None, data, Some(headers));
 
@@ -667,12 +654,7 @@ impl ObjectStore {
         let data = serde_json::to_vec(&object_info)?;
 
         let mut headers = HeaderMap::default();
-        let entry = headers
-            .inner
-            .entry(NATS_ROLLUP.to_string())
-            .or_insert_with(HashSet::default);
-
-        entry.insert(ROLLUP_SUBJECT.to_string());
+        headers.insert(NATS_ROLLUP, ROLLUP_SUBJECT);
 
         let subject = format!("$O.{}.M.{}", &self.name, &object_name);
         let message = Message::new(&subject, None, data, Some(headers));
@@ -726,7 +708,7 @@ impl ObjectStore {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn watch(&self) -> io::Result<Pin<Box<dyn Stream<Item = ObjectInfo>>>> {
+    pub async fn watch(&self) -> io::Result<Pin<Box<dyn Stream<Item = ObjectInfo> + Send>>> {
         let subject = format!("$O.{}.M.>", &self.name);
         let subscription = self
             .context
@@ -747,7 +729,7 @@ impl ObjectStore {
 
 /// Iterator returned by `watch`
 pub struct Watch {
-    subscription: Pin<Box<dyn Stream<Item = Message>>>,
+    subscription: Pin<Box<dyn Stream<Item = Message> + Send>>,
 }
 
 impl Watch {
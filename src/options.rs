@@ -16,6 +16,7 @@ use std::{
     convert::TryInto,
     fmt, io,
     io::Error,
+    net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
@@ -23,7 +24,7 @@ use std::{
 
 use crate::{
     auth_utils, rustls::WantsCipherSuites, secure_wipe::SecureString, BoxFuture, Connection,
-    IntoServerList,
+    IntoServerList, ServerAddress, Transport,
 };
 
 /// Connect options.
@@ -31,14 +32,29 @@ pub struct Options {
     pub(crate) auth: AuthStyle,
     pub(crate) name: Option<String>,
     pub(crate) no_echo: bool,
+    pub(crate) old_request_style: bool,
+    pub(crate) inbox_prefix: String,
     pub(crate) max_reconnects: Option<usize>,
+    pub(crate) no_randomize: bool,
+    pub(crate) ignore_discovered_servers: bool,
+    pub(crate) retry_on_failed_connect: bool,
     pub(crate) reconnect_buffer_size: usize,
+    pub(crate) ping_interval: Duration,
+    pub(crate) max_pings_outstanding: usize,
+    pub(crate) flush_timeout: Duration,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) tcp_keepalive: Option<Duration>,
+    pub(crate) tcp_nodelay: bool,
+    pub(crate) send_buffer_size: Option<u32>,
+    pub(crate) recv_buffer_size: Option<u32>,
+    pub(crate) local_address: Option<SocketAddr>,
     pub(crate) tls_required: bool,
     pub(crate) certificates: Vec<PathBuf>,
     pub(crate) client_cert: Option<PathBuf>,
     pub(crate) client_key: Option<PathBuf>,
     pub(crate) tls_client_config:
         crate::rustls::ConfigBuilder<crate::rustls::ClientConfig, WantsCipherSuites>,
+    pub(crate) dialer: Option<Box<dyn Dialer>>,
 
     pub(crate) error_callback: ErrorCallback,
     pub(crate) disconnect_callback: Callback,
@@ -55,13 +71,38 @@ impl fmt::Debug for Options {
             .entry(&"auth", &self.auth)
             .entry(&"name", &self.name)
             .entry(&"no_echo", &self.no_echo)
+            .entry(&"old_request_style", &self.old_request_style)
+            .entry(&"inbox_prefix", &self.inbox_prefix)
             .entry(&"reconnect_buffer_size", &self.reconnect_buffer_size)
             .entry(&"max_reconnects", &self.max_reconnects)
+            .entry(&"no_randomize", &self.no_randomize)
+            .entry(
+                &"ignore_discovered_servers",
+                &self.ignore_discovered_servers,
+            )
+            .entry(&"retry_on_failed_connect", &self.retry_on_failed_connect)
+            .entry(&"ping_interval", &self.ping_interval)
+            .entry(&"max_pings_outstanding", &self.max_pings_outstanding)
+            .entry(&"flush_timeout", &self.flush_timeout)
+            .entry(&"connect_timeout", &self.connect_timeout)
+            .entry(&"tcp_keepalive", &self.tcp_keepalive)
+            .entry(&"tcp_nodelay", &self.tcp_nodelay)
+            .entry(&"send_buffer_size", &self.send_buffer_size)
+            .entry(&"recv_buffer_size", &self.recv_buffer_size)
+            .entry(&"local_address", &self.local_address)
             .entry(&"tls_required", &self.tls_required)
             .entry(&"certificates", &self.certificates)
             .entry(&"client_cert", &self.client_cert)
             .entry(&"client_key", &self.client_key)
             .entry(&"tls_client_config", &"XXXXXXXX")
+            .entry(
+                &"dialer",
+                if self.dialer.is_some() {
+                    &"set"
+                } else {
+                    &"unset"
+                },
+            )
             .entry(&"error_callback", &self.error_callback)
             .entry(&"disconnect_callback", &self.disconnect_callback)
             .entry(&"reconnect_callback", &self.reconnect_callback)
@@ -78,8 +119,22 @@ impl Default for Options {
             auth: AuthStyle::NoAuth,
             name: None,
             no_echo: false,
+            old_request_style: false,
+            inbox_prefix: "_INBOX".to_string(),
             reconnect_buffer_size: 8 * 1024 * 1024,
             max_reconnects: Some(60),
+            no_randomize: false,
+            ignore_discovered_servers: false,
+            retry_on_failed_connect: false,
+            ping_interval: Duration::from_secs(20),
+            max_pings_outstanding: 2,
+            flush_timeout: Duration::from_secs(40),
+            connect_timeout: Duration::from_secs(2),
+            tcp_keepalive: None,
+            tcp_nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            local_address: None,
             tls_required: false,
             certificates: Vec::new(),
             client_cert: None,
@@ -91,6 +146,7 @@ impl Default for Options {
             close_callback: Callback(None),
             lame_duck_callback: Callback(None),
             tls_client_config: crate::rustls::ClientConfig::builder(),
+            dialer: None,
         }
     }
 }
@@ -420,9 +476,120 @@ impl Options {
         self
     }
 
-    /// Set the maximum number of reconnect attempts.
-    /// If no servers remain that are under this threshold,
-    /// then no further reconnect shall be attempted.
+    /// Set the prefix of the inboxes the client creates for replies, in place
+    /// of `_INBOX`. Useful when subject permissions only allow replies below a
+    /// given subject. Inboxes have the form `<prefix>.<nuid>`, and are used by
+    /// requests, JetStream API calls, push consumer deliver subjects, and key
+    /// value and object store watchers.
+    ///
+    /// The prefix must be a valid subject without wildcards or a trailing `.`,
+    /// otherwise connecting fails.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .inbox_prefix("_INBOX_billing")
+    ///     .connect("127.0.0.1:14222").await?;
+    /// assert!(nc.new_inbox().starts_with("_INBOX_billing."));
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn inbox_prefix(mut self, inbox_prefix: &str) -> Options {
+        self.inbox_prefix = inbox_prefix.to_string();
+        self
+    }
+
+    /// Send every request on its own short lived reply subscription, instead of
+    /// multiplexing them over one shared `<inbox prefix>.<nuid>.*` subscription.
+    /// Useful when permissions do not allow subscribing to a wildcard inbox.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .use_old_request_style()
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn use_old_request_style(mut self) -> Options {
+        self.old_request_style = true;
+        self
+    }
+
+    /// Try servers in the order they were given and discovered, instead of
+    /// shuffling the server pool before connecting. The server that was just
+    /// lost is still tried last when reconnecting.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .no_randomize()
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn no_randomize(mut self) -> Options {
+        self.no_randomize = true;
+        self
+    }
+
+    /// Only connect to the configured servers, ignoring the cluster URLs that
+    /// servers advertise in their INFO messages.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .ignore_discovered_servers()
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn ignore_discovered_servers(mut self) -> Options {
+        self.ignore_discovered_servers = true;
+        self
+    }
+
+    /// Return the `Connection` right away when no server is reachable, and keep
+    /// connecting in the background like after a lost connection.
+    ///
+    /// Until a server is reached, the connection is in the `Reconnecting` state,
+    /// publishes go into the reconnect buffer and subscriptions are sent once
+    /// connected. If every server runs out of `max_reconnects`, the connection
+    /// closes and the error is reported to the error callback.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .retry_on_failed_connect(true)
+    ///     .connect("127.0.0.1:14222").await?;
+    /// nc.publish("foo", "sent once connected").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn retry_on_failed_connect(mut self, retry_on_failed_connect: bool) -> Options {
+        self.retry_on_failed_connect = retry_on_failed_connect;
+        self
+    }
+
+    /// Set the maximum number of reconnect attempts per server.
+    /// A server that reaches this threshold is removed from
+    /// the server pool, and if no servers remain then no
+    /// further reconnect shall be attempted.
     /// The reconnect attempt for a server is reset upon
     /// successfull connection.
     /// If None then there is no maximum number of attempts.
@@ -465,6 +632,198 @@ impl Options {
         self
     }
 
+    /// Set how often the client sends a PING to an otherwise quiet server,
+    /// to detect broken connections and keep idle ones alive.
+    ///
+    /// The default value is 20 seconds. A zero interval disables PINGs.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .ping_interval(std::time::Duration::from_secs(5))
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn ping_interval(mut self, ping_interval: Duration) -> Options {
+        self.ping_interval = ping_interval;
+        self
+    }
+
+    /// Set how many PINGs may go unanswered before the connection is
+    /// considered stale. A stale connection is reported to the error callback
+    /// as `Error::StaleConnection`, and the client reconnects.
+    ///
+    /// The default value is 2. Connecting fails with `InvalidInput` if it is 0.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .max_pings_outstanding(3)
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn max_pings_outstanding(mut self, max_pings_outstanding: usize) -> Options {
+        self.max_pings_outstanding = max_pings_outstanding;
+        self
+    }
+
+    /// Set how long `Connection::flush` waits for the server to answer its PING,
+    /// before failing with `TimedOut`.
+    ///
+    /// The default value is 40 seconds.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .flush_timeout(std::time::Duration::from_secs(5))
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn flush_timeout(mut self, flush_timeout: Duration) -> Options {
+        self.flush_timeout = flush_timeout;
+        self
+    }
+
+    /// Set how long a single connect attempt may take, covering the TCP
+    /// connect, the TLS handshake and the exchange of INFO and CONNECT.
+    /// An attempt that runs out of time fails with `ErrorKind::TimedOut` and
+    /// the next server is tried.
+    ///
+    /// The default value is 2 seconds.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .connect_timeout(std::time::Duration::from_secs(5))
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Options {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Enable TCP keepalive on the socket, sending probes after the connection
+    /// has been idle for the given time.
+    ///
+    /// Keepalive is disabled by default. Like the other socket options, this
+    /// has no effect when a custom `Dialer` is used.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .tcp_keepalive(std::time::Duration::from_secs(60))
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn tcp_keepalive(mut self, idle: Duration) -> Options {
+        self.tcp_keepalive = Some(idle);
+        self
+    }
+
+    /// Set `TCP_NODELAY` on the socket, which disables Nagle's algorithm.
+    ///
+    /// The default value is `true`.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .tcp_nodelay(false)
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn tcp_nodelay(mut self, tcp_nodelay: bool) -> Options {
+        self.tcp_nodelay = tcp_nodelay;
+        self
+    }
+
+    /// Set the size of the socket send buffer (`SO_SNDBUF`).
+    ///
+    /// By default the operating system picks the size.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .send_buffer_size(256 * 1024)
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn send_buffer_size(mut self, size: u32) -> Options {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the socket receive buffer (`SO_RCVBUF`).
+    ///
+    /// By default the operating system picks the size.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .recv_buffer_size(256 * 1024)
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn recv_buffer_size(mut self, size: u32) -> Options {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Bind the socket to a local address before connecting, for example to
+    /// pick the network interface on a multi-homed host. Use port 0 to let the
+    /// operating system choose the port.
+    ///
+    /// Server addresses that resolve to a different address family than the
+    /// local address cannot be reached.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .local_address("127.0.0.1:0".parse().unwrap())
+    ///     .connect("127.0.0.1:14222").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn local_address(mut self, local_address: SocketAddr) -> Options {
+        self.local_address = Some(local_address);
+        self
+    }
+
     /// Establish a `Connection` with a NATS server.
     ///
     /// Multiple servers may be specified by separating
@@ -754,6 +1113,53 @@ impl Options {
         self.certificates.push(path.as_ref().to_owned());
         self
     }
+
+    /// Opens connections with a custom dialer instead of TCP.
+    ///
+    /// The dialer is called for every connect and reconnect attempt and returns
+    /// the byte stream to speak the NATS protocol on, for example an in-memory
+    /// pipe, an SSH tunnel or a proxy. TLS and websocket handshakes still run on
+    /// top of the returned stream when the server requires them.
+    ///
+    /// # Example
+    /// ```no_run
+    /// struct UnixDialer;
+    /// impl nats_aflowt::Dialer for UnixDialer {
+    ///     fn dial<'a>(
+    ///         &'a self,
+    ///         _server: &'a nats_aflowt::ServerAddress,
+    ///     ) -> nats_aflowt::BoxFuture<'a, std::io::Result<Box<dyn nats_aflowt::Transport>>> {
+    ///         Box::pin(async move {
+    ///             let stream = tokio::net::UnixStream::connect("/tmp/nats.sock").await?;
+    ///             Ok(Box::new(stream) as Box<dyn nats_aflowt::Transport>)
+    ///         })
+    ///     }
+    /// }
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let nc = nats_aflowt::Options::new()
+    ///     .dialer(UnixDialer)
+    ///     .connect("localhost").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn dialer<D>(mut self, dialer: D) -> Options
+    where
+        D: Dialer + 'static,
+    {
+        self.dialer = Some(Box::new(dialer));
+        self
+    }
+}
+
+/// Checks that an inbox prefix is a subject without wildcards or empty tokens.
+pub(crate) fn is_valid_inbox_prefix(prefix: &str) -> bool {
+    !prefix.is_empty()
+        && !prefix.contains(char::is_whitespace)
+        && prefix
+            .split('.')
+            .all(|token| !token.is_empty() && token != "*" && token != ">")
 }
 
 #[derive(Clone)]
@@ -849,6 +1255,15 @@ impl ReconnectDelayCallback {
     }
 }
 
+/// Opens the byte stream for a connection to a server, see [`Options::dialer`].
+pub trait Dialer: Send + Sync {
+    /// Connects to `server`.
+    fn dial<'a>(
+        &'a self,
+        server: &'a ServerAddress,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Transport>>>;
+}
+
 /// Trait for async error handler callback
 // NB(ss): the original api pass (&Client,&Error) but Client is not exported from the nats crate,
 // so I changed the interface to accept ServerInfo
@@ -0,0 +1,114 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! W3C trace context propagation through message headers.
+//!
+//! Publishes made inside a span carry its context in the `traceparent` and
+//! `tracestate` headers, once connected to a server that supports headers, and
+//! delivered messages are traced as children of the context they carry. Spans
+//! only have a context to propagate when the application installs a
+//! `tracing-opentelemetry` layer.
+
+use std::borrow::Cow;
+
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    sdk::propagation::TraceContextPropagator,
+    trace::TraceContextExt,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{header::HeaderMap, Message};
+
+/// Header carrying the W3C trace parent.
+const TRACEPARENT: &str = "traceparent";
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key, value);
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name).collect()
+    }
+}
+
+/// Adds the context of the current span to `headers`.
+///
+/// Headers are returned unchanged when there is no context to propagate, or when
+/// they already carry a `traceparent`.
+pub(crate) fn inject_context(headers: Option<&HeaderMap>) -> Option<Cow<'_, HeaderMap>> {
+    let context = tracing::Span::current().context();
+    let has_parent = matches!(headers, Some(headers) if headers.contains_key(TRACEPARENT));
+    if has_parent || !context.span().span_context().is_valid() {
+        return headers.map(Cow::Borrowed);
+    }
+
+    let mut headers = headers.cloned().unwrap_or_default();
+    TraceContextPropagator::new().inject_context(&context, &mut HeaderInjector(&mut headers));
+    Some(Cow::Owned(headers))
+}
+
+/// Makes `span` a child of the trace context in the headers of `message`, if any.
+fn set_parent(span: &tracing::Span, message: &Message) {
+    if let Some(headers) = message.headers.as_ref() {
+        if headers.contains_key(TRACEPARENT) {
+            span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(headers)));
+        }
+    }
+}
+
+/// Records the stream and stream sequence of a `JetStream` message on `span`.
+pub(crate) fn record_jetstream_info(span: &tracing::Span, message: &Message) {
+    if let Some(info) = message.jetstream_message_info() {
+        span.record("stream", info.stream);
+        span.record("seq", info.stream_seq);
+    }
+}
+
+/// Records the delivery of `message` by a subscription, in the trace it was published in.
+pub(crate) fn delivered(message: &Message) {
+    let span = tracing::info_span!(
+        "receive",
+        otel.kind = "consumer",
+        subject = %message.subject,
+        stream = tracing::field::Empty,
+        seq = tracing::field::Empty,
+    );
+    set_parent(&span, message);
+    record_jetstream_info(&span, message);
+}
+
+/// Creates a span for processing `message`, in the trace it was published in.
+pub(crate) fn process_span(message: &Message) -> tracing::Span {
+    let span = tracing::info_span!(
+        "process",
+        otel.kind = "consumer",
+        subject = %message.subject,
+        stream = tracing::field::Empty,
+        seq = tracing::field::Empty,
+    );
+    set_parent(&span, message);
+    record_jetstream_info(&span, message);
+    span
+}
@@ -11,13 +11,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bytes::{Bytes, BytesMut};
 use std::convert::TryFrom;
 //use std::io::prelude::*;
 use std::{
     io::{self, Error, ErrorKind},
     str::{self, FromStr},
 };
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::{connect::ConnectInfo, header::HeaderMap, inject_io_failure, ServerInfo};
 
@@ -32,7 +33,7 @@ pub(crate) enum ServerOp {
         subject: String,
         sid: u64,
         reply_to: Option<String>,
-        payload: Vec<u8>,
+        payload: Bytes,
     },
 
     /// `HMSG <subject> <sid> [reply-to] <# header bytes> <# total
@@ -42,7 +43,7 @@ pub(crate) enum ServerOp {
         headers: HeaderMap,
         sid: u64,
         reply_to: Option<String>,
-        payload: Vec<u8>,
+        payload: Bytes,
     },
 
     /// `PING`
@@ -58,245 +59,251 @@ pub(crate) enum ServerOp {
     Unknown(String),
 }
 
-// adapted from `std::io::BufRead::read_until`, made
-// to use a fixed buffer instead of a growable vector.
-async fn read_line<R: AsyncBufReadExt + ?Sized + std::marker::Unpin>(
-    r: &mut R,
-    buf: &mut [u8],
-) -> io::Result<usize> {
-    let mut read = 0;
-    loop {
-        let available = match r.fill_buf().await {
-            Ok(n) => n,
-            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
-            Err(e) => return Err(e),
-        };
-        let (done, len) = {
-            if let Some(i) = memchr::memchr(b'\n', available) {
-                (true, i + 1)
-            } else {
-                buf[read..read + available.len()].copy_from_slice(available);
-                (false, available.len())
+/// Maximum length of a control line such as `MSG` or `INFO`, including `\r\n`.
+const MAX_CONTROL_LINE: usize = 4096;
+
+/// Number of bytes the read buffer grows by when it is full.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Incremental decoder of operations sent by the server.
+///
+/// Received bytes are appended to a buffer, and `decode` is called until it
+/// returns `None`, which means the rest of the next operation has not arrived yet.
+/// The decoder remembers a parsed `MSG` or `HMSG` line while it waits for the
+/// payload. Payloads are split off the buffer without copying.
+#[derive(Debug, Default)]
+pub(crate) struct Decoder {
+    /// A message whose control line was parsed, waiting for its payload.
+    pending: Option<PendingMsg>,
+}
+
+/// The parsed control line of a `MSG` or `HMSG` operation.
+#[derive(Debug)]
+struct PendingMsg {
+    subject: String,
+    sid: u64,
+    reply_to: Option<String>,
+    /// Number of header bytes, only set for `HMSG`.
+    header_len: Option<usize>,
+    /// Number of header and payload bytes.
+    total_len: usize,
+}
+
+/// Outcome of parsing a control line.
+enum Control {
+    Op(ServerOp),
+    Msg(PendingMsg),
+}
+
+impl Decoder {
+    /// Returns true if the decoder is not in the middle of an operation.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.pending.is_none()
+    }
+
+    /// Decodes the next operation from the front of `buf`, or returns `None` if
+    /// more bytes are needed.
+    pub(crate) fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<ServerOp>> {
+        if self.pending.is_none() {
+            let end = match memchr::memchr(b'\n', buf) {
+                Some(end) => end + 1,
+                None if buf.len() >= MAX_CONTROL_LINE => return Err(line_too_long()),
+                None => return Ok(None),
+            };
+            if end > MAX_CONTROL_LINE {
+                return Err(line_too_long());
             }
-        };
-        if len + read > buf.len() {
+
+            let line = buf.split_to(end);
+            let line = str::from_utf8(&line).map_err(|_| {
+                Error::new(ErrorKind::InvalidData, "control line is not valid UTF-8")
+            })?;
+            match parse_control(line.trim_end_matches(&['\r', '\n'][..]))? {
+                Control::Op(op) => return Ok(Some(op)),
+                Control::Msg(msg) => self.pending = Some(msg),
+            }
+        }
+
+        // Wait for the payload and the "\r\n" after it.
+        let total_len = self.pending.as_ref().map_or(0, |msg| msg.total_len);
+        if buf.len() < total_len + 2 {
+            return Ok(None);
+        }
+        let msg = self.pending.take().expect("checked above");
+
+        let mut data = buf.split_to(total_len + 2);
+        if &data[total_len..] != b"\r\n" {
             return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "received command exceeded fixed command buffer",
+                ErrorKind::InvalidData,
+                format!("expected \\r\\n after the payload for sid {}", msg.sid),
             ));
         }
-        buf[read..read + len].copy_from_slice(&available[..len]);
-        r.consume(len);
-        read += len;
-        if done || len == 0 {
-            return Ok(read);
-        }
+        data.truncate(total_len);
+        let mut payload = data.freeze();
+
+        let op = match msg.header_len {
+            None => ServerOp::Msg {
+                subject: msg.subject,
+                sid: msg.sid,
+                reply_to: msg.reply_to,
+                payload,
+            },
+            Some(header_len) => {
+                let headers = HeaderMap::try_from(&payload[..header_len])?;
+                payload = payload.slice(header_len..);
+                ServerOp::Hmsg {
+                    subject: msg.subject,
+                    headers,
+                    sid: msg.sid,
+                    reply_to: msg.reply_to,
+                    payload,
+                }
+            }
+        };
+        Ok(Some(op))
     }
 }
 
-/// Decodes a single operation from the server.
-///
-/// If the connection is closed, `None` will be returned.
-pub(crate) async fn decode(
-    mut stream: impl AsyncBufRead + std::marker::Unpin,
-) -> io::Result<Option<ServerOp>> {
-    // Inject random I/O failures when testing.
-    inject_io_failure()?;
-
-    // Read a line, which should be human readable.
-    #[allow(unsafe_code)]
-    #[allow(clippy::uninit_assumed_init)]
-    let mut command_buf: [u8; 4096] = unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-
-    let command_len = read_line(&mut stream, &mut command_buf).await?;
-    if command_len == 0 {
-        // If zero bytes were read, the connection is closed.
-        return Ok(None);
-    }
+fn line_too_long() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "control line exceeds the maximum of {} bytes",
+            MAX_CONTROL_LINE
+        ),
+    )
+}
 
-    // Convert into a UTF8 string for simpler parsing.
-    let line = str::from_utf8(&command_buf[..command_len])
-        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
 
+/// Parses a control line without its trailing `\r\n`.
+fn parse_control(line: &str) -> io::Result<Control> {
     let op = line
         .split_ascii_whitespace()
         .next()
         .unwrap_or("")
         .to_ascii_uppercase();
 
-    if op == "PING" {
-        return Ok(Some(ServerOp::Ping));
-    }
+    let op = match op.as_str() {
+        "PING" => ServerOp::Ping,
+        "PONG" => ServerOp::Pong,
+        "INFO" => {
+            // Parse the JSON-formatted server information.
+            let server_info = ServerInfo::parse(&line["INFO".len()..])
+                .ok_or_else(|| invalid("cannot parse server info".to_string()))?;
+            ServerOp::Info(server_info)
+        }
+        "MSG" | "HMSG" => return parse_msg(&op, &line[op.len()..]).map(Control::Msg),
+        "-ERR" => {
+            // Extract the message argument.
+            let msg = line["-ERR".len()..].trim().trim_matches('\'').to_string();
+            ServerOp::Err(msg)
+        }
+        _ => ServerOp::Unknown(line.to_owned()),
+    };
+    Ok(Control::Op(op))
+}
 
-    if op == "PONG" {
-        return Ok(Some(ServerOp::Pong));
+/// Parses the arguments of `MSG <subject> <sid> [reply-to] <#bytes>` or
+/// `HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>`.
+fn parse_msg(op: &str, args: &str) -> io::Result<PendingMsg> {
+    let args = args.split_ascii_whitespace().collect::<Vec<_>>();
+    let (subject, sid, reply_to, header_len, total_len) = match (op, &args[..]) {
+        ("MSG", [subject, sid, total_len]) => (subject, sid, None, None, total_len),
+        ("MSG", [subject, sid, reply_to, total_len]) => {
+            (subject, sid, Some(reply_to), None, total_len)
+        }
+        ("HMSG", [subject, sid, header_len, total_len]) => {
+            (subject, sid, None, Some(header_len), total_len)
+        }
+        ("HMSG", [subject, sid, reply_to, header_len, total_len]) => {
+            (subject, sid, Some(reply_to), Some(header_len), total_len)
+        }
+        _ => return Err(invalid(format!("invalid number of arguments after {}", op))),
+    };
+
+    let number = |arg: &str, name: &str| {
+        u32::from_str(arg).map_err(|_| {
+            invalid(format!(
+                "cannot parse the {} argument after {}: {:?}",
+                name, op, arg
+            ))
+        })
+    };
+    let sid = u64::from_str(sid).map_err(|_| {
+        invalid(format!(
+            "cannot parse the sid argument after {}: {:?}",
+            op, sid
+        ))
+    })?;
+    let total_len = number(total_len, "number of bytes")? as usize;
+    let header_len = match header_len {
+        Some(header_len) => Some(number(header_len, "number of header bytes")? as usize),
+        None => None,
+    };
+    if matches!(header_len, Some(header_len) if header_len > total_len) {
+        return Err(invalid(
+            "number of header bytes was greater than the total number of bytes after HMSG"
+                .to_string(),
+        ));
     }
 
-    if op == "INFO" {
-        // Parse the JSON-formatted server information.
-        let server_info = ServerInfo::parse(&line["INFO".len()..])
-            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "cannot parse server info"))?;
-
-        return Ok(Some(ServerOp::Info(server_info)));
-    }
+    Ok(PendingMsg {
+        subject: subject.to_string(),
+        sid,
+        reply_to: reply_to.map(|reply_to| reply_to.to_string()),
+        header_len,
+        total_len,
+    })
+}
 
-    if op == "MSG" {
-        // Extract whitespace-delimited arguments that come after "MSG".
-        let args = line["MSG".len()..]
-            .split_whitespace()
-            .filter(|s| !s.is_empty());
-        let args = args.collect::<Vec<_>>();
-
-        // Parse the operation syntax: MSG <subject> <sid> [reply-to] <#bytes>
-        let (subject, sid, reply_to, num_bytes) = match args[..] {
-            [subject, sid, num_bytes] => (subject, sid, None, num_bytes),
-            [subject, sid, reply_to, num_bytes] => (subject, sid, Some(reply_to), num_bytes),
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "invalid number of arguments after MSG",
-                ));
-            }
-        };
+/// Reads operations sent by the server from a stream.
+#[derive(Debug)]
+pub(crate) struct ServerOpReader<R> {
+    reader: R,
+    buf: BytesMut,
+    decoder: Decoder,
+}
 
-        // Convert the slice into an owned string.
-        let subject = subject.to_string();
-
-        // Parse the subject ID.
-        let sid = u64::from_str(sid).map_err(|_| {
-            Error::new(
-                ErrorKind::InvalidInput,
-                "cannot parse sid argument after MSG",
-            )
-        })?;
-
-        // Convert the slice into an owned string.
-        let reply_to = reply_to.map(ToString::to_string);
-
-        // Parse the number of payload bytes.
-        let num_bytes = u32::from_str(num_bytes).map_err(|_| {
-            Error::new(
-                ErrorKind::InvalidInput,
-                "cannot parse the number of bytes argument after MSG",
-            )
-        })?;
-
-        // Read the payload.
-        let mut payload = Vec::new();
-        payload.resize(num_bytes as usize, 0_u8);
-        stream.read_exact(&mut payload[..]).await?;
-        // Read "\r\n".
-        stream.read_exact(&mut [0_u8; 2]).await?;
-
-        return Ok(Some(ServerOp::Msg {
-            subject,
-            sid,
-            reply_to,
-            payload,
-        }));
+impl<R: AsyncRead + Unpin> ServerOpReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        ServerOpReader {
+            reader,
+            buf: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            decoder: Decoder::default(),
+        }
     }
 
-    if op == "HMSG" {
-        // Extract whitespace-delimited arguments that come after "HMSG".
-        let args = line["HMSG".len()..]
-            .split_whitespace()
-            .filter(|s| !s.is_empty());
-        let args = args.collect::<Vec<_>>();
-
-        // Parse the operation syntax:
-        // `HMSG <subject> <sid> [reply-to] <# header bytes>
-        // <# total bytes>\r\n<version line>\r\n[headers]\r\n\r\n[payload]\r\n`
-        let (subject, sid, reply_to, num_header_bytes, num_bytes) = match args[..] {
-            [subject, sid, num_header_bytes, num_bytes] => {
-                (subject, sid, None, num_header_bytes, num_bytes)
+    /// Reads the next operation.
+    ///
+    /// If the connection is closed, `None` will be returned.
+    pub(crate) async fn next(&mut self) -> io::Result<Option<ServerOp>> {
+        // Inject random I/O failures when testing.
+        inject_io_failure()?;
+
+        loop {
+            if let Some(op) = self.decoder.decode(&mut self.buf)? {
+                return Ok(Some(op));
             }
-            [subject, sid, reply_to, num_header_bytes, num_bytes] => {
-                (subject, sid, Some(reply_to), num_header_bytes, num_bytes)
+
+            // Payloads still referencing the buffer keep their memory, a full
+            // buffer continues in a new allocation.
+            if self.buf.capacity() == self.buf.len() {
+                self.buf.reserve(READ_BUFFER_SIZE);
             }
-            _ => {
+            if self.reader.read_buf(&mut self.buf).await? == 0 {
+                if self.buf.is_empty() && self.decoder.is_idle() {
+                    return Ok(None);
+                }
                 return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "invalid number of arguments after HMSG",
+                    ErrorKind::UnexpectedEof,
+                    "connection closed in the middle of an operation",
                 ));
             }
-        };
-
-        // Convert the slice into an owned string.
-        let subject = subject.to_string();
-
-        // Parse the subject ID.
-        let sid = u64::from_str(sid).map_err(|_| {
-            Error::new(
-                ErrorKind::InvalidInput,
-                "cannot parse sid argument after HMSG",
-            )
-        })?;
-
-        // Convert the slice into an owned string.
-        let reply_to = reply_to.map(ToString::to_string);
-
-        // Parse the number of payload bytes.
-        let num_header_bytes = u32::from_str(num_header_bytes).map_err(|_| {
-            Error::new(
-                ErrorKind::InvalidInput,
-                "cannot parse the number of header bytes argument after \
-                     HMSG",
-            )
-        })?;
-
-        // Parse the number of payload bytes.
-        let num_bytes = u32::from_str(num_bytes).map_err(|_| {
-            Error::new(
-                ErrorKind::InvalidInput,
-                "cannot parse the number of bytes argument after HMSG",
-            )
-        })?;
-
-        if num_bytes < num_header_bytes {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "number of header bytes was greater than or equal to the \
-                 total number of bytes after HMSG",
-            ));
         }
-
-        let num_payload_bytes = num_bytes - num_header_bytes;
-
-        // `HMSG <subject> <sid> [reply-to]
-        // <# header bytes> <# total bytes>\r\n
-        // <version line>\r\n[headers]\r\n\r\n[payload]\r\n`
-
-        // Read the header payload.
-        let mut header_payload = Vec::new();
-        header_payload.resize(num_header_bytes as usize, 0_u8);
-        stream.read_exact(&mut header_payload[..]).await?;
-
-        let headers = HeaderMap::try_from(&*header_payload)?;
-
-        // Read the payload.
-        let mut payload = Vec::new();
-        payload.resize(num_payload_bytes as usize, 0_u8);
-        stream.read_exact(&mut payload[..]).await?;
-        // Read "\r\n".
-        stream.read_exact(&mut [0_u8; 2]).await?;
-
-        return Ok(Some(ServerOp::Hmsg {
-            subject,
-            headers,
-            sid,
-            reply_to,
-            payload,
-        }));
-    }
-
-    if op == "-ERR" {
-        // Extract the message argument.
-        let msg = line["-ERR".len()..].trim().trim_matches('\'').to_string();
-
-        return Ok(Some(ServerOp::Err(msg)));
     }
-
-    Ok(Some(ServerOp::Unknown(line.to_owned())))
 }
 
 /// A protocol operation sent by the client.
@@ -305,21 +312,6 @@ pub(crate) enum ClientOp<'a> {
     /// `CONNECT {["option_name":option_value],...}`
     Connect(&'a ConnectInfo),
 
-    /// `PUB <subject> [reply-to] <#bytes>\r\n[payload]\r\n`
-    Pub {
-        subject: &'a str,
-        reply_to: Option<&'a str>,
-        payload: &'a [u8],
-    },
-
-    /// `HPUB <subject> [reply-to] <#bytes>\r\n[payload]\r\n`
-    Hpub {
-        subject: &'a str,
-        reply_to: Option<&'a str>,
-        headers: &'a HeaderMap,
-        payload: &'a [u8],
-    },
-
     /// `SUB <subject> [queue group] <sid>\r\n`
     Sub {
         subject: &'a str,
@@ -354,69 +346,6 @@ pub(crate) async fn encode(
             stream.write_all(op.as_bytes()).await?;
         }
 
-        ClientOp::Pub {
-            subject,
-            reply_to,
-            payload,
-        } => {
-            stream.write_all(b"PUB ").await?;
-            stream.write_all(subject.as_bytes()).await?;
-            stream.write_all(b" ").await?;
-
-            if let Some(reply_to) = reply_to {
-                stream.write_all(reply_to.as_bytes()).await?;
-                stream.write_all(b" ").await?;
-            }
-
-            let mut buf = itoa::Buffer::new();
-            stream
-                .write_all(buf.format(payload.len()).as_bytes())
-                .await?;
-            stream.write_all(b"\r\n").await?;
-
-            stream.write_all(payload).await?;
-            stream.write_all(b"\r\n").await?;
-        }
-
-        ClientOp::Hpub {
-            subject,
-            reply_to,
-            headers,
-            payload,
-        } => {
-            stream.write_all(b"HPUB ").await?;
-            stream.write_all(subject.as_bytes()).await?;
-            stream.write_all(b" ").await?;
-
-            if let Some(reply_to) = reply_to {
-                stream.write_all(reply_to.as_bytes()).await?;
-                stream.write_all(b" ").await?;
-            }
-
-            let header_bytes = headers.to_bytes();
-
-            let header_len = header_bytes.len();
-            let total_len = header_len + payload.len();
-
-            let mut hlen_buf = itoa::Buffer::new();
-            stream
-                .write_all(hlen_buf.format(header_len).as_bytes())
-                .await?;
-
-            stream.write_all(b" ").await?;
-
-            let mut tlen_buf = itoa::Buffer::new();
-            stream
-                .write_all(tlen_buf.format(total_len).as_bytes())
-                .await?;
-
-            stream.write_all(b"\r\n").await?;
-
-            stream.write_all(&header_bytes).await?;
-            stream.write_all(payload).await?;
-            stream.write_all(b"\r\n").await?;
-        }
-
         ClientOp::Sub {
             subject,
             queue_group,
@@ -450,3 +379,375 @@ pub(crate) async fn encode(
 
     Ok(())
 }
+
+/// Encodes a PUB operation, or HPUB if there are headers, as chunks to be written
+/// one after another. The payload becomes a chunk of its own, without being copied.
+pub(crate) fn encode_publish(
+    chunks: &mut Vec<Bytes>,
+    subject: &str,
+    reply_to: Option<&str>,
+    headers: Option<&HeaderMap>,
+    payload: Bytes,
+) {
+    let header_bytes = headers.map(HeaderMap::to_bytes);
+
+    let mut head = BytesMut::with_capacity(
+        32 + subject.len()
+            + reply_to.map_or(0, str::len)
+            + header_bytes.as_ref().map_or(0, Vec::len),
+    );
+    head.extend_from_slice(match header_bytes {
+        Some(_) => b"HPUB ",
+        None => b"PUB ",
+    });
+    head.extend_from_slice(subject.as_bytes());
+    head.extend_from_slice(b" ");
+    if let Some(reply_to) = reply_to {
+        head.extend_from_slice(reply_to.as_bytes());
+        head.extend_from_slice(b" ");
+    }
+
+    let mut buf = itoa::Buffer::new();
+    match &header_bytes {
+        Some(header_bytes) => {
+            head.extend_from_slice(buf.format(header_bytes.len()).as_bytes());
+            head.extend_from_slice(b" ");
+            let total_len = header_bytes.len() + payload.len();
+            head.extend_from_slice(buf.format(total_len).as_bytes());
+            head.extend_from_slice(b"\r\n");
+            head.extend_from_slice(header_bytes);
+        }
+        None => {
+            head.extend_from_slice(buf.format(payload.len()).as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+    }
+
+    chunks.push(head.freeze());
+    chunks.push(payload);
+    chunks.push(Bytes::from_static(b"\r\n"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Decodes everything in `input`, feeding it to the decoder in chunks of the given sizes.
+    fn decode_chunked(
+        input: &[u8],
+        chunks: &mut dyn FnMut() -> usize,
+    ) -> io::Result<Vec<ServerOp>> {
+        let mut decoder = Decoder::default();
+        let mut buf = BytesMut::new();
+        let mut ops = Vec::new();
+        let mut rest = input;
+        loop {
+            while let Some(op) = decoder.decode(&mut buf)? {
+                ops.push(op);
+            }
+            if rest.is_empty() {
+                break;
+            }
+            let n = chunks().clamp(1, rest.len());
+            buf.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+        }
+        if !buf.is_empty() || !decoder.is_idle() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "incomplete"));
+        }
+        Ok(ops)
+    }
+
+    fn same(a: &ServerOp, b: &ServerOp) -> bool {
+        match (a, b) {
+            (ServerOp::Info(a), ServerOp::Info(b)) => a.server_id == b.server_id,
+            (ServerOp::Ping, ServerOp::Ping) | (ServerOp::Pong, ServerOp::Pong) => true,
+            (ServerOp::Err(a), ServerOp::Err(b)) | (ServerOp::Unknown(a), ServerOp::Unknown(b)) => {
+                a == b
+            }
+            (
+                ServerOp::Msg {
+                    subject,
+                    sid,
+                    reply_to,
+                    payload,
+                },
+                ServerOp::Msg {
+                    subject: subject_b,
+                    sid: sid_b,
+                    reply_to: reply_to_b,
+                    payload: payload_b,
+                },
+            ) => {
+                subject == subject_b
+                    && sid == sid_b
+                    && reply_to == reply_to_b
+                    && payload == payload_b
+            }
+            (
+                ServerOp::Hmsg {
+                    subject,
+                    headers,
+                    sid,
+                    reply_to,
+                    payload,
+                },
+                ServerOp::Hmsg {
+                    subject: subject_b,
+                    headers: headers_b,
+                    sid: sid_b,
+                    reply_to: reply_to_b,
+                    payload: payload_b,
+                },
+            ) => {
+                subject == subject_b
+                    && headers == headers_b
+                    && sid == sid_b
+                    && reply_to == reply_to_b
+                    && payload == payload_b
+            }
+            _ => false,
+        }
+    }
+
+    fn random_token(rng: &mut StdRng) -> String {
+        let len = rng.gen_range(1..12);
+        (0..len)
+            .map(|_| char::from(rng.gen_range(b'a'..=b'z')))
+            .collect()
+    }
+
+    /// Generates a random valid operation and its encoding.
+    fn random_op(rng: &mut StdRng) -> (Vec<u8>, ServerOp) {
+        let payload: Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+        let subject = random_token(rng);
+        let sid = rng.gen_range(0..1000);
+        let reply_to = if rng.gen() {
+            Some(random_token(rng))
+        } else {
+            None
+        };
+        let reply = reply_to
+            .as_ref()
+            .map(|reply| format!(" {}", reply))
+            .unwrap_or_default();
+
+        match rng.gen_range(0..6) {
+            0 => (b"PING\r\n".to_vec(), ServerOp::Ping),
+            1 => (b"pong\r\n".to_vec(), ServerOp::Pong),
+            2 => {
+                let line = format!("INFO {{\"server_id\":\"{}\",\"server_name\":\"n\",\"host\":\"h\",\"port\":4222,\"version\":\"2.8.0\",\"go\":\"go\",\"max_payload\":1024,\"proto\":1,\"client_id\":1}}\r\n", subject);
+                let info = ServerInfo::parse(line["INFO".len()..].trim_end()).unwrap();
+                (line.into_bytes(), ServerOp::Info(info))
+            }
+            3 => (
+                format!("-ERR '{}'\r\n", subject).into_bytes(),
+                ServerOp::Err(subject),
+            ),
+            4 => {
+                let mut bytes =
+                    format!("MSG {} {}{} {}\r\n", subject, sid, reply, payload.len()).into_bytes();
+                bytes.extend_from_slice(&payload);
+                bytes.extend_from_slice(b"\r\n");
+                let op = ServerOp::Msg {
+                    subject,
+                    sid,
+                    reply_to,
+                    payload: Bytes::from(payload),
+                };
+                (bytes, op)
+            }
+            _ => {
+                let headers = format!(
+                    "NATS/1.0\r\n{}: {}\r\n\r\n",
+                    random_token(rng),
+                    random_token(rng)
+                );
+                let mut bytes = format!(
+                    "HMSG {} {}{} {} {}\r\n",
+                    subject,
+                    sid,
+                    reply,
+                    headers.len(),
+                    headers.len() + payload.len()
+                )
+                .into_bytes();
+                bytes.extend_from_slice(headers.as_bytes());
+                bytes.extend_from_slice(&payload);
+                bytes.extend_from_slice(b"\r\n");
+                let op = ServerOp::Hmsg {
+                    subject,
+                    headers: HeaderMap::try_from(headers.as_bytes()).unwrap(),
+                    sid,
+                    reply_to,
+                    payload: Bytes::from(payload),
+                };
+                (bytes, op)
+            }
+        }
+    }
+
+    #[test]
+    fn pipelined_and_partial() {
+        let input: &[u8] =
+            b"PING\r\nMSG foo 1 5\r\nhello\r\nHMSG bar 2 _INBOX.1 18 23\r\nNATS/1.0\r\nA: B\r\n\r\nworld\r\n-ERR 'oops'\r\n+OK\r\n";
+
+        // Whole, then one byte at a time.
+        for chunk in [input.len(), 1] {
+            let ops = decode_chunked(input, &mut || chunk).unwrap();
+            assert_eq!(ops.len(), 5, "{:?}", ops);
+            assert!(matches!(ops[0], ServerOp::Ping));
+            assert!(
+                matches!(&ops[1], ServerOp::Msg { subject, sid: 1, payload, .. } if subject == "foo" && payload == "hello")
+            );
+            assert!(
+                matches!(&ops[2], ServerOp::Hmsg { subject, reply_to: Some(reply), headers, payload, .. }
+                    if subject == "bar" && reply == "_INBOX.1" && headers.get("A").is_some() && payload == "world")
+            );
+            assert!(matches!(&ops[3], ServerOp::Err(msg) if msg == "oops"));
+            assert!(matches!(&ops[4], ServerOp::Unknown(line) if line == "+OK"));
+        }
+    }
+
+    #[test]
+    fn payloads_share_buffer() {
+        let mut buf = BytesMut::from(&b"MSG foo 1 5\r\nhello\r\nMSG bar 2 5\r\nworld\r\n"[..]);
+        let mut decoder = Decoder::default();
+        let payload = |op| match op {
+            Some(ServerOp::Msg { payload, .. }) => payload,
+            op => panic!("unexpected {:?}", op),
+        };
+        let first = payload(decoder.decode(&mut buf).unwrap());
+        let second = payload(decoder.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+        assert_eq!(first, "hello");
+        assert_eq!(second, "world");
+        // Payloads are slices of the read buffer.
+        assert_eq!(
+            first.as_ptr().wrapping_add(first.len() + 15),
+            second.as_ptr()
+        );
+    }
+
+    #[test]
+    fn precise_errors() {
+        let error = |input: &[u8]| {
+            let err = decode_chunked(input, &mut || input.len()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+            err.to_string()
+        };
+
+        let long = vec![b'A'; MAX_CONTROL_LINE + 1];
+        assert!(error(&long).contains("exceeds the maximum of 4096 bytes"));
+        let mut long_line = long;
+        long_line.extend_from_slice(b"\r\n");
+        assert!(error(&long_line).contains("exceeds the maximum"));
+
+        assert!(error(b"MSG foo\r\n").contains("invalid number of arguments after MSG"));
+        assert!(error(b"MSG foo x 5\r\nhello\r\n").contains("sid argument after MSG"));
+        assert!(error(b"MSG foo 1 -5\r\n").contains("number of bytes argument after MSG"));
+        assert!(error(b"HMSG foo 1 10 5\r\n").contains("greater than the total"));
+        assert!(error(b"MSG foo 1 5\r\nhelloXX").contains("expected \\r\\n after the payload"));
+        assert!(error(b"INFO {\r\n").contains("cannot parse server info"));
+        assert!(error(b"\xff\xfe\r\n").contains("not valid UTF-8"));
+    }
+
+    #[tokio::test]
+    async fn reader_eof() {
+        let mut reader = ServerOpReader::new(&b"PONG\r\n"[..]);
+        assert!(matches!(reader.next().await.unwrap(), Some(ServerOp::Pong)));
+        assert!(reader.next().await.unwrap().is_none());
+
+        let mut reader = ServerOpReader::new(&b"MSG foo 1 5\r\nhel"[..]);
+        let err = reader.next().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn roundtrip_random_ops_in_random_chunks() {
+        let mut rng = StdRng::seed_from_u64(0x6e617473);
+        for _ in 0..500 {
+            let mut input = Vec::new();
+            let mut expected = Vec::new();
+            for _ in 0..rng.gen_range(1..8) {
+                let (bytes, op) = random_op(&mut rng);
+                input.extend_from_slice(&bytes);
+                expected.push(op);
+            }
+
+            let max_chunk = rng.gen_range(1..input.len() + 1);
+            let mut chunk_rng = StdRng::seed_from_u64(rng.gen());
+            let ops = decode_chunked(&input, &mut || chunk_rng.gen_range(1..=max_chunk)).unwrap();
+            assert_eq!(ops.len(), expected.len());
+            for (op, expected) in ops.iter().zip(&expected) {
+                assert!(same(op, expected), "{:?} != {:?}", op, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_input_never_panics() {
+        let mut rng = StdRng::seed_from_u64(0x666f6f);
+        for _ in 0..2000 {
+            let mut input = Vec::new();
+            for _ in 0..rng.gen_range(1..4) {
+                input.extend_from_slice(&random_op(&mut rng).0);
+            }
+
+            // Corrupt the valid input.
+            for _ in 0..rng.gen_range(1..6) {
+                let at = rng.gen_range(0..input.len());
+                match rng.gen_range(0..5) {
+                    0 => input[at] = rng.gen(),
+                    1 => input[at] = *b" \r\n0-9".get(rng.gen_range(0..6)).unwrap(),
+                    2 => input.truncate(at),
+                    3 => {
+                        input.insert(at, rng.gen());
+                    }
+                    _ => {
+                        input.remove(at);
+                    }
+                }
+                if input.is_empty() {
+                    input.push(rng.gen());
+                }
+            }
+
+            let max_chunk = rng.gen_range(1..input.len() + 1);
+            let _ = decode_chunked(&input, &mut || rng.gen_range(1..=max_chunk));
+        }
+
+        // Pure noise.
+        for _ in 0..500 {
+            let input: Vec<u8> = (0..rng.gen_range(1..256)).map(|_| rng.gen()).collect();
+            let _ = decode_chunked(&input, &mut || input.len());
+        }
+    }
+
+    #[test]
+    fn encode_publish_keeps_payload() {
+        let payload = Bytes::from(b"hello".to_vec());
+        let mut chunks = Vec::new();
+        encode_publish(&mut chunks, "foo", Some("bar"), None, payload.clone());
+        assert_eq!(chunks.concat(), b"PUB foo bar 5\r\nhello\r\n");
+        // The payload is shared, not copied.
+        assert_eq!(chunks[1].as_ptr(), payload.as_ptr());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("A", "b");
+        let header_bytes = headers.to_bytes();
+        let mut chunks = Vec::new();
+        encode_publish(&mut chunks, "foo", None, Some(&headers), payload.clone());
+        let mut expected = format!(
+            "HPUB foo {} {}\r\n",
+            header_bytes.len(),
+            header_bytes.len() + payload.len()
+        )
+        .into_bytes();
+        expected.extend_from_slice(&header_bytes);
+        expected.extend_from_slice(b"hello\r\n");
+        assert_eq!(chunks.concat(), expected);
+        assert_eq!(chunks[1].as_ptr(), payload.as_ptr());
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{fmt, io};
+
+use crate::{client::Client, header::HeaderMap, Message};
+
+/// Collects messages and publishes them as one batch, as returned by
+/// `Connection::publisher`.
+///
+/// Messages are only queued in memory until `send` or `try_send` is called.
+pub struct Publisher {
+    client: Client,
+    messages: Vec<Message>,
+}
+
+impl fmt::Debug for Publisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Publisher")
+            .field("messages", &self.messages.len())
+            .finish()
+    }
+}
+
+impl Publisher {
+    pub(crate) fn new(client: Client) -> Publisher {
+        Publisher {
+            client,
+            messages: Vec::new(),
+        }
+    }
+
+    /// Queues a message on the given subject.
+    pub fn publish(&mut self, subject: &str, msg: impl AsRef<[u8]>) {
+        self.push(Message::new(subject, None, msg, None));
+    }
+
+    /// Queues a message with an optional reply subject and headers.
+    pub fn publish_with_reply_or_headers(
+        &mut self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&HeaderMap>,
+        msg: impl AsRef<[u8]>,
+    ) {
+        self.push(Message::new(subject, reply, msg, headers.cloned()));
+    }
+
+    /// Queues a message.
+    pub fn push(&mut self, message: Message) {
+        self.messages.push(message);
+    }
+
+    /// Returns the number of queued messages.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns true if no messages are queued.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Publishes all queued messages, see `Connection::publish_batch`.
+    ///
+    /// The queue is emptied, also if publishing fails.
+    pub async fn send(&mut self) -> io::Result<()> {
+        let messages = std::mem::take(&mut self.messages);
+        self.client.publish_batch(&messages).await
+    }
+
+    /// Publishes queued messages without blocking, see `Connection::try_publish_batch`.
+    ///
+    /// Published messages are removed from the queue, the rest stays queued for
+    /// the next call. Returns the number of messages published, or `None` if
+    /// none could be published without blocking.
+    pub async fn try_send(&mut self) -> Option<io::Result<usize>> {
+        let res = self.client.try_publish_batch(&self.messages).await;
+        if let Some(Ok(published)) = res {
+            self.messages.drain(..published);
+        }
+        res
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::HashMap,
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+use tokio::sync::oneshot;
+
+use crate::{client::Client, message::Message, runtime, Error, SubscriptionReceiver};
+
+/// Routes responses of requests, which all share one wildcard reply subscription.
+pub(crate) struct RequestMux {
+    /// Inbox prefix, each request replies to a unique subject below it.
+    prefix: String,
+
+    /// Senders waiting for their response, keyed by reply subject.
+    pending: Mutex<HashMap<String, oneshot::Sender<Message>>>,
+
+    /// Set once the reply subscription has ended.
+    closed: AtomicBool,
+}
+
+impl fmt::Debug for RequestMux {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("RequestMux")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl RequestMux {
+    /// Subscribes to a new reply inbox and starts routing responses.
+    pub(crate) async fn start(client: &Client) -> io::Result<Arc<Self>> {
+        let prefix = client.new_inbox();
+        let (sid, receiver) = client.subscribe(&format!("{}.*", prefix), None).await?;
+
+        let mux = Arc::new(RequestMux {
+            prefix,
+            pending: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+        });
+
+        let weak = Arc::downgrade(&mux);
+        let client = client.clone();
+        runtime::spawn(async move {
+            dispatch(weak, receiver).await;
+            client.unsubscribe(sid).await.ok();
+        });
+
+        Ok(mux)
+    }
+
+    /// Registers a new request. Returns the reply subject to publish with and
+    /// the handle to wait on for the response.
+    pub(crate) fn register(self: &Arc<Self>) -> io::Result<(String, PendingRequest)> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::ConnectionClosed.into());
+        }
+
+        let reply = format!("{}.{}", self.prefix, nuid::next());
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(reply.clone(), sender);
+
+        Ok((
+            reply.clone(),
+            PendingRequest {
+                mux: self.clone(),
+                reply,
+                receiver,
+            },
+        ))
+    }
+
+    fn complete(&self, message: Message) {
+        let sender = self.pending.lock().unwrap().remove(&message.subject);
+        if let Some(sender) = sender {
+            sender.send(message).ok();
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+/// Routes responses to their requests until the mux is dropped or the subscription ends.
+async fn dispatch(mux: Weak<RequestMux>, receiver: SubscriptionReceiver<Message>) {
+    while let Some(message) = receiver.recv().await {
+        match mux.upgrade() {
+            Some(mux) => mux.complete(message),
+            None => return,
+        }
+    }
+
+    // The subscription ended, wake up everyone still waiting.
+    if let Some(mux) = mux.upgrade() {
+        mux.close();
+    }
+}
+
+/// A request waiting for its response. Dropping it, for example when the
+/// request times out or its future is cancelled, forgets the reply subject.
+#[derive(Debug)]
+pub(crate) struct PendingRequest {
+    mux: Arc<RequestMux>,
+    reply: String,
+    receiver: oneshot::Receiver<Message>,
+}
+
+impl PendingRequest {
+    /// Waits for the response.
+    pub(crate) async fn response(&mut self) -> io::Result<Message> {
+        (&mut self.receiver)
+            .await
+            .map_err(|_| Error::ConnectionClosed.into())
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        self.mux.pending.lock().unwrap().remove(&self.reply);
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The async runtime the client runs its background tasks, timers and sockets on.
+//!
+//! Tokio is used by default. With the `smol_runtime` feature, tasks are spawned on smol's
+//! global executor and sockets are driven by `async-io` instead, so the client can be
+//! embedded in smol or async-std applications without a tokio runtime. Either way the
+//! client only relies on runtime-independent parts of tokio (`tokio::sync` and the
+//! `tokio::io` traits) otherwise.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either};
+
+use crate::{BoxFuture, Options, Transport};
+
+#[cfg(not(feature = "smol_runtime"))]
+mod tokio_runtime;
+#[cfg(not(feature = "smol_runtime"))]
+type Rt = tokio_runtime::TokioRuntime;
+
+#[cfg(feature = "smol_runtime")]
+mod smol_runtime;
+#[cfg(feature = "smol_runtime")]
+type Rt = smol_runtime::SmolRuntime;
+
+/// The operations the client needs from an async runtime.
+pub(crate) trait Runtime {
+    /// Spawns a detached background task.
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static;
+
+    /// Runs blocking code on a thread where blocking is acceptable.
+    fn spawn_blocking<F, T>(f: F) -> BoxFuture<'static, io::Result<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static;
+
+    /// Waits until `duration` has elapsed.
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Opens a TCP connection to `addr`, applying the socket options in `options`.
+    fn connect_tcp(
+        addr: SocketAddr,
+        options: &Options,
+    ) -> BoxFuture<'_, io::Result<Box<dyn Transport>>>;
+}
+
+/// Spawns a detached background task on the selected runtime.
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    Rt::spawn(future)
+}
+
+/// Runs blocking code without stalling the executor.
+pub(crate) async fn spawn_blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Rt::spawn_blocking(f).await
+}
+
+/// Waits until `duration` has elapsed.
+pub(crate) async fn sleep(duration: Duration) {
+    Rt::sleep(duration).await
+}
+
+/// Waits until `deadline` is reached.
+pub(crate) async fn sleep_until(deadline: Instant) {
+    sleep(deadline.saturating_duration_since(Instant::now())).await
+}
+
+/// Opens a TCP connection to `addr` with the socket options in `options`.
+pub(crate) async fn connect_tcp(
+    addr: SocketAddr,
+    options: &Options,
+) -> io::Result<Box<dyn Transport>> {
+    Rt::connect_tcp(addr, options).await
+}
+
+/// Error returned by [`timeout`] when the deadline passes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+impl From<Elapsed> for io::Error {
+    fn from(_: Elapsed) -> io::Error {
+        crate::Error::TimedOut.into()
+    }
+}
+
+/// Awaits `future`, giving up once `duration` has elapsed.
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    futures::pin_mut!(future);
+    match future::select(future, Rt::sleep(duration)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(((), _)) => Err(Elapsed),
+    }
+}
+
+/// Yields once to the executor so other tasks can make progress.
+pub(crate) async fn yield_now() {
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    YieldNow(false).await
+}
@@ -0,0 +1,128 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::io::{AsyncRead as _, AsyncWrite as _};
+use smol::Async;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::Runtime;
+use crate::{BoxFuture, Options, Transport};
+
+/// Runtime backed by smol's global executor and `async-io` reactor.
+///
+/// Neither needs to be set up by the application, so this also works from async-std.
+pub(crate) struct SmolRuntime;
+
+impl Runtime for SmolRuntime {
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        smol::spawn(future).detach();
+    }
+
+    fn spawn_blocking<F, T>(f: F) -> BoxFuture<'static, io::Result<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        Box::pin(async move { Ok(smol::unblock(f).await) })
+    }
+
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            smol::Timer::after(duration).await;
+        })
+    }
+
+    fn connect_tcp(
+        addr: SocketAddr,
+        options: &Options,
+    ) -> BoxFuture<'_, io::Result<Box<dyn Transport>>> {
+        Box::pin(async move {
+            let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+            if let Some(size) = options.send_buffer_size {
+                socket.set_send_buffer_size(size as usize)?;
+            }
+            if let Some(size) = options.recv_buffer_size {
+                socket.set_recv_buffer_size(size as usize)?;
+            }
+            if let Some(local_address) = options.local_address {
+                socket.bind(&local_address.into())?;
+            }
+
+            // Start a non-blocking connect and wait for the socket to become writable.
+            socket.set_nonblocking(true)?;
+            match socket.connect(&addr.into()) {
+                Ok(()) => {}
+                #[cfg(unix)]
+                Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+            let stream = Async::new(TcpStream::from(socket))?;
+            stream.writable().await?;
+            if let Some(err) = stream.get_ref().take_error()? {
+                return Err(err);
+            }
+
+            stream.get_ref().set_nodelay(options.tcp_nodelay)?;
+            if let Some(idle) = options.tcp_keepalive {
+                let keepalive = TcpKeepalive::new().with_time(idle);
+                SockRef::from(stream.get_ref()).set_tcp_keepalive(&keepalive)?;
+            }
+            Ok(Box::new(SmolTcpStream(stream)) as Box<dyn Transport>)
+        })
+    }
+}
+
+/// Adapts an `async-io` TCP stream to the tokio I/O traits used by the connection.
+struct SmolTcpStream(Async<TcpStream>);
+
+impl AsyncRead for SmolTcpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = futures::ready!(Pin::new(&mut self.0).poll_read(cx, buf.initialize_unfilled()))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for SmolTcpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::TcpSocket;
+
+use super::Runtime;
+use crate::{BoxFuture, Options, Transport};
+
+/// The default runtime, backed by the ambient tokio runtime.
+pub(crate) struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        tokio::spawn(future);
+    }
+
+    fn spawn_blocking<F, T>(f: F) -> BoxFuture<'static, io::Result<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(f)
+                .await
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        })
+    }
+
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn connect_tcp(
+        addr: SocketAddr,
+        options: &Options,
+    ) -> BoxFuture<'_, io::Result<Box<dyn Transport>>> {
+        Box::pin(async move {
+            let socket = if addr.is_ipv4() {
+                TcpSocket::new_v4()?
+            } else {
+                TcpSocket::new_v6()?
+            };
+            if let Some(size) = options.send_buffer_size {
+                socket.set_send_buffer_size(size)?;
+            }
+            if let Some(size) = options.recv_buffer_size {
+                socket.set_recv_buffer_size(size)?;
+            }
+            if let Some(local_address) = options.local_address {
+                socket.bind(local_address)?;
+            }
+
+            let stream = socket.connect(addr).await?;
+            stream.set_nodelay(options.tcp_nodelay)?;
+            if let Some(idle) = options.tcp_keepalive {
+                let keepalive = socket2::TcpKeepalive::new().with_time(idle);
+                socket2::SockRef::from(&stream).set_tcp_keepalive(&keepalive)?;
+            }
+            Ok(Box::new(stream) as Box<dyn Transport>)
+        })
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of a connection's counters, as returned by `Connection::statistics`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Statistics {
+    /// Number of messages received from the server.
+    pub in_msgs: u64,
+    /// Number of payload bytes received from the server.
+    pub in_bytes: u64,
+    /// Number of messages published.
+    pub out_msgs: u64,
+    /// Number of payload bytes published.
+    pub out_bytes: u64,
+    /// Number of times the connection was re-established.
+    pub reconnects: u64,
+    /// Number of bytes waiting in the reconnect buffer.
+    pub pending_bytes: usize,
+    /// Counters of the active subscriptions.
+    pub subscriptions: Vec<SubscriptionStatistics>,
+}
+
+/// Counters of a single subscription.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SubscriptionStatistics {
+    /// The subscription id.
+    pub sid: u64,
+    /// The subscribed subject.
+    pub subject: String,
+    /// Number of messages handed to the subscription.
+    pub delivered: u64,
+    /// Number of messages dropped before reaching the subscription.
+    pub dropped: u64,
+}
+
+/// Message and byte counters for one direction.
+#[derive(Debug, Default)]
+pub(crate) struct Counter {
+    msgs: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Counter {
+    pub(crate) fn add(&self, bytes: usize) {
+        self.msgs.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn msgs(&self) -> u64 {
+        self.msgs.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// Delivered and dropped counters of a subscription.
+#[derive(Debug, Default)]
+pub(crate) struct SubscriptionCounter {
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl SubscriptionCounter {
+    pub(crate) fn delivered(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_total(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn snapshot(&self, sid: u64, subject: &str) -> SubscriptionStatistics {
+        SubscriptionStatistics {
+            sid,
+            subject: subject.to_string(),
+            delivered: self.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
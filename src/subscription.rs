@@ -11,9 +11,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{client::Client, message::Message, Stream};
-use std::{io, pin::Pin, sync::Arc, time::Duration};
-use tokio::sync::Mutex;
+use crate::{client::Client, message::Message, runtime, BoxFuture, Stream};
+use std::{
+    fmt, io, mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+use tokio::sync::{futures::Notified, Notify};
+
+/// Default maximum number of messages queued for a subscriber.
+pub(crate) const DEFAULT_PENDING_MESSAGES: usize = 650;
 
 #[derive(Debug)]
 struct Inner {
@@ -35,42 +47,129 @@ impl Drop for Inner {
     fn drop(&mut self) {
         let client = self.client.clone();
         let sid = self.sid;
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             client.unsubscribe(sid).await.ok();
         });
     }
 }
 
+/// Traces the delivery of `message` to the application, with the `otel` feature.
+pub(crate) fn delivered(message: Message) -> Message {
+    #[cfg(feature = "otel")]
+    crate::otel::delivered(&message);
+    message
+}
+
 /// Wrapper around `tokio::sync::mpsc::Receiver` that provides interior mutability
+///
+/// Several tasks may receive at once. The receiver is only locked while it is
+/// polled, never while waiting, so dropping a receive in progress never blocks
+/// the others.
 #[derive(Debug)]
 pub struct SubscriptionReceiver<T> {
-    inner: Mutex<tokio::sync::mpsc::Receiver<T>>,
+    inner: Arc<Mutex<tokio::sync::mpsc::Receiver<T>>>,
+
+    /// Tasks waiting for the next value.
+    waiters: Arc<Waiters>,
+
+    /// Byte accounting for subscriptions with a pending bytes limit.
+    pending: Option<Release<T>>,
+}
+
+/// Releases the size of each received value from the pending bytes.
+type Release<T> = (Arc<PendingBytes>, fn(&T) -> usize);
+
+/// Tasks waiting on a shared receiver.
+///
+/// The channel only remembers the last waker it was polled with, so it is
+/// polled with one that wakes every waiting task instead.
+#[derive(Debug, Default)]
+struct Waiters(Mutex<Vec<Waker>>);
+
+impl Waiters {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+impl Wake for Waiters {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
 }
 
 impl<T> SubscriptionReceiver<T> {
     /// Receives the next value. Returns None if the channel has been closed
     /// and there are no more values.
     pub async fn recv(&self) -> Option<T> {
-        let mut receiver = self.inner.lock().await;
-        let x = receiver.recv().await;
-        x
+        futures::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Polls to receive the next value. Returns `Ready(None)` if the channel
+    /// has been closed and there are no more values.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let waker = Waker::from(self.waiters.clone());
+        let mut waiters = Context::from_waker(&waker);
+
+        let mut receiver = self.lock();
+        let mut poll = receiver.poll_recv(&mut waiters);
+        if poll.is_pending() {
+            // Poll again once registered, in case a value arrived in between.
+            self.waiters.register(cx.waker());
+            poll = receiver.poll_recv(&mut waiters);
+        }
+        drop(receiver);
+
+        if let Poll::Ready(value) = &poll {
+            self.release(value.as_ref());
+        }
+        poll
     }
 
     /// Return Some(message) if a message is available,
     /// or None if there are no messages available,
     /// or the subscription has been closed or client disconnected.
     pub async fn try_recv(&self) -> Option<T> {
-        let mut receiver =
-            match tokio::time::timeout(Duration::from_secs(10), self.inner.lock()).await {
-                Err(_) => {
-                    panic!("try_recv in subscription failed to get inner lock in 10 secs");
-                }
-                Ok(g) => g,
-            };
-        //let mut receiver = self.inner.lock().await;
-        match receiver.try_recv() {
-            Ok(m) => Some(m),
-            Err(_) => None,
+        let value = self.lock().try_recv().ok();
+        self.release(value.as_ref());
+        value
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, tokio::sync::mpsc::Receiver<T>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Creates a receiver that releases the size of each received value from `pending`.
+    pub(crate) fn with_pending(
+        receiver: tokio::sync::mpsc::Receiver<T>,
+        pending: Arc<PendingBytes>,
+        size: fn(&T) -> usize,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(receiver)),
+            waiters: Arc::default(),
+            pending: Some((pending, size)),
+        }
+    }
+
+    /// Returns a weak handle to the underlying receiver.
+    pub(crate) fn downgrade(&self) -> Weak<Mutex<tokio::sync::mpsc::Receiver<T>>> {
+        Arc::downgrade(&self.inner)
+    }
+
+    fn release(&self, value: Option<&T>) {
+        if let (Some(value), Some((pending, size))) = (value, self.pending.as_ref()) {
+            pending.release(size(value));
         }
     }
 }
@@ -78,13 +177,140 @@ impl<T> SubscriptionReceiver<T> {
 impl<T> From<tokio::sync::mpsc::Receiver<T>> for SubscriptionReceiver<T> {
     fn from(r: tokio::sync::mpsc::Receiver<T>) -> Self {
         Self {
-            inner: Mutex::new(r),
+            inner: Arc::new(Mutex::new(r)),
+            waiters: Arc::default(),
+            pending: None,
         }
     }
 }
 
+/// Number of bytes queued for a subscription and not received yet.
+#[derive(Debug, Default)]
+pub(crate) struct PendingBytes {
+    bytes: AtomicUsize,
+    released: Notify,
+}
+
+impl PendingBytes {
+    pub(crate) fn get(&self) -> usize {
+        self.bytes.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn acquire(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::AcqRel);
+    }
+
+    pub(crate) fn release(&self, bytes: usize) {
+        self.bytes.fetch_sub(bytes, Ordering::AcqRel);
+        self.released.notify_waiters();
+    }
+
+    /// Waits until some bytes have been released.
+    pub(crate) fn released(&self) -> Notified<'_> {
+        self.released.notified()
+    }
+}
+
+/// What happens to new messages once a subscription has reached its pending limits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Wait for the subscriber to catch up. This stalls reading from the
+    /// connection, and therefore every other subscription on it.
+    #[default]
+    Block,
+    /// Drop the new message.
+    DropNew,
+    /// Drop the oldest pending message to make room for the new one.
+    DropOldest,
+}
+
+/// Pending limits of a subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PendingLimits {
+    pub(crate) messages: usize,
+    pub(crate) bytes: Option<usize>,
+    pub(crate) policy: SlowConsumerPolicy,
+}
+
+impl Default for PendingLimits {
+    fn default() -> Self {
+        PendingLimits {
+            messages: DEFAULT_PENDING_MESSAGES,
+            bytes: None,
+            policy: SlowConsumerPolicy::Block,
+        }
+    }
+}
+
+/// Options for `Connection::subscribe_with_options`.
+///
+/// # Example
+/// ```
+/// # #[tokio::main]
+/// # async fn main() -> std::io::Result<()> {
+/// use nats_aflowt::{SlowConsumerPolicy, SubscriptionOptions};
+/// let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+/// let sub = nc
+///     .subscribe_with_options(
+///         "foo",
+///         SubscriptionOptions::new()
+///             .pending_messages(10_000)
+///             .pending_bytes(64 * 1024 * 1024)
+///             .slow_consumer_policy(SlowConsumerPolicy::DropOldest),
+///     )
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionOptions {
+    pub(crate) queue_group: Option<String>,
+    pub(crate) limits: PendingLimits,
+}
+
+impl SubscriptionOptions {
+    /// Creates subscription options with the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes as a member of the given queue group.
+    #[must_use]
+    pub fn queue_group(mut self, queue_group: &str) -> Self {
+        self.queue_group = Some(queue_group.to_string());
+        self
+    }
+
+    /// Sets the maximum number of messages queued for the subscriber.
+    /// Defaults to 650, values below 1 are raised to 1.
+    #[must_use]
+    pub fn pending_messages(mut self, messages: usize) -> Self {
+        self.limits.messages = messages.max(1);
+        self
+    }
+
+    /// Sets the maximum number of payload bytes queued for the subscriber.
+    /// Unlimited by default. A single message larger than the limit is still
+    /// delivered when nothing else is pending.
+    #[must_use]
+    pub fn pending_bytes(mut self, bytes: usize) -> Self {
+        self.limits.bytes = Some(bytes);
+        self
+    }
+
+    /// Sets what happens to new messages once a limit is reached.
+    /// Defaults to `SlowConsumerPolicy::Block`.
+    #[must_use]
+    pub fn slow_consumer_policy(mut self, policy: SlowConsumerPolicy) -> Self {
+        self.limits.policy = policy;
+        self
+    }
+}
+
 /// A `Subscription` receives `Message`s published
 /// to specific NATS `Subject`s.
+///
+/// A `Subscription` is also a `Stream` of its messages.
 #[derive(Clone, Debug)]
 pub struct Subscription(Arc<Inner>);
 
@@ -167,7 +393,7 @@ impl Subscription {
     /// # }
     /// ```
     pub async fn next(&self) -> Option<Message> {
-        self.0.messages.recv().await
+        self.0.messages.recv().await.map(delivered)
     }
 
     /// Try to get the next message, or None if no messages
@@ -194,7 +420,7 @@ impl Subscription {
     /// # }
     /// ```
     pub async fn try_next(&self) -> Option<Message> {
-        self.0.messages.try_recv().await
+        self.0.messages.try_recv().await.map(delivered)
     }
 
     /// Get the next message, or a timeout error
@@ -220,16 +446,13 @@ impl Subscription {
     /// # }
     /// ```
     pub async fn next_timeout(&self, timeout: Duration) -> io::Result<Message> {
-        match tokio::time::timeout(timeout, self.0.messages.recv()).await {
-            Ok(Some(msg)) => Ok(msg),
+        match runtime::timeout(timeout, self.0.messages.recv()).await {
+            Ok(Some(msg)) => Ok(delivered(msg)),
             Ok(None) => Err(io::Error::new(
-                io::ErrorKind::TimedOut,
-                "next_timeout: timed out",
-            )),
-            Err(_) => Err(io::Error::new(
                 io::ErrorKind::Other,
                 "next_timeout: unsubscribed",
             )),
+            Err(_) => Err(crate::Error::TimedOut.into()),
         }
     }
 
@@ -249,18 +472,8 @@ impl Subscription {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn messages(self) -> Pin<Box<dyn Stream<Item = Message>>> {
-        Box::pin(self.into_stream())
-    }
-
-    /// Returns a stream (unpinned)
-    #[doc(hidden)]
-    fn into_stream(self) -> impl Stream<Item = Message> {
-        async_stream::stream! {
-            while let Some(message) = self.next().await {
-                yield message;
-            }
-        }
+    pub fn messages(self) -> Pin<Box<dyn Stream<Item = Message> + Send>> {
+        Box::pin(self)
     }
 
     /// Returns a pinned message stream.
@@ -278,8 +491,8 @@ impl Subscription {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Message>>> {
-        Box::pin(self.into_stream())
+    pub fn stream(self) -> Pin<Box<dyn Stream<Item = Message> + Send>> {
+        Box::pin(self)
     }
 
     /// Attach a closure to handle messages. This closure will execute in a
@@ -306,15 +519,20 @@ impl Subscription {
     {
         let sub = self.clone();
         let handler = Arc::new(handler);
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             while let Some(m) = sub.next().await {
                 let handler = handler.clone();
                 // just in case the handler blocks, we need to use blocking thread pool
-                let _ = tokio::task::spawn_blocking(move || {
-                    if let Err(e) = handler(m) {
-                        // TODO(dlc) - Capture for last error?
-                        log::error!("Error in callback! {:?}", e);
-                    }
+                runtime::spawn(async move {
+                    let _ = runtime::spawn_blocking(move || {
+                        #[cfg(feature = "otel")]
+                        let _span = m.span().entered();
+                        if let Err(e) = handler(m) {
+                            // TODO(dlc) - Capture for last error?
+                            log::error!("Error in callback! {:?}", e);
+                        }
+                    })
+                    .await;
                 });
             }
         });
@@ -339,7 +557,7 @@ impl Subscription {
     ///      .with_async_handler( move |m| async move { m.respond(b"ans=42").await?; Ok(()) });
     /// #
     /// # let resp = nc.request(&name, "send answer").await?;
-    /// # assert_eq!(resp.data, b"ans=42");
+    /// # assert_eq!(&resp.data[..], b"ans=42");
     /// # Ok(())
     /// # }
     /// ```
@@ -351,11 +569,16 @@ impl Subscription {
     {
         let sub = self.clone();
         let handler = Arc::new(handler);
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             while let Some(m) = sub.next().await {
                 let handler = handler.clone();
-                let _ = tokio::spawn(async move {
-                    if let Err(e) = handler(m).await {
+                runtime::spawn(async move {
+                    #[cfg(feature = "otel")]
+                    let span = m.span();
+                    let handled = handler(m);
+                    #[cfg(feature = "otel")]
+                    let handled = tracing::Instrument::instrument(handled, span);
+                    if let Err(e) = handled.await {
                         // TODO(dlc) - Capture for last error?
                         log::error!("Error in callback! {:?}", e);
                     }
@@ -365,6 +588,35 @@ impl Subscription {
         self
     }
 
+    /// Unsubscribes automatically once `max` messages have been received in total,
+    /// counting the ones received before this call. The subscription ends after the
+    /// last message has been read. The limit is kept across reconnects.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// # let nc = nats_aflowt::connect("127.0.0.1:14222").await?;
+    /// let sub = nc.subscribe("foo").await?;
+    /// sub.auto_unsubscribe(2).await?;
+    /// for _ in 0..3 {
+    ///     nc.publish("foo", "hello").await?;
+    /// }
+    /// assert!(sub.next().await.is_some());
+    /// assert!(sub.next().await.is_some());
+    /// assert!(sub.next().await.is_none());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn auto_unsubscribe(&self, max: u64) -> io::Result<()> {
+        if max == 0 {
+            return Err(
+                crate::Error::validation("auto_unsubscribe: max must be at least 1").into(),
+            );
+        }
+        self.0.client.auto_unsubscribe(self.0.sid, max).await
+    }
+
     /// Unsubscribe a subscription immediately without draining.
     /// Use `drain` instead if you want any pending messages
     /// to be processed by a handler, if one is configured.
@@ -440,12 +692,58 @@ impl Subscription {
     /// # }
     /// ```
     pub async fn drain(&self) -> io::Result<()> {
-        self.0.client.flush(crate::DEFAULT_FLUSH_TIMEOUT).await?;
+        self.0
+            .client
+            .flush(self.0.client.options.flush_timeout)
+            .await?;
         self.0.client.unsubscribe(self.0.sid).await?;
         Ok(())
     }
 }
 
+impl Stream for Subscription {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.0.messages.poll_recv(cx).map(|msg| msg.map(delivered))
+    }
+}
+
+/// The receive in progress of a subscription that is polled as a `Stream`.
+///
+/// The future sits behind a mutex only to keep subscriptions `Sync`, polling
+/// takes `&mut self` and never locks.
+#[derive(Default)]
+pub(crate) struct NextMessage(Mutex<Option<BoxFuture<'static, Option<Message>>>>);
+
+impl NextMessage {
+    /// Polls the receive in progress, starting one with `next` if there is none.
+    pub(crate) fn poll<F>(&mut self, cx: &mut Context<'_>, next: F) -> Poll<Option<Message>>
+    where
+        F: FnOnce() -> BoxFuture<'static, Option<Message>>,
+    {
+        let slot = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let poll = slot.get_or_insert_with(next).as_mut().poll(cx);
+        if poll.is_ready() {
+            *slot = None;
+        }
+        poll
+    }
+}
+
+// Clones share the messages but not a receive in progress.
+impl Clone for NextMessage {
+    fn clone(&self) -> Self {
+        NextMessage::default()
+    }
+}
+
+impl fmt::Debug for NextMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NextMessage").finish()
+    }
+}
+
 /// A `Handler` may be used to unsubscribe a handler thread.
 pub struct Handler {
     sub: Subscription,
@@ -0,0 +1,185 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{
+    tungstenite::{self, Message},
+    WebSocketStream,
+};
+
+use crate::connector::{ServerAddress, Transport};
+
+/// Carries the NATS protocol as binary websocket messages.
+pub(crate) struct WebSocket {
+    inner: WebSocketStream<Box<dyn Transport>>,
+
+    /// Payload of the last received message that has not been read yet.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("buffered", &(self.read_buf.len() - self.read_pos))
+            .finish()
+    }
+}
+
+impl WebSocket {
+    /// Performs the websocket handshake for `server` over an established transport.
+    pub(crate) async fn connect(
+        server: &ServerAddress,
+        transport: Box<dyn Transport>,
+    ) -> io::Result<WebSocket> {
+        let mut url = server.clone().into_inner();
+        // Credentials are sent in the CONNECT message, not in the HTTP request.
+        url.set_username("").ok();
+        url.set_password(None).ok();
+
+        let (inner, _) = tokio_tungstenite::client_async(url.as_str(), transport)
+            .await
+            .map_err(into_io_error)?;
+        Ok(WebSocket {
+            inner,
+            read_buf: Vec::new(),
+            read_pos: 0,
+        })
+    }
+
+    /// Wraps a transport on which the handshake has already completed.
+    #[cfg(test)]
+    async fn from_raw(
+        transport: Box<dyn Transport>,
+        role: tungstenite::protocol::Role,
+    ) -> WebSocket {
+        WebSocket {
+            inner: WebSocketStream::from_raw_socket(transport, role, None).await,
+            read_buf: Vec::new(),
+            read_pos: 0,
+        }
+    }
+}
+
+fn into_io_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::ConnectionReset, "websocket closed")
+        }
+        err => io::Error::new(io::ErrorKind::Other, err),
+    }
+}
+
+impl AsyncRead for WebSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if self.read_pos < self.read_buf.len() {
+                let n = buf.remaining().min(self.read_buf.len() - self.read_pos);
+                let start = self.read_pos;
+                buf.put_slice(&self.read_buf[start..start + n]);
+                self.read_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            let message = match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                // The end of the stream reads as end of file.
+                Poll::Ready(None) | Poll::Ready(Some(Ok(Message::Close(_)))) => {
+                    return Poll::Ready(Ok(()))
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(into_io_error(err))),
+                Poll::Ready(Some(Ok(message))) => message,
+            };
+            match message {
+                Message::Binary(data) => self.read_buf = data,
+                Message::Text(text) => self.read_buf = text.into_bytes(),
+                // Pings are answered by the websocket itself.
+                _ => continue,
+            }
+            self.read_pos = 0;
+        }
+    }
+}
+
+impl AsyncWrite for WebSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.inner).poll_ready(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(into_io_error(err))),
+            Poll::Ready(Ok(())) => {}
+        }
+        Pin::new(&mut self.inner)
+            .start_send(Message::Binary(buf.to_vec()))
+            .map_err(into_io_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(into_io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(into_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    #[tokio::test]
+    async fn frames_roundtrip() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = WebSocket::from_raw(Box::new(client), Role::Client).await;
+        let mut server = WebSocket::from_raw(Box::new(server), Role::Server).await;
+
+        client.write_all(b"PUB foo 5\r\n").await.unwrap();
+        client.write_all(b"hello\r\n").await.unwrap();
+        client.flush().await.unwrap();
+
+        let mut received = vec![0; 18];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"PUB foo 5\r\nhello\r\n");
+
+        // Reads smaller than a message keep the rest buffered.
+        server.write_all(b"PING\r\n").await.unwrap();
+        server.flush().await.unwrap();
+        let mut ping = [0; 2];
+        client.read_exact(&mut ping).await.unwrap();
+        assert_eq!(&ping, b"PI");
+        client.read_exact(&mut ping).await.unwrap();
+        assert_eq!(&ping, b"NG");
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use futures::StreamExt;
+use nats_aflowt::ConnectionEvent;
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn auto_unsubscribe() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::connect(&s.client_url()).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    sub.auto_unsubscribe(3).await?;
+    for _ in 0..5 {
+        nc.publish("foo", "hello").await?;
+    }
+    nc.flush().await?;
+
+    for _ in 0..3 {
+        assert!(sub.next_timeout(Duration::from_secs(5)).await.is_ok());
+    }
+    assert!(sub.next().await.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn auto_unsubscribe_counts_received_messages() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::connect(&s.client_url()).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello").await?;
+    nc.publish("foo", "hello").await?;
+    nc.flush().await?;
+    sub.next_timeout(Duration::from_secs(5)).await?;
+
+    // Two messages arrived already, so this ends the subscription right away.
+    sub.auto_unsubscribe(2).await?;
+    assert!(sub.next_timeout(Duration::from_secs(5)).await.is_ok());
+    assert!(sub.next().await.is_none());
+
+    assert!(sub.auto_unsubscribe(0).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn auto_unsubscribe_closes_locally() -> io::Result<()> {
+    // The fake server ignores the max count of UNSUB, so the client has to end
+    // the subscription itself.
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    sub.auto_unsubscribe(2).await?;
+    for _ in 0..3 {
+        nc.publish("foo", "hello").await?;
+    }
+    nc.flush().await?;
+
+    assert!(sub.next_timeout(Duration::from_secs(5)).await.is_ok());
+    assert!(sub.next_timeout(Duration::from_secs(5)).await.is_ok());
+    assert!(sub.next().await.is_none());
+    assert!(server.ops().iter().any(|op| op == "UNSUB 1 2"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn auto_unsubscribe_survives_reconnect() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let mut events = nc.events();
+
+    let sub = nc.subscribe("foo").await?;
+    sub.auto_unsubscribe(3).await?;
+    nc.publish("foo", "hello").await?;
+    nc.flush().await?;
+    sub.next_timeout(Duration::from_secs(5)).await?;
+
+    server.disconnect();
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.next()).await?;
+        if let Some(ConnectionEvent::Reconnected { .. }) = event {
+            break;
+        }
+    }
+    nc.flush().await?;
+
+    // Only the remaining two messages are asked for after the reconnect.
+    assert_eq!(server.dials(), 2);
+    assert_eq!(
+        server.ops().iter().filter(|op| *op == "SUB foo 1").count(),
+        2
+    );
+    assert!(server.ops().iter().any(|op| op == "UNSUB 1 2"));
+    Ok(())
+}
@@ -0,0 +1,58 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use nats_aflowt::{BoxFuture, Dialer, ServerAddress, Transport};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn custom_dialer_pub_sub() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .connect("nats://fake:4222")
+        .await?;
+    assert_eq!(server.dials(), 1);
+
+    let sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello").await?;
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(&msg.data[..], b"hello");
+
+    nc.close().await;
+    Ok(())
+}
+
+struct FailingDialer;
+
+impl Dialer for FailingDialer {
+    fn dial<'a>(
+        &'a self,
+        _server: &'a ServerAddress,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Transport>>> {
+        Box::pin(async move { Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")) })
+    }
+}
+
+#[tokio::test]
+async fn custom_dialer_error() {
+    let err = nats_aflowt::Options::new()
+        .dialer(FailingDialer)
+        .connect("nats://fake:4222")
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+}
@@ -30,7 +30,7 @@ async fn drop_flushes() -> io::Result<()> {
     nc1.publish(&inbox, b"hello").await?;
     drop(nc1); // Dropping should flush the published message.
 
-    assert_eq!(sub.next().await.unwrap().data, b"hello");
+    assert_eq!(&sub.next().await.unwrap().data[..], b"hello");
 
     Ok(())
 }
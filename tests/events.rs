@@ -0,0 +1,66 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use futures::StreamExt;
+use nats_aflowt::{ConnectionEvent, ConnectionState};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn connection_state_and_events() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::connect(&s.client_url()).await?;
+    assert_eq!(nc.state(), ConnectionState::Connected);
+
+    let mut events = nc.events();
+    nc.drain().await?;
+    assert_eq!(nc.state(), ConnectionState::Closed);
+
+    let event = tokio::time::timeout(Duration::from_secs(1), events.next()).await?;
+    assert!(
+        matches!(event, Some(ConnectionEvent::Draining)),
+        "{:?}",
+        event
+    );
+    let event = tokio::time::timeout(Duration::from_secs(1), events.next()).await?;
+    assert!(
+        matches!(event, Some(ConnectionEvent::Closed)),
+        "{:?}",
+        event
+    );
+    let event = tokio::time::timeout(Duration::from_secs(1), events.next()).await?;
+    assert!(event.is_none(), "{:?}", event);
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(target_os = "windows", ignore)]
+async fn connection_events_lame_duck() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::connect(&s.client_url()).await?;
+    let mut events = nc.events();
+
+    set_lame_duck_mode(&s);
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(1), events.next()).await?;
+        match event {
+            Some(ConnectionEvent::LameDuck { .. }) => break,
+            Some(_) => continue,
+            None => panic!("expected lame duck event, got end of stream"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+mod util;
+pub use util::*;
+
+async fn requests_use_prefix(old_request_style: bool) -> io::Result<()> {
+    let server = FakeServer::new();
+    let mut options = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .inbox_prefix("_INBOX_svc");
+    if old_request_style {
+        options = options.use_old_request_style();
+    }
+    let nc = options.connect("nats://fake:4222").await?;
+    assert!(nc.new_inbox().starts_with("_INBOX_svc."));
+
+    let service = nc.subscribe("help").await?;
+    tokio::spawn(async move {
+        while let Some(msg) = service.next().await {
+            msg.respond("ok").await.unwrap();
+        }
+    });
+
+    let resp = nc
+        .request_timeout("help", "please", Duration::from_secs(5))
+        .await?;
+    assert_eq!(&resp.data[..], b"ok");
+
+    let subs: Vec<String> = server
+        .ops()
+        .into_iter()
+        .filter(|op| op.starts_with("SUB ") && op != "SUB help 1")
+        .collect();
+    assert!(!subs.is_empty());
+    for sub in subs {
+        assert!(sub.starts_with("SUB _INBOX_svc."), "{}", sub);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn inbox_prefix() -> io::Result<()> {
+    requests_use_prefix(false).await
+}
+
+#[tokio::test]
+async fn inbox_prefix_old_request_style() -> io::Result<()> {
+    requests_use_prefix(true).await
+}
+
+#[tokio::test]
+async fn invalid_inbox_prefix() {
+    for prefix in ["", "_INBOX.", "_INBOX.*", "_INBOX.>", "a..b", "in box"] {
+        let err = nats_aflowt::Options::new()
+            .dialer(FakeServer::new().dialer())
+            .inbox_prefix(prefix)
+            .connect("nats://fake:4222")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", prefix);
+    }
+}
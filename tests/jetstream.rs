@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashSet, io, iter::FromIterator, time::Duration};
+use std::{io, time::Duration};
 
 mod util;
 use nats_aflowt::jetstream::{self, *};
@@ -256,10 +256,8 @@ async fn jetstream_publish() {
     assert_eq!(
         msg.headers
             .unwrap()
-            .inner
-            .get("Nats-Expected-Last-Subject-Sequence")
-            .unwrap(),
-        &HashSet::from_iter(vec!["1".to_string()])
+            .get("Nats-Expected-Last-Subject-Sequence"),
+        Some("1")
     );
 }
 
@@ -295,7 +293,7 @@ async fn jetstream_subscribe() {
         // And receive it on our subscription
         let msg = sub.next().await.unwrap();
         msg.ack().await.unwrap();
-        assert_eq!(msg.data, payload);
+        assert_eq!(&msg.data[..], payload);
     }
 
     // Check the state of consumer matches up with our expectations
@@ -425,11 +423,11 @@ async fn jetstream_queue_subscribe() {
 
     let msg = sub1.next().await.unwrap();
     msg.ack().await.unwrap();
-    assert_eq!(msg.data, b"hello js");
+    assert_eq!(&msg.data[..], b"hello js");
 
     let msg = sub2.next_timeout(Duration::from_secs(1)).await.unwrap();
     msg.ack().await.unwrap();
-    assert_eq!(msg.data, b"hello js");
+    assert_eq!(&msg.data[..], b"hello js");
 
     sub1.unsubscribe().await.unwrap();
     sub2.unsubscribe().await.unwrap();
@@ -483,7 +481,7 @@ async fn jetstream_flow_control() {
     // Make sure no control messages make it through `next`
     for _ in 0..250 {
         let message = sub.next().await.unwrap();
-        assert_eq!(message.data, data);
+        assert_eq!(&message.data[..], data);
     }
 }
 
@@ -522,6 +520,222 @@ async fn jetstream_ordered() {
 
     for i in 0..250 {
         let message = sub.next().await.unwrap();
-        assert_eq!(message.data, (i as i64).to_be_bytes());
+        assert_eq!(&message.data[..], (i as i64).to_be_bytes());
     }
 }
+
+#[tokio::test]
+async fn jetstream_pull_subscribe_fetch() {
+    let (_s, _nc, js) = run_basic_jetstream().await;
+
+    js.add_stream(&StreamConfig {
+        name: "TEST".to_string(),
+        subjects: vec!["foo".to_string()],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let sub = js.pull_subscribe("foo").await.unwrap();
+
+    // Nothing published yet, so a no wait fetch returns right away.
+    let messages = sub.fetch_no_wait(10).await.unwrap();
+    assert!(messages.is_empty());
+
+    for i in 0..10 {
+        js.publish("foo", format!("{}", i)).await.unwrap();
+    }
+
+    // A full batch is returned as soon as it is available.
+    let messages = sub.fetch(5, Duration::from_secs(1)).await.unwrap();
+    assert_eq!(messages.len(), 5);
+    for (i, message) in messages.iter().enumerate() {
+        assert_eq!(message.data, format!("{}", i).as_bytes());
+        message.ack().await.unwrap();
+    }
+
+    // Only the remaining messages are returned when the request expires.
+    let messages = sub.fetch(10, Duration::from_millis(500)).await.unwrap();
+    assert_eq!(messages.len(), 5);
+    for message in messages.iter() {
+        message.ack().await.unwrap();
+    }
+
+    // Expires without any messages.
+    let messages = sub.fetch(10, Duration::from_millis(200)).await.unwrap();
+    assert!(messages.is_empty());
+
+    let info = sub.consumer_info().await.unwrap();
+    assert_eq!(info.config.deliver_subject, None);
+    assert_eq!(info.delivered.consumer_seq, 10);
+}
+
+#[tokio::test]
+async fn jetstream_pull_subscribe_max_bytes() {
+    let (_s, _nc, js) = run_basic_jetstream().await;
+
+    js.add_stream(&StreamConfig {
+        name: "TEST".to_string(),
+        subjects: vec!["foo".to_string()],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    for _ in 0..10 {
+        js.publish("foo", [0u8; 100]).await.unwrap();
+    }
+
+    let sub = js
+        .pull_subscribe_with_options(
+            "foo",
+            &PullSubscribeOptions::new().durable_name("pull".to_string()),
+        )
+        .await
+        .unwrap();
+
+    let messages = sub
+        .fetch_with_max_bytes(10, 500, Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert!(!messages.is_empty());
+    assert!(messages.len() < 10);
+
+    // Binding to the durable consumer works, binding to a push consumer does not.
+    js.pull_subscribe_with_options(
+        "foo",
+        &PullSubscribeOptions::bind("TEST".to_string(), "pull".to_string()),
+    )
+    .await
+    .unwrap();
+
+    js.add_consumer(
+        "TEST",
+        ConsumerConfig {
+            durable_name: Some("push".to_string()),
+            deliver_subject: Some("push.deliver".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    js.pull_subscribe_with_options(
+        "foo",
+        &PullSubscribeOptions::bind("TEST".to_string(), "push".to_string()),
+    )
+    .await
+    .unwrap_err();
+}
+
+#[tokio::test]
+async fn jetstream_subscribe_empty_subject_requires_stream() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let js = jetstream::new(nc);
+
+    let err = js
+        .pull_subscribe_with_options("", &PullSubscribeOptions::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        nats_aflowt::Error::from(err),
+        nats_aflowt::Error::Validation(_)
+    ));
+
+    let err = js
+        .subscribe_with_options("", &SubscribeOptions::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        nats_aflowt::Error::from(err),
+        nats_aflowt::Error::Validation(_)
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn jetstream_pull_subscribe_messages() {
+    use futures::stream::StreamExt;
+
+    let (_s, _nc, js) = run_basic_jetstream().await;
+
+    js.add_stream(&StreamConfig {
+        name: "TEST".to_string(),
+        subjects: vec!["foo".to_string()],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    for i in 0..20 {
+        js.publish("foo", format!("{}", i)).await.unwrap();
+    }
+
+    let sub = js.pull_subscribe("foo").await.unwrap();
+    let mut messages = sub.clone().messages_with_options(PullStreamOptions {
+        max_messages: 5,
+        expires: Duration::from_secs(2),
+        idle_heartbeat: Duration::from_millis(500),
+        ..Default::default()
+    });
+
+    // The stream keeps pulling in windows of at most 5 messages.
+    for i in 0..20 {
+        let message = messages.next().await.unwrap().unwrap();
+        assert_eq!(message.data, format!("{}", i).as_bytes());
+        message.ack().await.unwrap();
+    }
+
+    js.publish("foo", "late").await.unwrap();
+    let message = messages.next().await.unwrap().unwrap();
+    assert_eq!(&message.data[..], b"late");
+    message.ack().await.unwrap();
+    drop(messages);
+
+    let info = sub.consumer_info().await.unwrap();
+    assert_eq!(info.delivered.consumer_seq, 21);
+}
+
+#[tokio::test]
+async fn jetstream_publish_async() {
+    let s = util::run_server("tests/configs/jetstream.conf");
+    let nc = nats_aflowt::connect(&s.client_url()).await.unwrap();
+    let js = JetStream::new(nc, JetStreamOptions::new().publish_async_max_pending(10));
+
+    js.add_stream(&StreamConfig {
+        name: "TEST".to_string(),
+        subjects: vec!["foo".to_string()],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    // More messages than the pending limit, publishing waits for acks as needed.
+    let mut acks = Vec::new();
+    for i in 0..100 {
+        acks.push(js.publish_async("foo", format!("{}", i)).await.unwrap());
+        assert!(js.publish_async_pending() <= 10);
+    }
+    for (i, ack) in acks.into_iter().enumerate() {
+        let ack = ack.await.unwrap();
+        assert_eq!(ack.stream, "TEST");
+        assert_eq!(ack.sequence, i as u64 + 1);
+    }
+
+    // Acks are tracked even if the futures are dropped.
+    for _ in 0..50 {
+        js.publish_async("foo", "dropped").await.unwrap();
+    }
+    js.publish_async_complete().await;
+    assert_eq!(js.publish_async_pending(), 0);
+    assert_eq!(js.stream_info("TEST").await.unwrap().state.messages, 150);
+
+    // Publishing to a subject without a stream fails with no responders.
+    let err = js
+        .publish_async("bar", "nobody")
+        .await
+        .unwrap()
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
@@ -0,0 +1,49 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn next_timeout_elapsed() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let sub = nc.subscribe("quiet").await?;
+
+    let err = sub
+        .next_timeout(Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    let err = err
+        .into_inner()
+        .and_then(|err| err.downcast::<nats_aflowt::Error>().ok())
+        .expect("should be a nats_aflowt::Error");
+    assert!(matches!(*err, nats_aflowt::Error::TimedOut), "{:?}", err);
+    Ok(())
+}
+
+#[tokio::test]
+async fn next_timeout_unsubscribed() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let sub = nc.subscribe("quiet").await?;
+    sub.drain().await?;
+
+    let err = sub.next_timeout(Duration::from_secs(5)).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert!(err.to_string().contains("unsubscribed"), "{}", err);
+    Ok(())
+}
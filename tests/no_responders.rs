@@ -24,4 +24,8 @@ async fn no_responders() {
     assert!(res.is_err());
     let err = res.err().unwrap();
     assert!(err.to_string().contains("no responders"), "{}", err);
+    assert!(matches!(
+        nats_aflowt::Error::from(err),
+        nats_aflowt::Error::NoResponders
+    ));
 }
@@ -0,0 +1,262 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "otel")]
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use nats_aflowt::{header::HeaderMap, Message};
+use opentelemetry::{
+    sdk::trace::TracerProvider,
+    trace::{TraceContextExt, TraceId, TracerProvider as _},
+};
+use tracing::{subscriber::DefaultGuard, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+mod util;
+pub use util::*;
+
+/// Records spans with OpenTelemetry contexts on this thread, while the
+/// returned provider is alive.
+fn trace() -> (TracerProvider, DefaultGuard) {
+    trace_with(SpanNames::default())
+}
+
+fn trace_with(names: SpanNames) -> (TracerProvider, DefaultGuard) {
+    let provider = TracerProvider::builder().build();
+    let tracer = provider.tracer("nats-test");
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .with(names);
+    (provider, tracing::subscriber::set_default(subscriber))
+}
+
+/// Collects the names of created spans.
+#[derive(Clone, Default)]
+struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanNames {
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        _id: &tracing::span::Id,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        self.0.lock().unwrap().push(attrs.metadata().name());
+    }
+}
+
+/// Asserts that `msg` carries the trace context `sent_in`.
+fn assert_traced(msg: &Message, sent_in: TraceId) {
+    let traceparent = msg.headers.as_ref().and_then(|h| h.get("traceparent"));
+    assert!(traceparent
+        .unwrap()
+        .starts_with(&format!("00-{:032x}-", sent_in)));
+}
+
+fn trace_id(span: &tracing::Span) -> TraceId {
+    span.context().span().span_context().trace_id()
+}
+
+#[tokio::test]
+async fn publish_propagates_trace_context() -> io::Result<()> {
+    let _trace = trace();
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    let span = tracing::info_span!("send");
+    let sent_in = trace_id(&span);
+    assert_ne!(sent_in, TraceId::INVALID);
+    nc.publish("foo", "hello").instrument(span).await?;
+
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_traced(&msg, sent_in);
+    assert_eq!(trace_id(&msg.span()), sent_in);
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn publish_without_tracer_has_no_headers() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello").await?;
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert!(msg.headers.is_none());
+    assert!(server.ops().iter().any(|op| op == "PUB foo 5"));
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn explicit_traceparent_is_kept() -> io::Result<()> {
+    let _trace = trace();
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+    let mut headers = HeaderMap::new();
+    headers.insert("traceparent", traceparent);
+
+    let sub = nc.subscribe("foo").await?;
+    nc.publish_with_reply_or_headers("foo", None, Some(&headers), "hello")
+        .instrument(tracing::info_span!("send"))
+        .await?;
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(
+        msg.headers.as_ref().and_then(|h| h.get("traceparent")),
+        Some(traceparent)
+    );
+    assert_eq!(
+        format!("{:032x}", trace_id(&msg.span())),
+        "0af7651916cd43dd8448eb211c80319c"
+    );
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_handler_continues_trace() -> io::Result<()> {
+    let _trace = trace();
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let _sub = nc
+        .subscribe("help")
+        .await?
+        .with_async_handler(move |msg| async move {
+            let handled_in = trace_id(&tracing::Span::current());
+            msg.respond(format!("{:032x}", handled_in)).await
+        });
+
+    let span = tracing::info_span!("ask");
+    let sent_in = trace_id(&span);
+    assert_ne!(sent_in, TraceId::INVALID);
+    let resp = nc.request("help", "please").instrument(span).await?;
+    assert_eq!(&resp.data[..], format!("{:032x}", sent_in).as_bytes());
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn jetstream_publish_propagates_trace_context() -> io::Result<()> {
+    let _trace = trace();
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    // Stands in for the stream, acking with the trace the message arrived in.
+    let _stream = nc
+        .subscribe("events")
+        .await?
+        .with_async_handler(move |msg| async move {
+            let stored_in = trace_id(&msg.span());
+            let ack = format!(r#"{{"stream":"{:032x}","seq":7}}"#, stored_in);
+            msg.respond(ack).await
+        });
+
+    let js = nats_aflowt::jetstream::new(nc.clone());
+    let span = tracing::info_span!("store");
+    let sent_in = trace_id(&span);
+    assert_ne!(sent_in, TraceId::INVALID);
+    let message = nats_aflowt::Message::new("events", None, "hello", None);
+    let ack = js.publish_message(&message).instrument(span).await?;
+    assert_eq!(ack.stream, format!("{:032x}", sent_in));
+    assert_eq!(ack.sequence, 7);
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn every_publish_propagates_trace_context() -> io::Result<()> {
+    let _trace = trace();
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    let span = tracing::info_span!("send");
+    let sent_in = trace_id(&span);
+    async {
+        nc.publish_request("foo", "reply", "request").await?;
+        nc.publish_batch(vec![Message::new("foo", None, "batch", None)])
+            .await?;
+        let batch = [Message::new("foo", None, "try batch", None)];
+        assert_eq!(nc.try_publish_batch(&batch).await.unwrap()?, 1);
+        let mut publisher = nc.publisher();
+        publisher.publish("foo", "publisher");
+        publisher.send().await?;
+        nc.try_publish_with_reply_or_headers("foo", None, None, "try")
+            .await
+            .unwrap()
+    }
+    .instrument(span)
+    .await?;
+
+    for data in ["request", "batch", "try batch", "publisher", "try"] {
+        let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+        assert_eq!(&msg.data[..], data.as_bytes());
+        assert_traced(&msg, sent_in);
+    }
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn publish_without_header_support_has_no_headers() -> io::Result<()> {
+    let _trace = trace();
+    let server = FakeServer::new();
+    server.without_headers();
+    let nc = connect(&server).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello")
+        .instrument(tracing::info_span!("send"))
+        .await?;
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert!(msg.headers.is_none());
+    assert!(server.ops().iter().any(|op| op == "PUB foo 5"));
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn stream_delivery_is_traced() -> io::Result<()> {
+    let names = SpanNames::default();
+    let _trace = trace_with(names.clone());
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let mut sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello").await?;
+    let msg = tokio::time::timeout(Duration::from_secs(5), StreamExt::next(&mut sub)).await?;
+    assert!(msg.is_some());
+    assert!(names.0.lock().unwrap().contains(&"receive"));
+
+    nc.close().await;
+    Ok(())
+}
@@ -0,0 +1,42 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn pending_acks_fail_when_connection_closes() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let js = nats_aflowt::jetstream::new(nc.clone());
+
+    // Someone receives the message, but no ack is ever sent back.
+    let _sub = nc.subscribe("events").await?;
+    let ack = js.publish_async("events", "hello").await?;
+    assert_eq!(js.publish_async_pending(), 1);
+
+    nc.close().await;
+
+    let err = tokio::time::timeout(Duration::from_secs(5), ack)
+        .await
+        .expect("ack should fail once the connection is closed")
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    tokio::time::timeout(Duration::from_secs(5), js.publish_async_complete())
+        .await
+        .expect("publish_async_complete should return once the connection is closed");
+    assert_eq!(js.publish_async_pending(), 0);
+    Ok(())
+}
@@ -0,0 +1,87 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use nats_aflowt::Message;
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn publish_batch_in_order() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let sub = nc.subscribe("events").await?;
+
+    let batch = (0..1000).map(|i| Message::new("events", None, i.to_string(), None));
+    nc.publish_batch(batch).await?;
+
+    for i in 0..1000 {
+        let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+        assert_eq!(msg.data, i.to_string().as_bytes());
+    }
+    assert_eq!(nc.statistics().await.out_msgs, 1000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn publisher_send() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let sub = nc.subscribe("events").await?;
+
+    let mut publisher = nc.publisher();
+    publisher.publish("events", "one");
+    publisher.publish_with_reply_or_headers("events", Some("reply"), None, "two");
+    assert_eq!(publisher.len(), 2);
+    publisher.send().await?;
+    assert!(publisher.is_empty());
+
+    assert_eq!(
+        &sub.next_timeout(Duration::from_secs(5)).await?.data[..],
+        b"one"
+    );
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(&msg.data[..], b"two");
+    assert_eq!(msg.reply.as_deref(), Some("reply"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn publisher_try_send() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let sub = nc.subscribe("events").await?;
+
+    // More than fits into the queue of the writer task at once.
+    let mut publisher = nc.publisher();
+    let payload = vec![b'x'; 64];
+    for _ in 0..2000 {
+        publisher.publish("events", &payload);
+    }
+
+    let mut published = 0;
+    while !publisher.is_empty() {
+        match publisher.try_send().await {
+            Some(res) => published += res?,
+            None => tokio::task::yield_now().await,
+        }
+    }
+    assert_eq!(published, 2000);
+
+    for _ in 0..2000 {
+        sub.next_timeout(Duration::from_secs(5)).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn request_shared_inbox() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::connect(&s.client_url()).await?;
+    nc.subscribe("echo")
+        .await?
+        .with_async_handler(move |m| async move {
+            let data = m.data.clone();
+            m.respond(data).await?;
+            Ok(())
+        });
+
+    // Concurrent requests each get their own response.
+    let requests = (0..50).map(|i| {
+        let nc = nc.clone();
+        async move {
+            let resp = nc
+                .request_timeout("echo", i.to_string(), Duration::from_secs(2))
+                .await?;
+            assert_eq!(resp.data, i.to_string().as_bytes());
+            io::Result::Ok(())
+        }
+    });
+    for result in futures::future::join_all(requests).await {
+        result?;
+    }
+
+    // A request that times out does not disturb later requests.
+    let err = nc
+        .request_timeout("silent", "hello", Duration::from_millis(100))
+        .await;
+    assert!(err.is_err());
+    nc.subscribe("silent").await?;
+    let err = nc
+        .request_timeout("silent", "hello", Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    let resp = nc.request("echo", "after").await?;
+    assert_eq!(&resp.data[..], b"after");
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_old_style() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::Options::new()
+        .use_old_request_style()
+        .connect(&s.client_url())
+        .await?;
+    nc.subscribe("echo")
+        .await?
+        .with_async_handler(move |m| async move {
+            let data = m.data.clone();
+            m.respond(data).await?;
+            Ok(())
+        });
+
+    let resp = nc.request("echo", "hello").await?;
+    assert_eq!(&resp.data[..], b"hello");
+
+    let err = nc.request("nobody-home", "hello").await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    Ok(())
+}
@@ -22,7 +22,7 @@ async fn request_multi() -> io::Result<()> {
             }
             Ok(msg) => {
                 println!("Received: {}", msg);
-                if msg.data == b"ans=42" {
+                if &msg.data[..] == b"ans=42" {
                     break;
                 }
             }
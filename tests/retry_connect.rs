@@ -0,0 +1,168 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use nats_aflowt::{
+    header::HeaderMap, BoxFuture, ConnectionState, Dialer, ServerAddress, Transport,
+};
+
+mod util;
+pub use util::*;
+
+/// Refuses connections until opened.
+struct GateDialer {
+    open: Arc<AtomicBool>,
+    dialer: FakeDialer,
+}
+
+impl Dialer for GateDialer {
+    fn dial<'a>(
+        &'a self,
+        server: &'a ServerAddress,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Transport>>> {
+        if self.open.load(Ordering::SeqCst) {
+            self.dialer.dial(server)
+        } else {
+            Box::pin(async { Err(io::ErrorKind::ConnectionRefused.into()) })
+        }
+    }
+}
+
+#[tokio::test]
+async fn retry_on_failed_connect() -> io::Result<()> {
+    let server = FakeServer::new();
+    let open = Arc::new(AtomicBool::new(false));
+    let nc = nats_aflowt::Options::new()
+        .dialer(GateDialer {
+            open: open.clone(),
+            dialer: server.dialer(),
+        })
+        .max_reconnects(None)
+        .retry_on_failed_connect(true)
+        .connect("nats://fake:4222")
+        .await?;
+    assert_eq!(nc.state(), ConnectionState::Reconnecting);
+
+    // Registered locally and buffered until a server comes up.
+    let sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello").await?;
+    assert_eq!(server.dials(), 0);
+
+    open.store(true, Ordering::SeqCst);
+    let msg = sub.next_timeout(Duration::from_secs(10)).await?;
+    assert_eq!(&msg.data[..], b"hello");
+    assert_eq!(nc.state(), ConnectionState::Connected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn retry_on_failed_connect_gives_up() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(GateDialer {
+            open: Arc::new(AtomicBool::new(false)),
+            dialer: server.dialer(),
+        })
+        .max_reconnects(3)
+        .retry_on_failed_connect(true)
+        .connect("nats://fake:4222")
+        .await?;
+
+    for _ in 0..100 {
+        if nc.state() == ConnectionState::Closed {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(nc.state(), ConnectionState::Closed);
+    let err = nc.publish("foo", "hello").await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn headers_are_buffered_before_first_connect() -> io::Result<()> {
+    let server = FakeServer::new();
+    let open = Arc::new(AtomicBool::new(false));
+    let nc = nats_aflowt::Options::new()
+        .dialer(GateDialer {
+            open: open.clone(),
+            dialer: server.dialer(),
+        })
+        .max_reconnects(None)
+        .retry_on_failed_connect(true)
+        .connect("nats://fake:4222")
+        .await?;
+
+    // The server is not known yet, so headers are not rejected.
+    let sub = nc.subscribe("foo").await?;
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Test", "1");
+    nc.publish_with_reply_or_headers("foo", None, Some(&headers), "hello")
+        .await?;
+
+    open.store(true, Ordering::SeqCst);
+    let msg = sub.next_timeout(Duration::from_secs(10)).await?;
+    assert_eq!(&msg.data[..], b"hello");
+    assert!(msg.headers.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn buffered_headers_are_dropped_without_server_support() -> io::Result<()> {
+    let server = FakeServer::new();
+    server.without_headers();
+    let open = Arc::new(AtomicBool::new(false));
+    let nc = nats_aflowt::Options::new()
+        .dialer(GateDialer {
+            open: open.clone(),
+            dialer: server.dialer(),
+        })
+        .max_reconnects(None)
+        .retry_on_failed_connect(true)
+        .connect("nats://fake:4222")
+        .await?;
+
+    let sub = nc.subscribe("foo").await?;
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Test", "1");
+    nc.publish_with_reply_or_headers("foo", None, Some(&headers), "dropped")
+        .await?;
+    nc.publish("foo", "kept").await?;
+
+    // Only the message without headers reaches the server.
+    open.store(true, Ordering::SeqCst);
+    let msg = sub.next_timeout(Duration::from_secs(10)).await?;
+    assert_eq!(&msg.data[..], b"kept");
+    assert!(!server.ops().iter().any(|op| op.starts_with("HPUB")));
+
+    // Once connected, headers are rejected right away.
+    let err = nc
+        .publish_with_reply_or_headers("foo", None, Some(&headers), "hello")
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(matches!(
+        nats_aflowt::Error::from(err),
+        nats_aflowt::Error::Validation(_)
+    ));
+    Ok(())
+}
@@ -0,0 +1,160 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use futures::StreamExt;
+use nats_aflowt::{
+    jetstream::{JetStream, PushSubscription},
+    kv, object_store, Connection, SlowConsumerPolicy, Stream, Subscription, SubscriptionOptions,
+};
+
+mod util;
+pub use util::*;
+
+fn is_send<T: Send>(_: &T) {}
+
+fn is_stream<T: Stream + Send + Sync + Unpin>() {}
+
+// Only needs to compile.
+#[allow(dead_code)]
+async fn streams_are_send(
+    nc: Connection,
+    js: JetStream,
+    store: kv::Store,
+    objects: object_store::ObjectStore,
+) -> io::Result<()> {
+    is_stream::<Subscription>();
+    is_stream::<PushSubscription>();
+
+    is_send(&nc.subscribe("foo").await?.messages());
+    is_send(&nc.subscribe("foo").await?.stream());
+    is_send(&js.subscribe("foo").await?.messages());
+    is_send(&js.subscribe("foo").await?.stream());
+    is_send(&js.pull_subscribe("foo").await?.messages());
+    is_send(&js.stream_names());
+    is_send(&js.list_streams());
+    is_send(&js.list_consumers("stream")?);
+    is_send(&store.keys().await?);
+    is_send(&store.history("key").await?);
+    is_send(&store.watch("key").await?);
+    is_send(&store.watch_all().await?);
+    is_send(&objects.watch().await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn subscription_stream_in_spawned_task() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let mut sub = nc.subscribe("foo").await?;
+    let task = tokio::spawn(async move {
+        let mut received = Vec::new();
+        while let Some(msg) = StreamExt::next(&mut sub).await {
+            received.push(msg.data);
+            if received.len() == 3 {
+                break;
+            }
+        }
+        received
+    });
+
+    for data in ["1", "2", "3"] {
+        nc.publish("foo", data).await?;
+    }
+    let received = tokio::time::timeout(Duration::from_secs(5), task)
+        .await?
+        .unwrap();
+    assert_eq!(received, vec!["1", "2", "3"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn boxed_stream_ends_on_unsubscribe() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    let mut messages = sub.clone().messages();
+    nc.publish("foo", "hello").await?;
+    let msg = tokio::time::timeout(Duration::from_secs(5), messages.next()).await?;
+    assert_eq!(&msg.unwrap().data[..], b"hello");
+
+    let task = tokio::spawn(async move { messages.next().await });
+    sub.unsubscribe().await?;
+    let end = tokio::time::timeout(Duration::from_secs(5), task).await?;
+    assert!(end.unwrap().is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancelled_stream_poll_leaves_subscription_usable() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let mut sub = nc
+        .subscribe_with_options(
+            "foo",
+            SubscriptionOptions::new()
+                .pending_messages(1)
+                .slow_consumer_policy(SlowConsumerPolicy::DropOldest),
+        )
+        .await?;
+
+    // Give up on a stream poll while it waits for a message.
+    let polled = tokio::time::timeout(Duration::from_millis(100), StreamExt::next(&mut sub)).await;
+    assert!(polled.is_err());
+
+    // Dropping the oldest message must not wait for the abandoned poll.
+    for data in ["1", "2", "3"] {
+        nc.publish("foo", data).await?;
+    }
+    tokio::time::timeout(Duration::from_secs(5), nc.flush()).await??;
+
+    // Clones and the inherent methods keep receiving.
+    let msg = sub.clone().next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(&msg.data[..], b"3");
+    nc.publish("foo", "4").await?;
+    let msg = tokio::time::timeout(Duration::from_secs(5), StreamExt::next(&mut sub)).await?;
+    assert_eq!(&msg.unwrap().data[..], b"4");
+    Ok(())
+}
+
+#[tokio::test]
+async fn stream_and_inherent_receivers_share_messages() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    // Both wait at the same time, each has to be woken for its message.
+    let sub = nc.subscribe("foo").await?;
+    let mut stream = sub.clone();
+    let streamed = tokio::spawn(async move { StreamExt::next(&mut stream).await });
+    let inherent = tokio::spawn({
+        let sub = sub.clone();
+        async move { sub.next().await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    nc.publish("foo", "1").await?;
+    nc.publish("foo", "2").await?;
+    let streamed = tokio::time::timeout(Duration::from_secs(5), streamed).await?;
+    let inherent = tokio::time::timeout(Duration::from_secs(5), inherent).await?;
+    let mut received = vec![
+        streamed.unwrap().unwrap().data,
+        inherent.unwrap().unwrap().data,
+    ];
+    received.sort();
+    assert_eq!(received, vec!["1", "2"]);
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use nats_aflowt::{BoxFuture, Connection, Dialer, ServerAddress, Transport};
+
+mod util;
+pub use util::*;
+
+fn hosts(nc: &Connection) -> Vec<String> {
+    nc.servers()
+        .iter()
+        .map(|server| format!("{}:{}", server.host(), server.port()))
+        .collect()
+}
+
+/// Waits until the connection's server pool matches `expected`.
+async fn wait_for_servers(nc: &Connection, expected: &[&str]) {
+    for _ in 0..100 {
+        if hosts(nc) == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(hosts(nc), expected);
+}
+
+#[tokio::test]
+async fn discovered_servers_are_added_and_pruned() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    assert_eq!(hosts(&nc), ["fake:4222"]);
+
+    server.advertise(&["a:4222", "b:4222"]);
+    wait_for_servers(&nc, &["fake:4222", "a:4222", "b:4222"]).await;
+
+    // Configured servers stay when the cluster stops advertising them.
+    server.advertise(&["b:4222", "c:4222"]);
+    wait_for_servers(&nc, &["fake:4222", "b:4222", "c:4222"]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn ignore_discovered_servers() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .ignore_discovered_servers()
+        .connect("nats://fake:4222")
+        .await?;
+
+    server.advertise(&["a:4222"]);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    nc.flush().await?;
+    assert_eq!(hosts(&nc), ["fake:4222"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn no_randomize_tries_lost_server_last() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .no_randomize()
+        .connect("nats://a:4222,nats://b:4222,nats://c:4222")
+        .await?;
+    assert_eq!(server.dialed(), ["a:4222"]);
+
+    server.disconnect();
+    for _ in 0..100 {
+        if server.dials() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(server.dialed(), ["a:4222", "b:4222"]);
+    assert_eq!(hosts(&nc), ["a:4222", "b:4222", "c:4222"]);
+    Ok(())
+}
+
+/// Refuses connections to the host `down`.
+struct DownDialer(FakeDialer);
+
+impl Dialer for DownDialer {
+    fn dial<'a>(
+        &'a self,
+        server: &'a ServerAddress,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Transport>>> {
+        if server.host() == "down" {
+            Box::pin(async { Err(io::ErrorKind::ConnectionRefused.into()) })
+        } else {
+            self.0.dial(server)
+        }
+    }
+}
+
+#[tokio::test]
+async fn servers_out_of_reconnects_are_pruned() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(DownDialer(server.dialer()))
+        .no_randomize()
+        .max_reconnects(1)
+        .connect("nats://down:4222,nats://fake:4222")
+        .await?;
+    assert_eq!(hosts(&nc), ["down:4222", "fake:4222"]);
+
+    server.disconnect();
+    wait_for_servers(&nc, &["fake:4222"]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn discovered_servers_keep_current_scheme() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .connect("ws://fake:8080")
+        .await?;
+
+    server.advertise(&["a:8080", "nats://b:4222"]);
+    wait_for_servers(&nc, &["fake:8080", "a:8080", "b:4222"]).await;
+    let websockets: Vec<bool> = nc.servers().iter().map(|s| s.is_websocket()).collect();
+    assert_eq!(websockets, [true, true, false]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn malformed_discovered_server_is_skipped() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    server.advertise(&["[::1:4222", "a:4222"]);
+    wait_for_servers(&nc, &["fake:4222", "a:4222"]).await;
+    nc.flush().await?;
+    assert_eq!(server.dials(), 1);
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use nats_aflowt::{AsyncErrorCallback, BoxFuture, SlowConsumerPolicy, SubscriptionOptions};
+
+mod util;
+pub use util::*;
+
+struct SendErrCallback {
+    tx: tokio::sync::mpsc::Sender<io::Error>,
+}
+
+impl AsyncErrorCallback for SendErrCallback {
+    fn call(&self, _si: nats_aflowt::ServerInfo, err: io::Error) -> BoxFuture<'_, ()> {
+        let tx = self.tx.clone();
+        Box::pin(async move {
+            tx.send(err).await.ok();
+        })
+    }
+}
+
+#[tokio::test]
+async fn slow_consumer_drop_new() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    let nc = nats_aflowt::Options::new()
+        .error_callback(SendErrCallback { tx })
+        .connect(&s.client_url())
+        .await?;
+
+    let sub = nc
+        .subscribe_with_options(
+            "slow",
+            SubscriptionOptions::new()
+                .pending_messages(2)
+                .slow_consumer_policy(SlowConsumerPolicy::DropNew),
+        )
+        .await?;
+    for i in 0..5 {
+        nc.publish("slow", i.to_string()).await?;
+    }
+    nc.flush().await?;
+
+    let err = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+        .await?
+        .expect("expected a slow consumer error");
+    match nats_aflowt::Error::from(err) {
+        nats_aflowt::Error::SlowConsumer { subject, .. } => assert_eq!(subject, "slow"),
+        err => panic!("unexpected error {}", err),
+    }
+
+    assert_eq!(&sub.next().await.unwrap().data[..], b"0");
+    assert_eq!(&sub.next().await.unwrap().data[..], b"1");
+    assert!(sub.try_next().await.is_none());
+
+    let stats = nc.statistics().await;
+    assert_eq!(stats.subscriptions[0].delivered, 2);
+    assert_eq!(stats.subscriptions[0].dropped, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn slow_consumer_drop_oldest() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::connect(&s.client_url()).await?;
+
+    let sub = nc
+        .subscribe_with_options(
+            "slow",
+            SubscriptionOptions::new()
+                .pending_messages(10)
+                .pending_bytes(2)
+                .slow_consumer_policy(SlowConsumerPolicy::DropOldest),
+        )
+        .await?;
+    for i in 0..5 {
+        nc.publish("slow", i.to_string()).await?;
+    }
+    nc.flush().await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(&sub.next().await.unwrap().data[..], b"3");
+    assert_eq!(&sub.next().await.unwrap().data[..], b"4");
+    assert!(sub.try_next().await.is_none());
+    Ok(())
+}
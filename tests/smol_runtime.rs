@@ -0,0 +1,150 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "smol_runtime")]
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+mod util;
+pub use util::*;
+
+/// Runs the fake server on its own tokio runtime, so the client under test
+/// never finds a tokio runtime on its thread.
+fn spawn_server(server: &Arc<FakeServer>) -> (tokio::runtime::Runtime, std::net::SocketAddr) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let addr = rt.block_on(server.listen());
+    (rt, addr)
+}
+
+#[test]
+fn smol_pub_sub() -> io::Result<()> {
+    let server = FakeServer::new();
+    let (_rt, addr) = spawn_server(&server);
+
+    smol::block_on(async {
+        assert!(tokio::runtime::Handle::try_current().is_err());
+
+        let nc = nats_aflowt::Options::new()
+            .ping_interval(Duration::from_millis(100))
+            .connect(&format!("nats://{}", addr))
+            .await?;
+        assert_eq!(server.dials(), 1);
+
+        let sub = nc.subscribe("foo").await?;
+        nc.publish("foo", "hello").await?;
+        let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+        assert_eq!(&msg.data[..], b"hello");
+
+        // Timers keep running, the connection is not considered stale.
+        smol::Timer::after(Duration::from_millis(350)).await;
+        nc.flush().await?;
+
+        nc.close().await;
+        Ok(())
+    })
+}
+
+#[test]
+fn smol_request_and_handler() -> io::Result<()> {
+    let server = FakeServer::new();
+    let (_rt, addr) = spawn_server(&server);
+
+    smol::block_on(async {
+        let nc = nats_aflowt::connect(&format!("nats://{}", addr)).await?;
+
+        let responder = nc.clone();
+        let _handler = nc.subscribe("help").await?.with_handler(move |msg| {
+            let responder = responder.clone();
+            smol::block_on(responder.publish(msg.reply.as_deref().unwrap(), "ok"))
+        });
+
+        let resp = nc
+            .request_timeout("help", "please", Duration::from_secs(5))
+            .await?;
+        assert_eq!(&resp.data[..], b"ok");
+
+        // Timeouts fire without a tokio timer driver.
+        let start = Instant::now();
+        let sub = nc.subscribe("nobody").await?;
+        let err = sub
+            .next_timeout(Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        nc.close().await;
+        Ok(())
+    })
+}
+
+/// Consumer info of `worker` on `events`, pushing to `deliver`.
+const CONSUMER_INFO: &str = concat!(
+    r#"{"type":"io.nats.jetstream.api.v1.consumer_info_response","stream_name":"events","#,
+    r#""name":"worker","created":"2022-01-01T00:00:00Z","config":{"durable_name":"worker","#,
+    r#""deliver_subject":"deliver","ack_policy":"explicit","deliver_policy":"all","#,
+    r#""replay_policy":"instant"},"delivered":{"consumer_seq":0,"stream_seq":0},"#,
+    r#""ack_floor":{"consumer_seq":0,"stream_seq":0},"num_ack_pending":0,"#,
+    r#""num_redelivered":0,"num_waiting":0,"num_pending":0,"cluster":{"leader":"fake"}}"#
+);
+
+#[test]
+fn smol_process_handler() -> io::Result<()> {
+    let server = FakeServer::new();
+    let (_rt, addr) = spawn_server(&server);
+
+    smol::block_on(async {
+        let nc = nats_aflowt::connect(&format!("nats://{}", addr)).await?;
+
+        // Stands in for the JetStream API.
+        let api = nc.clone();
+        let _api = nc
+            .subscribe("$JS.API.CONSUMER.INFO.events.worker")
+            .await?
+            .with_handler(move |msg| {
+                smol::block_on(api.publish(msg.reply.as_deref().unwrap(), CONSUMER_INFO))
+            });
+
+        let js = nats_aflowt::jetstream::new(nc.clone());
+        let options =
+            nats_aflowt::jetstream::SubscribeOptions::bind("events".into(), "worker".into());
+        let processed = Arc::new(AtomicBool::new(false));
+        let _handler = js
+            .subscribe_with_options("events", &options)
+            .await?
+            .with_process_handler({
+                let processed = processed.clone();
+                move |_| {
+                    processed.store(true, Ordering::SeqCst);
+                    Ok(())
+                }
+            });
+
+        // The handler runs and the message is acknowledged.
+        let acks = nc.subscribe("acks").await?;
+        nc.publish_request("deliver", "acks", "hello").await?;
+        let ack = acks.next_timeout(Duration::from_secs(5)).await?;
+        assert!(ack.data.is_empty());
+        assert!(processed.load(Ordering::SeqCst));
+
+        nc.close().await;
+        Ok(())
+    })
+}
@@ -0,0 +1,95 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use nats_aflowt::{BoxFuture, Dialer, ServerAddress, Transport};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn tcp_socket_options() -> io::Result<()> {
+    let server = FakeServer::new();
+    let addr = server.listen().await;
+
+    // Pick a free port, so that the server can tell the client bound to it.
+    let local_address = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let nc = nats_aflowt::Options::new()
+        .tcp_nodelay(false)
+        .tcp_keepalive(Duration::from_secs(30))
+        .send_buffer_size(64 * 1024)
+        .recv_buffer_size(64 * 1024)
+        .local_address(local_address)
+        .connect(&format!("nats://{}", addr))
+        .await?;
+    assert_eq!(server.dials(), 1);
+    assert_eq!(server.peers(), vec![local_address]);
+
+    let sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello").await?;
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(&msg.data[..], b"hello");
+
+    nc.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn connect_timeout_without_info() {
+    // Accepts connections but never says anything.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut streams = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            streams.push(stream);
+        }
+    });
+
+    let start = Instant::now();
+    let err = nats_aflowt::Options::new()
+        .connect_timeout(Duration::from_millis(200))
+        .connect(&format!("nats://{}", addr))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+struct HangingDialer;
+
+impl Dialer for HangingDialer {
+    fn dial<'a>(
+        &'a self,
+        _server: &'a ServerAddress,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Transport>>> {
+        Box::pin(futures::future::pending())
+    }
+}
+
+#[tokio::test]
+async fn connect_timeout_applies_to_dialer() {
+    let start = Instant::now();
+    let err = nats_aflowt::Options::new()
+        .dialer(HangingDialer)
+        .connect_timeout(Duration::from_millis(200))
+        .connect("nats://fake:4222")
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
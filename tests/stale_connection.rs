@@ -0,0 +1,127 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use nats_aflowt::{AsyncErrorCallback, BoxFuture, ConnectionEvent, ServerInfo};
+
+mod util;
+pub use util::*;
+
+struct Errors(tokio::sync::mpsc::UnboundedSender<io::Error>);
+
+impl AsyncErrorCallback for Errors {
+    fn call(&self, _si: ServerInfo, err: io::Error) -> BoxFuture<'_, ()> {
+        self.0.send(err).ok();
+        Box::pin(async {})
+    }
+}
+
+#[tokio::test]
+async fn stale_connection_reconnects() -> io::Result<()> {
+    let server = FakeServer::new();
+    let (errors, mut reported) = tokio::sync::mpsc::unbounded_channel();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .ping_interval(Duration::from_millis(100))
+        .max_pings_outstanding(2)
+        .error_callback(Errors(errors))
+        .connect("nats://fake:4222")
+        .await?;
+    let mut events = nc.events();
+
+    server.stop_responding();
+
+    let err = tokio::time::timeout(Duration::from_secs(5), reported.recv())
+        .await?
+        .unwrap();
+    assert!(matches!(
+        nats_aflowt::Error::from(err),
+        nats_aflowt::Error::StaleConnection
+    ));
+
+    let mut disconnected: Option<Arc<io::Error>> = None;
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await?
+            .unwrap();
+        match event {
+            ConnectionEvent::Disconnected { error, .. } => disconnected = error,
+            ConnectionEvent::Reconnected { .. } => break,
+            _ => {}
+        }
+    }
+    assert_eq!(
+        disconnected.unwrap().kind(),
+        io::ErrorKind::ConnectionAborted
+    );
+    assert_eq!(server.dials(), 2);
+
+    // The new connection answers PINGs again.
+    nc.flush().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn flush_timeout() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .ping_interval(Duration::ZERO)
+        .flush_timeout(Duration::from_millis(200))
+        .connect("nats://fake:4222")
+        .await?;
+
+    server.stop_responding();
+    let started = std::time::Instant::now();
+    let err = nc.flush().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(matches!(
+        nats_aflowt::Error::from(err),
+        nats_aflowt::Error::TimedOut
+    ));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+#[tokio::test]
+async fn flush_with_timeout_waits_for_pong_at_most_that_long() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .ping_interval(Duration::ZERO)
+        .connect("nats://fake:4222")
+        .await?;
+
+    server.stop_responding();
+    let started = std::time::Instant::now();
+    let err = nc
+        .flush_timeout(Duration::from_millis(200))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+#[tokio::test]
+async fn zero_max_pings_outstanding_is_rejected() {
+    let err = nats_aflowt::Options::new()
+        .dialer(FakeServer::new().dialer())
+        .max_pings_outstanding(0)
+        .connect("nats://fake:4222")
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,45 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn connection_statistics() -> io::Result<()> {
+    let s = util::run_basic_server();
+    let nc = nats_aflowt::connect(&s.client_url()).await?;
+    let sub = nc.subscribe("stats").await?;
+
+    for _ in 0..3 {
+        nc.publish("stats", "hello").await?;
+    }
+    for _ in 0..3 {
+        sub.next_timeout(Duration::from_secs(1)).await?;
+    }
+
+    let stats = nc.statistics().await;
+    assert_eq!(stats.out_msgs, 3);
+    assert_eq!(stats.out_bytes, 15);
+    assert_eq!(stats.in_msgs, 3);
+    assert_eq!(stats.in_bytes, 15);
+    assert_eq!(stats.reconnects, 0);
+    assert_eq!(stats.pending_bytes, 0);
+
+    assert_eq!(stats.subscriptions.len(), 1);
+    assert_eq!(stats.subscriptions[0].subject, "stats");
+    assert_eq!(stats.subscriptions[0].delivered, 3);
+    assert_eq!(stats.subscriptions[0].dropped, 0);
+    Ok(())
+}
@@ -0,0 +1,55 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+use nats_aflowt::header::StatusCode;
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn no_responders_status() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let inbox = nc.new_inbox();
+    let sub = nc.subscribe(&inbox).await?;
+    nc.publish_request("nobody", &inbox, "hello").await?;
+
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(msg.status(), Some(StatusCode::NoResponders));
+    assert_eq!(msg.description(), None);
+    assert!(msg.is_no_responders());
+
+    let err = nc
+        .request_timeout("nobody", "hello", Duration::from_secs(5))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    Ok(())
+}
+
+#[tokio::test]
+async fn plain_messages_have_no_status() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    let sub = nc.subscribe("foo").await?;
+    nc.publish("foo", "hello").await?;
+
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(msg.status(), None);
+    assert_eq!(msg.description(), None);
+    Ok(())
+}
@@ -15,13 +15,17 @@ use std::io::{BufRead, BufReader};
 use std::net::TcpStream;
 use std::path::PathBuf;
 use std::process::{Child, Command};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
 use std::{env, fs};
 use std::{thread, time::Duration};
 
 use lazy_static::lazy_static;
 use nats_aflowt::{
     jetstream::{JetStream, JetStreamOptions},
-    Connection,
+    BoxFuture, Connection, Dialer, ServerAddress, Transport,
 };
 use regex::Regex;
 
@@ -166,3 +170,326 @@ pub async fn run_basic_jetstream() -> (Server, Connection, JetStream) {
 
     (s, nc, js)
 }
+
+/// A scripted server that speaks just enough of the protocol to answer pings and
+/// route messages, reached through a custom dialer over in-memory pipes.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct FakeServer {
+    dials: AtomicUsize,
+    dialed: Mutex<Vec<String>>,
+    ops: Mutex<Vec<String>>,
+    disconnect: tokio::sync::Notify,
+    silent: AtomicBool,
+    connect_urls: Mutex<Vec<String>>,
+    info: tokio::sync::Notify,
+    websockets: AtomicUsize,
+    no_headers: AtomicBool,
+    peers: Mutex<Vec<std::net::SocketAddr>>,
+}
+
+#[allow(dead_code)]
+impl FakeServer {
+    pub fn new() -> Arc<FakeServer> {
+        Arc::new(FakeServer::default())
+    }
+
+    /// Returns a dialer that connects to this server.
+    ///
+    /// Sessions run on the tokio runtime the dialer is created on, whichever
+    /// runtime the client dials from.
+    pub fn dialer(self: &Arc<Self>) -> FakeDialer {
+        FakeDialer(self.clone(), tokio::runtime::Handle::current())
+    }
+
+    /// Accepts TCP connections on a random local port, and returns its address.
+    pub async fn listen(self: &Arc<Self>) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = self.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                server.dials.fetch_add(1, Ordering::SeqCst);
+                server.peers.lock().unwrap().push(peer);
+                tokio::spawn(server.clone().serve(stream));
+            }
+        });
+        addr
+    }
+
+    /// Number of connections made to this server.
+    pub fn dials(&self) -> usize {
+        self.dials.load(Ordering::SeqCst)
+    }
+
+    /// The `host:port` of every server dialed, in order.
+    pub fn dialed(&self) -> Vec<String> {
+        self.dialed.lock().unwrap().clone()
+    }
+
+    /// Addresses of the clients connected through `listen`, in order.
+    pub fn peers(&self) -> Vec<std::net::SocketAddr> {
+        self.peers.lock().unwrap().clone()
+    }
+
+    /// Number of sessions that carried the protocol in websocket frames.
+    pub fn websockets(&self) -> usize {
+        self.websockets.load(Ordering::SeqCst)
+    }
+
+    /// Protocol lines received from clients, without payloads.
+    pub fn ops(&self) -> Vec<String> {
+        self.ops.lock().unwrap().clone()
+    }
+
+    /// Drops the current connection.
+    pub fn disconnect(&self) {
+        self.disconnect.notify_waiters();
+    }
+
+    /// Advertises cluster URLs in a new INFO message, and on future connections.
+    pub fn advertise(&self, urls: &[&str]) {
+        *self.connect_urls.lock().unwrap() = urls.iter().map(|url| url.to_string()).collect();
+        self.info.notify_waiters();
+    }
+
+    /// Advertises that headers are not supported, on future connections.
+    pub fn without_headers(&self) {
+        self.no_headers.store(true, Ordering::SeqCst);
+    }
+
+    fn info_line(&self) -> String {
+        let connect_urls = serde_json::to_string(&*self.connect_urls.lock().unwrap()).unwrap();
+        let headers = !self.no_headers.load(Ordering::SeqCst);
+        format!(
+            concat!(
+                r#"INFO {{"server_id":"fake","server_name":"fake","host":"fake","port":4222,"#,
+                r#""version":"2.8.0","go":"go1.18","headers":{},"max_payload":1048576,"#,
+                r#""proto":1,"client_id":1,"connect_urls":{}}}"#,
+                "\r\n"
+            ),
+            headers, connect_urls
+        )
+    }
+
+    /// Stops answering PINGs until the next connection is made.
+    pub fn stop_responding(&self) {
+        self.silent.store(true, Ordering::SeqCst);
+    }
+
+    async fn serve<S>(self: Arc<Self>, stream: S) -> std::io::Result<()>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+    {
+        use std::{collections::HashMap, mem};
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = tokio::io::BufReader::new(reader);
+        writer.write_all(self.info_line().as_bytes()).await?;
+
+        let mut subscriptions: HashMap<String, String> = HashMap::new();
+        let mut buf = Vec::new();
+        loop {
+            // Reading a line continues where it left off if another branch wins.
+            tokio::select! {
+                read = reader.read_until(b'\n', &mut buf) => {
+                    if read? == 0 {
+                        return Ok(());
+                    }
+                }
+                _ = self.disconnect.notified() => return Ok(()),
+                _ = self.info.notified() => {
+                    writer.write_all(self.info_line().as_bytes()).await?;
+                    continue;
+                }
+            }
+            let line = String::from_utf8(mem::take(&mut buf)).unwrap();
+            self.ops.lock().unwrap().push(line.trim_end().to_string());
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match args.as_slice() {
+                ["PING"] if self.silent.load(Ordering::SeqCst) => {}
+                ["PING"] => writer.write_all(b"PONG\r\n").await?,
+                ["SUB", subject, sid] => {
+                    subscriptions.insert(subject.to_string(), sid.to_string());
+                }
+                ["PUB", subject, ..] | ["HPUB", subject, ..] => {
+                    // PUB <subject> [reply] <len>
+                    // HPUB <subject> [reply] <header len> <len>
+                    let (op, sizes) = match args[0] {
+                        "HPUB" => ("HMSG", 2),
+                        _ => ("MSG", 1),
+                    };
+                    let reply_to = match args.len() - 2 - sizes {
+                        1 => Some(args[2]),
+                        _ => None,
+                    };
+                    let reply = reply_to.map(|r| format!(" {}", r)).unwrap_or_default();
+                    let sizes = args[args.len() - sizes..].join(" ");
+                    let len = args[args.len() - 1];
+                    let mut payload = vec![0; len.parse::<usize>().unwrap() + 2];
+                    reader.read_exact(&mut payload).await?;
+                    let mut delivered = false;
+                    for (pattern, sid) in &subscriptions {
+                        if !subject_matches(pattern, subject) {
+                            continue;
+                        }
+                        delivered = true;
+                        writer
+                            .write_all(
+                                format!("{} {} {}{} {}\r\n", op, subject, sid, reply, sizes)
+                                    .as_bytes(),
+                            )
+                            .await?;
+                        writer.write_all(&payload).await?;
+                    }
+
+                    // Tell requesters that nobody is listening.
+                    if let (false, Some(reply_to)) = (delivered, reply_to) {
+                        let status = "NATS/1.0 503\r\n\r\n";
+                        for (pattern, sid) in &subscriptions {
+                            if subject_matches(pattern, reply_to) {
+                                let len = status.len();
+                                let hmsg = format!("HMSG {} {} {} {}\r\n", reply_to, sid, len, len);
+                                writer.write_all(hmsg.as_bytes()).await?;
+                                writer.write_all(status.as_bytes()).await?;
+                                writer.write_all(b"\r\n").await?;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Serves a session in websocket frames, after a TLS handshake for `wss://`.
+    async fn serve_websocket<S>(self: Arc<Self>, stream: S, tls: bool) -> std::io::Result<()>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        if tls {
+            let stream = tls_acceptor().accept(stream).await?;
+            self.serve_frames(stream).await
+        } else {
+            self.serve_frames(stream).await
+        }
+    }
+
+    /// Accepts the websocket handshake, then relays frames to and from a regular session.
+    async fn serve_frames<S>(self: Arc<Self>, stream: S) -> std::io::Result<()>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        use futures::{SinkExt, StreamExt};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let into_io = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+        let websocket = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(into_io)?;
+        self.websockets.fetch_add(1, Ordering::SeqCst);
+
+        let (mut sink, mut frames) = websocket.split();
+        let (session, relay) = tokio::io::duplex(64 * 1024);
+        let (mut from_session, mut to_session) = tokio::io::split(relay);
+        let inbound = async move {
+            while let Some(Ok(message)) = frames.next().await {
+                if let Message::Binary(data) = message {
+                    to_session.write_all(&data).await?;
+                }
+            }
+            Ok(())
+        };
+        let outbound = async move {
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = from_session.read(&mut buf).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                sink.send(Message::Binary(buf[..n].to_vec()))
+                    .await
+                    .map_err(into_io)?;
+            }
+        };
+
+        tokio::select! {
+            served = self.serve(session) => served,
+            relayed = inbound => relayed,
+            relayed = outbound => relayed,
+        }
+    }
+}
+
+/// Accepts TLS with the test certificate for `localhost`.
+fn tls_acceptor() -> tokio_rustls::TlsAcceptor {
+    use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+    let certs = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/configs/certs");
+    let read = |name: &str| BufReader::new(fs::File::open(certs.join(name)).unwrap());
+    let chain = rustls_pemfile::certs(&mut read("server-cert.pem"))
+        .unwrap()
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut read("server-key.pem"))
+        .unwrap()
+        .remove(0);
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain, PrivateKey(key))
+        .unwrap();
+    tokio_rustls::TlsAcceptor::from(Arc::new(config))
+}
+
+/// Matches a subject against a subscription subject with `*` and `>` wildcards.
+fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut tokens = subject.split('.');
+    for pattern in pattern.split('.') {
+        match (pattern, tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (pattern, Some(token)) if pattern == token => {}
+            _ => return false,
+        }
+    }
+    tokens.next().is_none()
+}
+
+/// Connects to a `FakeServer` with default options.
+#[allow(dead_code)]
+pub async fn connect(server: &Arc<FakeServer>) -> std::io::Result<Connection> {
+    nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .connect("nats://fake:4222")
+        .await
+}
+
+/// Connects every dial to a new session of a `FakeServer`.
+pub struct FakeDialer(Arc<FakeServer>, tokio::runtime::Handle);
+
+impl Dialer for FakeDialer {
+    fn dial<'a>(
+        &'a self,
+        server: &'a ServerAddress,
+    ) -> BoxFuture<'a, std::io::Result<Box<dyn Transport>>> {
+        Box::pin(async move {
+            self.0.dials.fetch_add(1, Ordering::SeqCst);
+            let address = format!("{}:{}", server.host(), server.port());
+            self.0.dialed.lock().unwrap().push(address);
+            self.0.silent.store(false, Ordering::SeqCst);
+            let (client, session) = tokio::io::duplex(64 * 1024);
+            if server.is_websocket() {
+                let tls = server.tls_required();
+                self.1.spawn(self.0.clone().serve_websocket(session, tls));
+            } else {
+                self.1.spawn(self.0.clone().serve(session));
+            }
+            Ok(Box::new(client) as Box<dyn Transport>)
+        })
+    }
+}
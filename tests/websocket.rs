@@ -0,0 +1,88 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, time::Duration};
+
+mod util;
+pub use util::*;
+
+async fn round_trip(
+    nc: &nats_aflowt::Connection,
+    sub: &nats_aflowt::Subscription,
+) -> io::Result<()> {
+    nc.publish("ws.data", "hello").await?;
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(&msg.data[..], b"hello");
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_pub_sub() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .connect("ws://fake:8080")
+        .await?;
+
+    let sub = nc.subscribe("ws.data").await?;
+    round_trip(&nc, &sub).await?;
+
+    assert_eq!(server.dialed(), vec!["fake:8080"]);
+    assert_eq!(server.websockets(), 1);
+    assert!(server.ops().contains(&"PUB ws.data 5".to_string()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn secure_websocket_pub_sub() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .add_root_certificate("tests/configs/certs/rootCA.pem")
+        .connect("wss://localhost")
+        .await?;
+
+    let sub = nc.subscribe("ws.data").await?;
+    round_trip(&nc, &sub).await?;
+
+    // Secure websockets default to the HTTPS port.
+    assert_eq!(server.dialed(), vec!["localhost:443"]);
+    assert_eq!(server.websockets(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_reconnect() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = nats_aflowt::Options::new()
+        .dialer(server.dialer())
+        .connect("ws://fake:8080")
+        .await?;
+
+    let sub = nc.subscribe("ws.data").await?;
+    round_trip(&nc, &sub).await?;
+
+    server.disconnect();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while server.websockets() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("client should reconnect over websocket");
+
+    // The subscription is restored on the new session.
+    round_trip(&nc, &sub).await?;
+    assert_eq!(server.dials(), 2);
+    Ok(())
+}
@@ -0,0 +1,176 @@
+// Copyright 2020-2022 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use nats_aflowt::{BoxFuture, Dialer, ServerAddress, Transport};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+mod util;
+pub use util::*;
+
+#[tokio::test]
+async fn concurrent_publishers_keep_their_order() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+    let sub = nc.subscribe("events").await?;
+
+    let publishers: Vec<_> = (0..8)
+        .map(|task| {
+            let nc = nc.clone();
+            tokio::spawn(async move {
+                for i in 0..500 {
+                    nc.publish("events", format!("{} {}", task, i)).await?;
+                }
+                io::Result::Ok(())
+            })
+        })
+        .collect();
+
+    let mut next = [0; 8];
+    for _ in 0..8 * 500 {
+        let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+        let data = String::from_utf8(msg.data.to_vec()).unwrap();
+        let (task, i) = data.split_once(' ').unwrap();
+        let task: usize = task.parse().unwrap();
+        assert_eq!(i.parse::<usize>().unwrap(), next[task]);
+        next[task] += 1;
+    }
+    assert_eq!(next, [500; 8]);
+
+    for publisher in publishers {
+        publisher.await.unwrap()?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn close_flushes_queued_messages() -> io::Result<()> {
+    let server = FakeServer::new();
+    let nc = connect(&server).await?;
+
+    for i in 0..1000 {
+        nc.publish("events", i.to_string()).await?;
+    }
+    nc.close().await;
+
+    let published = server
+        .ops()
+        .iter()
+        .filter(|op| op.starts_with("PUB events"))
+        .count();
+    assert_eq!(published, 1000);
+    Ok(())
+}
+
+/// Dials a `FakeServer`, with connections whose writes fail once `broken` is set,
+/// while reading from them still works.
+struct BreakableDialer(FakeDialer, Arc<AtomicBool>);
+
+impl Dialer for BreakableDialer {
+    fn dial<'a>(
+        &'a self,
+        server: &'a ServerAddress,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Transport>>> {
+        Box::pin(async move {
+            let inner = self.0.dial(server).await?;
+            Ok(Box::new(Breakable(inner, self.1.clone())) as Box<dyn Transport>)
+        })
+    }
+}
+
+struct Breakable(Box<dyn Transport>, Arc<AtomicBool>);
+
+impl AsyncRead for Breakable {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Breakable {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // Fails once, the connection after it works again.
+        if self.1.swap(false, Ordering::SeqCst) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test]
+async fn failed_write_is_buffered_for_reconnect() -> io::Result<()> {
+    let server = FakeServer::new();
+    let broken = Arc::new(AtomicBool::new(false));
+    let nc = nats_aflowt::Options::new()
+        .dialer(BreakableDialer(server.dialer(), broken.clone()))
+        .connect("nats://fake:4222")
+        .await?;
+    let sub = nc.subscribe("events").await?;
+    nc.flush().await?;
+
+    // Too large for the write buffer, so it is written to the socket right away.
+    broken.store(true, Ordering::SeqCst);
+    let payload = vec![7; 64 * 1024];
+    nc.publish("events", payload.clone()).await?;
+
+    let msg = sub.next_timeout(Duration::from_secs(5)).await?;
+    assert_eq!(msg.data, payload);
+    assert_eq!(server.dials(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_write_wakes_up_flush() -> io::Result<()> {
+    let server = FakeServer::new();
+    let broken = Arc::new(AtomicBool::new(false));
+    let nc = nats_aflowt::Options::new()
+        .dialer(BreakableDialer(server.dialer(), broken.clone()))
+        .connect("nats://fake:4222")
+        .await?;
+
+    // The PONG for this flush will never arrive, it must not wait for the timeout.
+    let start = Instant::now();
+    broken.store(true, Ordering::SeqCst);
+    let err = nc.flush_timeout(Duration::from_secs(30)).await.unwrap_err();
+    assert!(matches!(
+        nats_aflowt::Error::from(err),
+        nats_aflowt::Error::ConnectionClosed
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    Ok(())
+}